@@ -3,6 +3,7 @@
 #include <linux/gfp.h>
 #include <linux/kernel.h>
 #include <linux/slab.h>
+#include <linux/spinlock.h>
 #include <linux/version.h>
 #include <linux/sched.h>
 #include <linux/pid.h>
@@ -26,28 +27,43 @@
 #include "manager/manager_identity.h"
 #include "ksu.h"
 
-// Dynamic sign configuration
-static struct dynamic_manager_config dynamic_manager = {
-    .size = 0x300,
-    .hash = "0000000000000000000000000000000000000000000000000000000000000000",
-    .is_set = 0
-};
+// Dynamic sign configuration, all of these signatures are trusted
+static struct dynamic_manager_config dynamic_managers[DYNAMIC_MANAGER_MAX];
+static int dynamic_manager_count;
+static DEFINE_SPINLOCK(dynamic_manager_lock);
 
 bool ksu_is_dynamic_manager_enabled(void)
 {
-    return dynamic_manager.is_set;
+    return READ_ONCE(dynamic_manager_count) > 0;
 }
 
-apk_sign_key_t ksu_get_dynamic_manager_sign(void)
+// caller holds dynamic_manager_lock
+static int find_dynamic_manager(unsigned size, const char *hash)
 {
-    apk_sign_key_t sign_key = { .size = dynamic_manager.size, .sha256 = dynamic_manager.hash };
+    int i;
+
+    for (i = 0; i < dynamic_manager_count; i++) {
+        if (dynamic_managers[i].size == size && !memcmp(dynamic_managers[i].hash, hash, 64))
+            return i;
+    }
+    return -1;
+}
+
+bool ksu_is_dynamic_manager_sign(unsigned size, const char *hash)
+{
+    unsigned long flags;
+    bool found;
 
-    return sign_key;
+    spin_lock_irqsave(&dynamic_manager_lock, flags);
+    found = find_dynamic_manager(size, hash) >= 0;
+    spin_unlock_irqrestore(&dynamic_manager_lock, flags);
+    return found;
 }
 
 int ksu_handle_dynamic_manager(struct ksu_dynamic_manager_cmd *cmd)
 {
     unsigned long flags;
+    bool synchronous, replace;
     int ret = 0;
     int i;
 
@@ -58,6 +74,12 @@ int ksu_handle_dynamic_manager(struct ksu_dynamic_manager_cmd *cmd)
     switch (cmd->operation) {
     case DYNAMIC_MANAGER_OP_SET_SYNCHRONOUS:
     case DYNAMIC_MANAGER_OP_SET:
+    case DYNAMIC_MANAGER_OP_ADD_SYNCHRONOUS:
+    case DYNAMIC_MANAGER_OP_ADD:
+        synchronous = cmd->operation == DYNAMIC_MANAGER_OP_SET_SYNCHRONOUS ||
+                      cmd->operation == DYNAMIC_MANAGER_OP_ADD_SYNCHRONOUS;
+        replace = cmd->operation == DYNAMIC_MANAGER_OP_SET || cmd->operation == DYNAMIC_MANAGER_OP_SET_SYNCHRONOUS;
+
         if (cmd->size < 0x100 || cmd->size > 0x1000) {
             pr_err("invalid size: 0x%x\n", cmd->size);
             return -EINVAL;
@@ -72,19 +94,36 @@ int ksu_handle_dynamic_manager(struct ksu_dynamic_manager_cmd *cmd)
             }
         }
 
-        if (dynamic_manager.is_set) {
+        // set replaces the trusted signatures, add keeps them
+        if (replace) {
+            spin_lock_irqsave(&dynamic_manager_lock, flags);
+            WRITE_ONCE(dynamic_manager_count, 0);
+            spin_unlock_irqrestore(&dynamic_manager_lock, flags);
             ksu_unregister_manager_by_signature_index(KSU_SIGNATURE_INDEX_DYNAMIC_MANAGER);
         }
 
-        dynamic_manager.size = cmd->size;
-        // userspace always put an char[64] to our
-        // we just use memcpy to copy memory, and flag [64] to \0 by ourselves
-        memcpy(dynamic_manager.hash, cmd->hash, 64);
-        dynamic_manager.hash[64] = '\0';
-
-        dynamic_manager.is_set = 1;
+        spin_lock_irqsave(&dynamic_manager_lock, flags);
+        if (find_dynamic_manager(cmd->size, (const char *)cmd->hash) < 0) {
+            if (dynamic_manager_count < DYNAMIC_MANAGER_MAX) {
+                struct dynamic_manager_config *manager = &dynamic_managers[dynamic_manager_count];
+
+                manager->size = cmd->size;
+                // userspace always put an char[64] to our
+                // we just use memcpy to copy memory, and flag [64] to \0 by ourselves
+                memcpy(manager->hash, cmd->hash, 64);
+                manager->hash[64] = '\0';
+                WRITE_ONCE(dynamic_manager_count, dynamic_manager_count + 1);
+            } else {
+                ret = -ENOSPC;
+            }
+        }
+        spin_unlock_irqrestore(&dynamic_manager_lock, flags);
+        if (ret) {
+            pr_err("too many dynamic managers, at most %d\n", DYNAMIC_MANAGER_MAX);
+            return ret;
+        }
 
-        if (cmd->operation == DYNAMIC_MANAGER_OP_SET_SYNCHRONOUS)
+        if (synchronous)
             track_throne(TRACK_THRONE_FORCE_SEARCH_MGR | TRACK_THRONE_FORCE_SYNCHRONOUS);
         else
             track_throne(TRACK_THRONE_FORCE_SEARCH_MGR);
@@ -92,18 +131,23 @@ int ksu_handle_dynamic_manager(struct ksu_dynamic_manager_cmd *cmd)
         break;
 
     case DYNAMIC_MANAGER_OP_GET:
-        if (dynamic_manager.is_set) {
-            cmd->size = dynamic_manager.size;
+        // the first trusted signature, for callers knowing only one
+        spin_lock_irqsave(&dynamic_manager_lock, flags);
+        if (dynamic_manager_count > 0) {
+            cmd->size = dynamic_managers[0].size;
 
             // only copy [64] is enough, userspace will handle that
-            memcpy(cmd->hash, dynamic_manager.hash, 64);
+            memcpy(cmd->hash, dynamic_managers[0].hash, 64);
             ret = 0;
         } else {
             ret = -ENODATA;
         }
+        spin_unlock_irqrestore(&dynamic_manager_lock, flags);
         break;
     case DYNAMIC_MANAGER_OP_WIPE:
-        dynamic_manager.is_set = 0;
+        spin_lock_irqsave(&dynamic_manager_lock, flags);
+        WRITE_ONCE(dynamic_manager_count, 0);
+        spin_unlock_irqrestore(&dynamic_manager_lock, flags);
         ret = 0;
         ksu_unregister_manager_by_signature_index(KSU_SIGNATURE_INDEX_DYNAMIC_MANAGER);
         pr_info("dynamic manager kernel settings reseted");
@@ -9,7 +9,6 @@
 struct dynamic_manager_config {
     unsigned size;
     char hash[65];
-    int is_set;
 };
 
 struct manager_info {
@@ -22,6 +21,6 @@ struct manager_info {
 int ksu_handle_dynamic_manager(struct ksu_dynamic_manager_cmd *cmd);
 bool ksu_load_dynamic_manager(void);
 bool ksu_is_dynamic_manager_enabled(void);
-apk_sign_key_t ksu_get_dynamic_manager_sign(void);
+bool ksu_is_dynamic_manager_sign(unsigned size, const char *hash);
 
 #endif
\ No newline at end of file
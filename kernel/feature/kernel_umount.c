@@ -167,6 +167,10 @@ int ksu_handle_umount(uid_t old_uid, uid_t new_uid)
 
     down_read(&mount_list_lock);
     list_for_each_entry (entry, &mount_list, list) {
+        // scoped entries only apply to processes of their app
+        if (entry->appid != KSU_UMOUNT_ANY_APPID && entry->appid != new_uid % PER_USER_RANGE) {
+            continue;
+        }
         pr_info("%s: unmounting: %s flags 0x%x\n", __func__, entry->umountable, entry->flags);
         try_umount(entry->umountable, entry->flags);
     }
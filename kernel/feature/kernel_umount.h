@@ -12,9 +12,12 @@ void ksu_kernel_umount_exit(void);
 int ksu_handle_umount(uid_t old_uid, uid_t new_uid);
 
 // for the umount list
+#define KSU_UMOUNT_ANY_APPID ((uid_t)-1)
+
 struct mount_entry {
     char *umountable;
     unsigned int flags;
+    uid_t appid; // KSU_UMOUNT_ANY_APPID for global entries
     struct list_head list;
 };
 extern struct list_head mount_list;
@@ -688,9 +688,53 @@ static int ksu_umount_list_getlist(struct ksu_manage_try_umount_cmd *cmd, bool l
     return 0;
 }
 
+static int ksu_umount_list_add(const char *path, unsigned int flags, uid_t appid)
+{
+    struct mount_entry *new_entry, *entry;
+
+    new_entry = kzalloc(sizeof(*new_entry), GFP_KERNEL);
+    if (!new_entry)
+        return -ENOMEM;
+
+    new_entry->umountable = kstrdup(path, GFP_KERNEL);
+    if (!new_entry->umountable) {
+        kfree(new_entry);
+        return -ENOMEM;
+    }
+
+    down_write(&mount_list_lock);
+
+    // disallow dupes
+    // if this gets too many, we can consider moving this whole task to a kthread
+    list_for_each_entry (entry, &mount_list, list) {
+        if (!strcmp(entry->umountable, path) && entry->appid == appid) {
+            pr_info("cmd_manage_try_umount: %s is already here!\n", path);
+            up_write(&mount_list_lock);
+            kfree(new_entry->umountable);
+            kfree(new_entry);
+            return -EEXIST;
+        }
+    }
+
+    // now check flags and add
+    // this also serves as a null check
+    if (flags)
+        new_entry->flags = flags;
+    else
+        new_entry->flags = 0;
+    new_entry->appid = appid;
+
+    // debug
+    list_add(&new_entry->list, &mount_list);
+    up_write(&mount_list_lock);
+    pr_info("cmd_manage_try_umount: %s added (appid: %u)!\n", path, appid);
+
+    return 0;
+}
+
 static int manage_try_umount(void __user *arg)
 {
-    struct mount_entry *new_entry, *entry, *tmp;
+    struct mount_entry *entry, *tmp;
     struct ksu_manage_try_umount_cmd cmd;
     char buf[256] = { 0 };
 
@@ -719,43 +763,23 @@ static int manage_try_umount(void __user *arg)
 
         buf[sizeof(buf) - 1] = '\0';
 
-        new_entry = kzalloc(sizeof(*new_entry), GFP_KERNEL);
-        if (!new_entry)
-            return -ENOMEM;
-
-        new_entry->umountable = kstrdup(buf, GFP_KERNEL);
-        if (!new_entry->umountable) {
-            kfree(new_entry);
-            return -ENOMEM;
-        }
+        return ksu_umount_list_add(buf, cmd.flags, KSU_UMOUNT_ANY_APPID);
+    }
 
-        down_write(&mount_list_lock);
+    case KSU_UMOUNT_ADD_SCOPED: {
+        struct ksu_umount_scoped_entry scoped;
+        long len;
 
-        // disallow dupes
-        // if this gets too many, we can consider moving this whole task to a kthread
-        list_for_each_entry (entry, &mount_list, list) {
-            if (!strcmp(entry->umountable, buf)) {
-                pr_info("cmd_manage_try_umount: %s is already here!\n", buf);
-                up_write(&mount_list_lock);
-                kfree(new_entry->umountable);
-                kfree(new_entry);
-                return -EEXIST;
-            }
-        }
+        if (copy_from_user(&scoped, (const void __user *)cmd.arg, sizeof(scoped)))
+            return -EFAULT;
 
-        // now check flags and add
-        // this also serves as a null check
-        if (cmd.flags)
-            new_entry->flags = cmd.flags;
-        else
-            new_entry->flags = 0;
+        len = strncpy_from_user(buf, (const char __user *)scoped.path, 256);
+        if (len <= 0)
+            return -EFAULT;
 
-        // debug
-        list_add(&new_entry->list, &mount_list);
-        up_write(&mount_list_lock);
-        pr_info("cmd_manage_try_umount: %s added!\n", buf);
+        buf[sizeof(buf) - 1] = '\0';
 
-        return 0;
+        return ksu_umount_list_add(buf, cmd.flags, scoped.appid);
     }
 
     // this is just strcmp'd wipe anyway
@@ -162,6 +162,12 @@ DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_GETSIZE_LEGACY, 107) // get list size (le
 DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_GETLIST_LEGACY, 108) // get list (legacy)
 DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_GETSIZE_NEW, 200) // get list size (new (with flags))
 DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_GETLIST_NEW, 201) // get list (new (with flags))
+DEFINE_KSU_UAPI_CONST(__u8, KSU_UMOUNT_ADD_SCOPED, 202) // add entry only for one app id
+
+struct ksu_umount_scoped_entry {
+    __aligned_u64 path; /* Input: char ptr, the mountpoint */
+    __u32 appid; /* Input: only umount for processes of this app id */
+};
 
 // Downstream supercall struct
 struct ksu_get_full_version_cmd {
@@ -176,6 +182,10 @@ DEFINE_KSU_UAPI_CONST(__u8, DYNAMIC_MANAGER_OP_SET, 0)
 DEFINE_KSU_UAPI_CONST(__u8, DYNAMIC_MANAGER_OP_GET, 1)
 DEFINE_KSU_UAPI_CONST(__u8, DYNAMIC_MANAGER_OP_WIPE, 2)
 DEFINE_KSU_UAPI_CONST(__u8, DYNAMIC_MANAGER_OP_SET_SYNCHRONOUS, 3)
+// add to the trusted signatures instead of replacing them
+DEFINE_KSU_UAPI_CONST(__u8, DYNAMIC_MANAGER_OP_ADD, 4)
+DEFINE_KSU_UAPI_CONST(__u8, DYNAMIC_MANAGER_OP_ADD_SYNCHRONOUS, 5)
+DEFINE_KSU_UAPI_CONST(__u32, DYNAMIC_MANAGER_MAX, 8)
 
 struct ksu_dynamic_manager_cmd {
     __u8 operation;
@@ -0,0 +1,215 @@
+//! Android IDs, see `system/core/libcutils/include/private/android_filesystem_config.h`.
+
+use std::ffi::CString;
+
+use anyhow::{Result, bail};
+
+const AID_USER_OFFSET: u32 = 100_000;
+const AID_APP_START: u32 = 10_000;
+const AID_CACHE_GID_START: u32 = 20_000;
+const AID_ISOLATED_START: u32 = 90_000;
+
+const ANDROID_IDS: &[(&str, u32)] = &[
+    ("root", 0),
+    ("system", 1000),
+    ("radio", 1001),
+    ("bluetooth", 1002),
+    ("graphics", 1003),
+    ("input", 1004),
+    ("audio", 1005),
+    ("camera", 1006),
+    ("log", 1007),
+    ("compass", 1008),
+    ("mount", 1009),
+    ("wifi", 1010),
+    ("adb", 1011),
+    ("install", 1012),
+    ("media", 1013),
+    ("dhcp", 1014),
+    ("sdcard_rw", 1015),
+    ("vpn", 1016),
+    ("keystore", 1017),
+    ("usb", 1018),
+    ("drm", 1019),
+    ("mdnsr", 1020),
+    ("gps", 1021),
+    ("media_rw", 1023),
+    ("mtp", 1024),
+    ("drmrpc", 1026),
+    ("nfc", 1027),
+    ("sdcard_r", 1028),
+    ("clat", 1029),
+    ("loop_radio", 1030),
+    ("mediadrm", 1031),
+    ("package_info", 1032),
+    ("sdcard_pics", 1033),
+    ("sdcard_av", 1034),
+    ("sdcard_all", 1035),
+    ("logd", 1036),
+    ("shared_relro", 1037),
+    ("dbus", 1038),
+    ("tlsdate", 1039),
+    ("mediaex", 1040),
+    ("audioserver", 1041),
+    ("metrics_coll", 1042),
+    ("metricsd", 1043),
+    ("webserv", 1044),
+    ("debuggerd", 1045),
+    ("mediacodec", 1046),
+    ("cameraserver", 1047),
+    ("firewall", 1048),
+    ("trunks", 1049),
+    ("nvram", 1050),
+    ("dns", 1051),
+    ("dns_tether", 1052),
+    ("webview_zygote", 1053),
+    ("vehicle_network", 1054),
+    ("media_audio", 1055),
+    ("media_video", 1056),
+    ("media_image", 1057),
+    ("tombstoned", 1058),
+    ("media_obb", 1059),
+    ("ese", 1060),
+    ("ota_update", 1061),
+    ("automotive_evs", 1062),
+    ("lowpan", 1063),
+    ("hsm", 1064),
+    ("reserved_disk", 1065),
+    ("statsd", 1066),
+    ("incidentd", 1067),
+    ("secure_element", 1068),
+    ("lmkd", 1069),
+    ("llkd", 1070),
+    ("iorapd", 1071),
+    ("gpu_service", 1072),
+    ("network_stack", 1073),
+    ("gsid", 1074),
+    ("fsverity_cert", 1075),
+    ("credstore", 1076),
+    ("external_storage", 1077),
+    ("ext_data_rw", 1078),
+    ("ext_obb_rw", 1079),
+    ("context_hub", 1080),
+    ("virtualizationservice", 1081),
+    ("artd", 1082),
+    ("uwb", 1083),
+    ("thread_network", 1084),
+    ("diced", 1085),
+    ("dmesgd", 1086),
+    ("shell", 2000),
+    ("cache", 2001),
+    ("diag", 2002),
+    ("net_bt_admin", 3001),
+    ("net_bt", 3002),
+    ("inet", 3003),
+    ("net_raw", 3004),
+    ("net_admin", 3005),
+    ("net_bw_stats", 3006),
+    ("net_bw_acct", 3007),
+    ("readproc", 3009),
+    ("wakelock", 3010),
+    ("uhid", 3011),
+    ("readtracefs", 3012),
+    ("everybody", 9997),
+    ("misc", 9998),
+    ("nobody", 9999),
+];
+
+fn android_id(name: &str) -> Option<u32> {
+    ANDROID_IDS
+        .iter()
+        .find(|(n, _)| *n == name)
+        .map(|(_, id)| *id)
+}
+
+// u<user>_a<app>, u<user>_a<app>_cache, u<user>_i<isolated> and u<user>_<aid name>
+fn per_user_id(name: &str) -> Option<u32> {
+    let (user, rest) = name.strip_prefix('u')?.split_once('_')?;
+    let user = user.parse::<u32>().ok()?;
+
+    let number = |prefix: char, suffix: &str| {
+        rest.strip_prefix(prefix)?
+            .strip_suffix(suffix)?
+            .parse::<u32>()
+            .ok()
+    };
+    let id = if let Some(app) = number('a', "") {
+        AID_APP_START.checked_add(app)?
+    } else if let Some(app) = number('a', "_cache") {
+        AID_CACHE_GID_START.checked_add(app)?
+    } else if let Some(isolated) = number('i', "") {
+        AID_ISOLATED_START.checked_add(isolated)?
+    } else {
+        android_id(rest).filter(|id| *id < AID_APP_START)?
+    };
+
+    user.checked_mul(AID_USER_OFFSET)?.checked_add(id)
+}
+
+fn lookup(name: &str) -> Option<u32> {
+    name.parse::<u32>()
+        .ok()
+        .or_else(|| android_id(name))
+        .or_else(|| per_user_id(name))
+}
+
+/// Resolve a user name or numeric uid, e.g. `shell`, `u10_a123` or `2000`.
+pub fn resolve_uid(name: &str) -> Result<u32> {
+    if let Some(uid) = lookup(name) {
+        return Ok(uid);
+    }
+    // OEM and vendor ids only bionic knows about
+    let pw = CString::new(name)
+        .ok()
+        .and_then(|c_name| unsafe { libc::getpwnam(c_name.as_ptr()).as_ref() });
+    match pw {
+        Some(pw) => Ok(pw.pw_uid),
+        None => bail!("Unknown user: {name}"),
+    }
+}
+
+/// Resolve a group name or numeric gid, e.g. `inet`, `u0_a123_cache` or `3003`.
+pub fn resolve_gid(name: &str) -> Result<u32> {
+    if let Some(gid) = lookup(name) {
+        return Ok(gid);
+    }
+    let gr = CString::new(name)
+        .ok()
+        .and_then(|c_name| unsafe { libc::getgrnam(c_name.as_ptr()).as_ref() });
+    match gr {
+        Some(gr) => Ok(gr.gr_gid),
+        None => bail!("Unknown group: {name}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn per_user_app() {
+        assert_eq!(resolve_uid("u10_a123").unwrap(), 1_010_123);
+        assert_eq!(resolve_uid("u0_a0").unwrap(), 10_000);
+    }
+
+    #[test]
+    fn per_user_cache() {
+        assert_eq!(resolve_gid("u0_a5_cache").unwrap(), 20_005);
+        assert_eq!(resolve_gid("u10_a5_cache").unwrap(), 1_020_005);
+    }
+
+    #[test]
+    fn android_names() {
+        assert_eq!(resolve_gid("inet").unwrap(), 3003);
+        assert_eq!(resolve_uid("shell").unwrap(), 2000);
+        assert_eq!(resolve_uid("2000").unwrap(), 2000);
+        assert_eq!(resolve_uid("u10_shell").unwrap(), 1_002_000);
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert_eq!(lookup("u10_a"), None);
+        assert_eq!(lookup("ux_a1"), None);
+        assert_eq!(lookup("u0_nosuchaid"), None);
+    }
+}
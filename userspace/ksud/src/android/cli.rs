@@ -1,14 +1,15 @@
-use std::path::PathBuf;
+use std::{collections::HashMap, path::PathBuf};
 
 use android_logger::Config;
 use anyhow::{Context, Ok, Result};
 use clap::Parser;
-use log::{LevelFilter, error, info};
+use log::{LevelFilter, error, info, warn};
 
 use crate::{
     android::{
         debug, dynamic_manager, feature, init_event, ksucalls,
-        module::{self, module_config, regenerate_preinit_rc},
+        late_load::Step,
+        module::{self, config_schema, magic_mount, module_config, regenerate_preinit_rc},
         profile, sepolicy, su, sulog, susfs, uapi, umount_config, utils,
     },
     apk_sign, assets,
@@ -63,9 +64,25 @@ enum Commands {
         #[arg(long)]
         kmi: Option<String>,
 
+        /// Load this kernelsu.ko instead of an embedded one
+        #[arg(long)]
+        module: Option<PathBuf>,
+
         /// manager package name
         #[arg(long, default_value_t = String::from("com.resukisu.resukisu"))]
         package_name: String,
+
+        /// Show which steps of the last late-load succeeded or failed
+        #[arg(long, conflicts_with_all = ["magica", "resume_from", "skip"])]
+        status: bool,
+
+        /// Start at this step, e.g. to complete an interrupted late-load
+        #[arg(long, value_parser = crate::android::late_load::parse_step)]
+        resume_from: Option<Step>,
+
+        /// Don't run this step (can be repeated)
+        #[arg(long, value_parser = crate::android::late_load::parse_step)]
+        skip: Vec<Step>,
     },
 
     /// Manage susfs component
@@ -87,6 +104,9 @@ enum Commands {
     Insmod {
         /// kernel module path
         module: PathBuf,
+        /// only report symbol resolution and version mismatches, don't load
+        #[arg(long)]
+        dry_run: bool,
         /// module load parameters (e.g. key=val key2=val2)
         #[arg(trailing_var_arg = true, allow_hyphen_values = true, num_args = 0..)]
         params: Vec<String>,
@@ -99,7 +119,27 @@ enum Commands {
     },
 
     /// Unload KernelSU kernel module (LKM Only)
-    Unload,
+    Unload {
+        /// List the processes that would be terminated, without doing anything
+        #[arg(long)]
+        dry_run: bool,
+
+        /// Seconds to wait after SIGTERM before sending SIGKILL, 0 to kill right away
+        #[arg(long, default_value_t = 3)]
+        grace: u64,
+
+        /// Don't stop and restart Android services unless a framework process holds a ksu fd
+        #[arg(long)]
+        keep_framework: bool,
+
+        /// Also unmount module mounts and restore module properties
+        #[arg(long)]
+        cleanup: bool,
+
+        /// With --cleanup, also run the uninstall hooks of modules, although they stay installed
+        #[arg(long, requires = "cleanup")]
+        run_uninstall: bool,
+    },
 
     /// Uninstall KernelSU modules and itself(LKM Only)
     Uninstall {
@@ -162,21 +202,48 @@ enum Commands {
 enum UmountConfigOp {
     /// Add an new umount config to configuration file
     Add {
-        /// mount point path
+        /// mount point path, or a glob pattern (`*`, `?`, `**`) expanded against mountinfo
         mnt: String,
         /// umount flags (default: 0, MNT_DETACH: 2)
         #[arg(short, long, default_value = "0")]
         flags: u32,
+        /// only umount for this package (can be repeated)
+        #[arg(long)]
+        package: Vec<String>,
+        /// only umount for this uid (can be repeated)
+        #[arg(long)]
+        uid: Vec<u32>,
     },
     /// Delete an umount config from configuration file
     Del {
         /// mount point path
         mnt: String,
+        /// only remove this package from the entry's scope (can be repeated)
+        #[arg(long)]
+        package: Vec<String>,
+        /// only remove this uid from the entry's scope (can be repeated)
+        #[arg(long)]
+        uid: Vec<u32>,
     },
     /// Clear all auto apply umount config from configuration file
     Clear,
     /// List all configured auto apply umount configuration
-    List,
+    List {
+        /// expand patterns against the current mount table
+        #[arg(short, long)]
+        expand: bool,
+    },
+    /// Compare configuration file with the kernel umount list, app scoped entries are left out
+    Diff,
+    /// Reconcile configuration file and the kernel umount list, app scoped entries are left out
+    Sync {
+        /// update configuration file from the kernel umount list instead
+        #[arg(long)]
+        from_kernel: bool,
+        /// also remove entries that only exist on the target side
+        #[arg(long)]
+        prune: bool,
+    },
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -196,6 +263,10 @@ enum BootInfo {
     /// list available partitions for current or OTA toggled slot
     AvailablePartitions,
 
+    /// show whether ksuinit loaded kernelsu.ko at boot, and why not, for this and the
+    /// previous boot
+    LkmStatus,
+
     /// show slot suffix for current or OTA toggled slot
     SlotSuffix {
         /// toggle to another slot
@@ -219,6 +290,12 @@ enum Debug {
         apk: String,
     },
 
+    /// Verify apk signatures and list all signers
+    VerifyApk {
+        /// apk path
+        apk: String,
+    },
+
     /// Root Shell
     Su {
         /// switch to gloabl mount namespace
@@ -251,6 +328,19 @@ enum Debug {
 
     /// Get kernel info
     Info,
+
+    /// Magic mount modules below a root, e.g. a fake root on a tmpfs
+    MagicMount {
+        /// root to mount below
+        #[arg(long, default_value = "/")]
+        root: PathBuf,
+        /// where to build directories on a tmpfs
+        #[arg(long, default_value = defs::MAGIC_MOUNT_WORK_DIR)]
+        work_dir: PathBuf,
+        /// directory containing the modules
+        #[arg(long, default_value = defs::MODULE_DIR)]
+        module_dir: PathBuf,
+    },
 }
 
 #[derive(clap::Subcommand, Debug)]
@@ -342,6 +432,13 @@ enum Module {
     /// list all modules
     List,
 
+    /// show or select the engine that mounts modules at boot
+    MountEngine {
+        /// metamodule (the metamodule's mount script) or magic (built-in magic mount)
+        #[arg(value_parser = module::mount::MountEngine::parse)]
+        engine: Option<module::mount::MountEngine>,
+    },
+
     /// manage module configuration
     Config {
         /// target internal module name (resolved as internal.<name>)
@@ -372,10 +469,54 @@ enum ModuleConfigCmd {
         /// use temporary config (cleared on reboot)
         #[arg(short, long)]
         temp: bool,
+        /// value type: bool, int, string or json (default: from the config schema, else string)
+        #[arg(long = "type")]
+        value_type: Option<module_config::ValueType>,
+        /// only set if the current value equals this, compared as the type of the current value
+        #[arg(long, conflicts_with = "expect_missing")]
+        expect: Option<String>,
+        /// only set if the key does not exist yet
+        #[arg(long)]
+        expect_missing: bool,
     },
 
     /// List all config entries
-    List,
+    List {
+        /// print a JSON object with typed values
+        #[arg(long)]
+        json: bool,
+        /// include defaults from the module's config schema for unset keys
+        #[arg(long)]
+        with_defaults: bool,
+    },
+
+    /// Print the path of the change stamp, rewritten on every config change
+    Stamp,
+
+    /// Export persist configs of modules as a portable JSON document, works without a
+    /// module context
+    #[command(group = clap::ArgGroup::new("target").required(true))]
+    Export {
+        /// export the configs of all modules
+        #[arg(long, group = "target")]
+        all: bool,
+        /// export the configs of this module
+        #[arg(long, group = "target")]
+        module: Option<String>,
+        /// write to this file instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+
+    /// Import persist configs of modules from a document written by export, works without a
+    /// module context
+    Import {
+        /// document to import (omit to read from stdin)
+        file: Option<PathBuf>,
+        /// merge: keep keys missing from the document, replace: drop them
+        #[arg(long, default_value = "merge")]
+        mode: module_config::ImportMode,
+    },
 
     /// Delete a config entry
     Delete {
@@ -492,7 +633,7 @@ enum Kernel {
 
 #[derive(clap::Subcommand, Debug)]
 enum DynamicManagerOp {
-    /// Get the signature of the current dynamic manager (size+hash)
+    /// Get the signature of the first trusted dynamic manager (size+hash)
     Get {
         #[arg(long)]
         internal: Option<bool>,
@@ -510,6 +651,21 @@ enum DynamicManagerOp {
         /// the apk path
         apk: String,
     },
+    /// Trust the signer of an apk as a dynamic manager, next to the ones trusted already
+    Add {
+        /// label of the trusted manager (e.g. release, debug)
+        label: String,
+        /// the apk path
+        #[arg(long)]
+        apk: String,
+    },
+    /// Remove a trusted dynamic manager
+    Remove {
+        /// label of the trusted manager
+        label: String,
+    },
+    /// List all trusted dynamic managers
+    List,
     /// Clear the dynamic manager
     Clear,
 }
@@ -590,13 +746,34 @@ pub fn run() -> Result<()> {
             Ok(())
         }
         Commands::UmountConfig { command } => match command {
-            UmountConfigOp::Add { mnt, flags } => umount_config::add_umount(&mnt, flags),
-            UmountConfigOp::Del { mnt } => umount_config::del_umount(&mnt),
+            UmountConfigOp::Add {
+                mnt,
+                flags,
+                package,
+                uid,
+            } => umount_config::add_umount(&mnt, flags, package, uid),
+            UmountConfigOp::Del { mnt, package, uid } => {
+                umount_config::del_umount(&mnt, &package, &uid)
+            }
             UmountConfigOp::Clear => umount_config::wipe_umount(),
-            UmountConfigOp::List => umount_config::list_umount(),
+            UmountConfigOp::List { expand } => umount_config::list_umount(expand),
+            UmountConfigOp::Diff => umount_config::diff_umount(),
+            UmountConfigOp::Sync { from_kernel, prune } => {
+                umount_config::sync_umount(from_kernel, prune)
+            }
         },
         Commands::SoftReboot => init_event::soft_reboot(),
-        Commands::Insmod { module, params } => debug::insmod(&module, &params),
+        Commands::Insmod {
+            module,
+            dry_run,
+            params,
+        } => {
+            if dry_run {
+                debug::insmod_dry_run(&module)
+            } else {
+                debug::insmod(&module, &params)
+            }
+        }
         Commands::Module { command } => {
             utils::switch_mnt_ns(1)?;
             match command {
@@ -607,6 +784,51 @@ pub fn run() -> Result<()> {
                 Module::Disable { id } => module::disable_module(&id),
                 Module::Action { id } => module::run_action(&id),
                 Module::List => module::list_modules(),
+                Module::MountEngine { engine } => match engine {
+                    Some(engine) => engine.select(),
+                    None => {
+                        println!("{}", module::mount::MountEngine::current().as_str());
+                        Ok(())
+                    }
+                },
+                // these cover all modules, so they don't need a module context
+                Module::Config {
+                    command:
+                        ModuleConfigCmd::Export {
+                            all: _,
+                            module,
+                            output,
+                        },
+                    ..
+                } => {
+                    let json = module_config::export_configs(module.as_deref())?;
+                    match output {
+                        Some(output) => std::fs::write(&output, json)
+                            .with_context(|| format!("Failed to write {}", output.display())),
+                        None => {
+                            println!("{json}");
+                            Ok(())
+                        }
+                    }
+                }
+                Module::Config {
+                    command: ModuleConfigCmd::Import { file, mode },
+                    ..
+                } => {
+                    let json = match file {
+                        Some(file) => std::fs::read_to_string(&file)
+                            .with_context(|| format!("Failed to read {}", file.display()))?,
+                        None => {
+                            use std::io::Read;
+                            let mut buffer = String::new();
+                            std::io::stdin()
+                                .read_to_string(&mut buffer)
+                                .context("Failed to read from stdin")?;
+                            buffer
+                        }
+                    };
+                    module_config::import_configs(&json, mode)
+                }
                 Module::Config { internal, command } => {
                     let module_id = match internal {
                         Some(internal_name) => format!("internal.{internal_name}"),
@@ -635,6 +857,9 @@ pub fn run() -> Result<()> {
                             value,
                             stdin,
                             temp,
+                            value_type,
+                            expect,
+                            expect_missing,
                         } => {
                             // Validate key at CLI layer for better user experience
                             module_config::validate_config_key(&key)?;
@@ -655,21 +880,67 @@ pub fn run() -> Result<()> {
 
                             // Validate value
                             module_config::validate_config_value(&value_str)?;
+                            let value_type = match value_type {
+                                Some(value_type) => value_type,
+                                None => config_schema::Schema::load(&module_id)?
+                                    .and_then(|schema| {
+                                        schema
+                                            .properties
+                                            .get(&key)
+                                            .map(config_schema::Property::value_type)
+                                    })
+                                    .unwrap_or_default(),
+                            };
+                            let value = module_config::ConfigValue::parse(value_type, &value_str)?;
 
                             let config_type = if temp {
                                 module_config::ConfigType::Temp
                             } else {
                                 module_config::ConfigType::Persist
                             };
-                            module_config::set_config_value(
+                            let expected = match (expect.as_deref(), expect_missing) {
+                                (Some(expected), _) => {
+                                    Some(module_config::Expected::Value(expected))
+                                }
+                                (None, true) => Some(module_config::Expected::Missing),
+                                (None, false) => None,
+                            };
+                            module_config::compare_and_set(
                                 &module_id,
                                 &key,
-                                &value_str,
+                                value,
                                 config_type,
+                                expected,
                             )
                         }
-                        ModuleConfigCmd::List => {
-                            let config = module_config::merge_configs(&module_id)?;
+                        ModuleConfigCmd::List {
+                            json: true,
+                            with_defaults,
+                        } => {
+                            let config = if with_defaults {
+                                module_config::merge_configs_with_defaults(&module_id)?
+                            } else {
+                                module_config::merge_typed_configs(&module_id)?
+                            };
+                            let config: serde_json::Map<String, serde_json::Value> = config
+                                .into_iter()
+                                .map(|(key, value)| (key, value.to_json()))
+                                .collect();
+                            println!("{}", serde_json::Value::Object(config));
+                            Ok(())
+                        }
+                        ModuleConfigCmd::List {
+                            json: false,
+                            with_defaults,
+                        } => {
+                            let config: HashMap<String, String> = if with_defaults {
+                                module_config::merge_configs_with_defaults(&module_id)?
+                                    .into_iter()
+                                    .map(|(key, value)| (key, value.to_string()))
+                                    .collect()
+                            } else {
+                                module_config::merge_configs(&module_id)?
+                            };
                             if config.is_empty() {
                                 println!("No config entries found");
                             } else {
@@ -695,12 +966,31 @@ pub fn run() -> Result<()> {
                             };
                             module_config::clear_config(&module_id, config_type)
                         }
+                        ModuleConfigCmd::Stamp => {
+                            println!("{}", module_config::get_stamp_path(&module_id).display());
+                            Ok(())
+                        }
+                        ModuleConfigCmd::Export { .. } | ModuleConfigCmd::Import { .. } => {
+                            unreachable!("handled without a module context")
+                        }
                     }
                 }
             }
         }
         Commands::Install { libadbroot } => utils::install(libadbroot),
-        Commands::Unload => crate::android::unload::unload(),
+        Commands::Unload {
+            dry_run,
+            grace,
+            keep_framework,
+            cleanup,
+            run_uninstall,
+        } => crate::android::unload::unload(&crate::android::unload::Options {
+            dry_run,
+            grace: std::time::Duration::from_secs(grace),
+            keep_framework,
+            cleanup,
+            run_uninstall,
+        }),
         Commands::Uninstall { package_name } => utils::uninstall(&package_name),
         Commands::Sepolicy { command } => match command {
             Sepolicy::Patch { sepolicy } => sepolicy::live_patch(&sepolicy),
@@ -712,8 +1002,15 @@ pub fn run() -> Result<()> {
             allow_shell,
             post_magica,
             kmi,
+            module,
             package_name,
+            status,
+            resume_from,
+            skip,
         } => {
+            if status {
+                return crate::android::late_load::print_status();
+            }
             if let Some(port) = magica {
                 return crate::android::late_load::magica::run(port, &package_name, allow_shell)
                     .map_err(|e| {
@@ -721,7 +1018,14 @@ pub fn run() -> Result<()> {
                         e
                     });
             }
-            let result = crate::android::late_load::run(&package_name, kmi, allow_shell);
+            let result = crate::android::late_load::run(
+                &package_name,
+                kmi,
+                allow_shell,
+                module.as_deref(),
+                resume_from,
+                &skip,
+            );
             if post_magica {
                 info!("Restoring adb properties (post-magica cleanup)...");
                 if let Err(e) = crate::android::late_load::magica::disable_adb_root() {
@@ -773,6 +1077,10 @@ pub fn run() -> Result<()> {
                 println!("size: {:#x}, hash: {}", sign.0, sign.1);
                 Ok(())
             }
+            Debug::VerifyApk { apk } => {
+                let sdk = utils::getprop("ro.build.version.sdk").and_then(|s| s.parse().ok());
+                apk_sign::dump_apk_signers(&apk, sdk)
+            }
             Debug::Version => {
                 println!("Kernel Version: {}", ksucalls::get_version());
                 Ok(())
@@ -790,6 +1098,14 @@ pub fn run() -> Result<()> {
                 MarkCommand::Refresh => debug::mark_refresh(),
             },
             Debug::Sulogd => sulog::ensure_sulogd_running(),
+            Debug::MagicMount {
+                root,
+                work_dir,
+                module_dir,
+            } => {
+                let modules = magic_mount::modules_in(&module_dir)?;
+                magic_mount::MagicMount::new(root, work_dir).mount(&modules)
+            }
             Debug::Info => {
                 let info = ksucalls::get_info();
                 println!("version: {}", info.version);
@@ -842,6 +1158,19 @@ pub fn run() -> Result<()> {
                 println!("{suffix}");
                 return Ok(());
             }
+            BootInfo::LkmStatus => {
+                match utils::lkm_status() {
+                    Some(status) => match status.strip_prefix("failed:") {
+                        Some(reason) => println!("LKM failed to load at boot: {}", reason.trim()),
+                        None => println!("LKM {status}"),
+                    },
+                    None => println!("no ksuinit record for this boot"),
+                }
+                if let Some(status) = utils::previous_lkm_status() {
+                    println!("previous boot: LKM {status}");
+                }
+                return Ok(());
+            }
             BootInfo::AvailablePartitions => {
                 let parts = crate::boot_patch::list_available_partitions();
                 for p in &parts {
@@ -880,20 +1209,18 @@ pub fn run() -> Result<()> {
                     }
                     Ok(())
                 }
-                DynamicManagerOp::SetApk { apk } => {
-                    let sign = apk_sign::get_apk_signature(&apk)?;
-
-                    let bytes = sign.1.as_bytes();
-
-                    let mut hash = [0u8; 64];
-                    hash.copy_from_slice(bytes);
-
-                    dynamic_manager::set(sign.0, hash)
-                }
+                DynamicManagerOp::SetApk { apk } => dynamic_manager::set_apk(&apk),
+                DynamicManagerOp::Add { label, apk } => dynamic_manager::add(&label, &apk),
+                DynamicManagerOp::Remove { label } => dynamic_manager::remove(&label),
+                DynamicManagerOp::List => dynamic_manager::list(),
                 DynamicManagerOp::Clear => dynamic_manager::clear(),
             },
             Kernel::NotifyModuleMounted => {
                 ksucalls::report_module_mounted();
+                // module mounts may add new matches for umount patterns
+                if let Err(e) = umount_config::load_umount_config() {
+                    warn!("reload umount config failed: {e}");
+                }
                 Ok(())
             }
         },
@@ -907,3 +1234,52 @@ pub fn run() -> Result<()> {
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn verify_cli() {
+        Args::command().debug_assert();
+    }
+
+    #[test]
+    fn late_load_module() {
+        let args =
+            Args::try_parse_from(["ksud", "late-load", "--module", "/data/local/tmp/ksu.ko"])
+                .unwrap();
+        let Commands::LateLoad { module, .. } = args.command else {
+            panic!("not parsed as late-load: {args:?}");
+        };
+        assert_eq!(module, Some(PathBuf::from("/data/local/tmp/ksu.ko")));
+    }
+
+    #[test]
+    fn module_config_export_import() {
+        let args = Args::try_parse_from(["ksud", "module", "config", "export", "--all"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Commands::Module {
+                command: Module::Config {
+                    command: ModuleConfigCmd::Export { all: true, .. },
+                    ..
+                }
+            }
+        ));
+        let args =
+            Args::try_parse_from(["ksud", "module", "config", "import", "configs.json"]).unwrap();
+        assert!(matches!(
+            args.command,
+            Commands::Module {
+                command: Module::Config {
+                    command: ModuleConfigCmd::Import { file: Some(_), .. },
+                    ..
+                }
+            }
+        ));
+        assert!(Args::try_parse_from(["ksud", "module", "config", "export"]).is_err());
+    }
+}
@@ -67,6 +67,44 @@ pub fn insmod(module: &Path, params: &[String]) -> Result<()> {
     Ok(())
 }
 
+pub fn insmod_dry_run(module: &Path) -> Result<()> {
+    let module_data =
+        fs::read(module).with_context(|| format!("read module failed: {}", module.display()))?;
+    let check = ksuinit::check_module(&module_data)
+        .with_context(|| format!("check module failed: {}", module.display()))?;
+
+    for (name, addr) in &check.symbols {
+        match addr {
+            Some(addr) => println!("resolved {name} = {addr:#x}"),
+            None => println!("missing  {name}"),
+        }
+    }
+
+    let missing = check.missing().count();
+    println!(
+        "{} symbols, {} resolved, {missing} missing",
+        check.symbols.len(),
+        check.symbols.len() - missing
+    );
+    println!("{} symbol versions, not checked", check.crc_unchecked);
+    println!(
+        "vermagic: {} (kernel {}){}",
+        check.vermagic.as_deref().unwrap_or("<none>"),
+        check.kernel_release,
+        if check.vermagic_matches() {
+            ""
+        } else if check.vermagic_compatible() {
+            ", same branch"
+        } else {
+            ", mismatch"
+        }
+    );
+    if !check.is_loadable() {
+        bail!("{} would fail to load", module.display());
+    }
+    Ok(())
+}
+
 /// Get mark status for a process
 pub fn mark_get(pid: i32) -> Result<()> {
     let result = ksucalls::mark_get(pid)?;
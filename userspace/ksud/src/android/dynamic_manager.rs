@@ -1,45 +1,154 @@
 use std::fs;
 
-use anyhow::Result;
+use anyhow::{Result, anyhow, bail, ensure};
 use serde::{Deserialize, Serialize};
 
-use crate::{android::ksucalls, defs};
+use crate::{
+    android::{ksucalls, uapi},
+    apk_sign, defs,
+};
+
+const CONFIG_VERSION: u32 = 2;
+// label given to signatures set without one (v1 config, `set`, `set-apk`)
+const DEFAULT_LABEL: &str = "default";
 
 #[derive(Debug, Deserialize, Serialize)]
-struct Config {
+struct Signature {
+    label: String,
     size: u32,
     hash: String,
 }
 
-impl Config {
-    fn hash_bytes(&self) -> Result<[u8; 64], String> {
-        parse_hash(&self.hash)
+impl Signature {
+    fn hash_bytes(&self) -> Result<[u8; 64]> {
+        parse_hash(&self.hash).map_err(|e| anyhow!("{e} for manager '{}'", self.label))
     }
+}
+
+/// Trusted manager signatures, all of them are pushed to the kernel.
+#[derive(Debug, Deserialize, Serialize)]
+struct Config {
+    version: u32,
+    #[serde(default)]
+    managers: Vec<Signature>,
+}
 
-    pub fn set_hash_from_bytes(&mut self, hash: [u8; 64]) {
-        self.hash = String::from_utf8_lossy(&hash).to_string();
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: CONFIG_VERSION,
+            managers: Vec::new(),
+        }
     }
 }
 
-pub fn booted_load() -> Result<()> {
+impl Config {
+    fn upsert(&mut self, signature: Signature) -> Result<()> {
+        if let Some(existing) = self
+            .managers
+            .iter_mut()
+            .find(|m| m.label == signature.label)
+        {
+            *existing = signature;
+        } else {
+            ensure!(
+                self.managers.len() < uapi::DYNAMIC_MANAGER_MAX_RUST as usize,
+                "At most {} managers can be trusted",
+                uapi::DYNAMIC_MANAGER_MAX_RUST
+            );
+            self.managers.push(signature);
+        }
+        Ok(())
+    }
+}
+
+// v1 config: a single unlabeled signature
+#[derive(Deserialize)]
+struct LegacyConfig {
+    size: u32,
+    hash: String,
+}
+
+fn read_config() -> Result<Config> {
     let buf = match fs::read_to_string(defs::DYNAMIC_MANAGER) {
         Ok(s) => s,
-        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Config::default()),
         Err(e) => return Err(e.into()),
     };
 
-    let json: Config = serde_json::from_str(&buf)?;
-    if json.hash.is_empty() || json.size == 0 {
-        return Ok(());
+    let value: serde_json::Value = serde_json::from_str(&buf)?;
+    if value.get("version").is_some() {
+        let config: Config = serde_json::from_value(value)?;
+        ensure!(
+            config.version <= CONFIG_VERSION,
+            "Unsupported dynamic manager config version {}",
+            config.version
+        );
+        return Ok(config);
     }
-    let Ok(hash) = json.hash_bytes() else {
-        return Ok(());
-    };
 
-    ksucalls::dynamic_manager_set(json.size, hash)?;
+    let legacy: LegacyConfig = serde_json::from_value(value)?;
+    let mut config = Config::default();
+    if !legacy.hash.is_empty() && legacy.size != 0 {
+        config.managers.push(Signature {
+            label: DEFAULT_LABEL.to_string(),
+            size: legacy.size,
+            hash: legacy.hash,
+        });
+    }
+    Ok(config)
+}
+
+fn write_config(config: &Config) -> Result<()> {
+    let string = serde_json::to_string_pretty(config)?;
+    fs::write(defs::DYNAMIC_MANAGER, string)?;
     Ok(())
 }
 
+// the first replaces what the kernel had, the others are added to it
+fn push(signatures: &[(u32, [u8; 64])], synchronous: bool) -> Result<()> {
+    let Some(((size, hash), rest)) = signatures.split_first() else {
+        return ksucalls::dynamic_manager_clear();
+    };
+    if synchronous {
+        ksucalls::dynamic_manager_set_synchronous(*size, *hash)?;
+    } else {
+        ksucalls::dynamic_manager_set(*size, *hash)?;
+    }
+    for (size, hash) in rest {
+        if synchronous {
+            ksucalls::dynamic_manager_add_synchronous(*size, *hash)?;
+        } else {
+            ksucalls::dynamic_manager_add(*size, *hash)?;
+        }
+    }
+    Ok(())
+}
+
+/// Push all trusted signatures to the kernel, or clear it if there are none
+fn apply(config: &Config) -> Result<()> {
+    let signatures = config
+        .managers
+        .iter()
+        .map(|m| Ok((m.size, m.hash_bytes()?)))
+        .collect::<Result<Vec<_>>>()?;
+    push(&signatures, true)
+}
+
+pub fn booted_load() -> Result<()> {
+    let config = read_config()?;
+    if config.managers.is_empty() {
+        return Ok(());
+    }
+    let signatures: Vec<_> = config
+        .managers
+        .iter()
+        .filter_map(|m| Some((m.size, m.hash_bytes().ok()?)))
+        .collect();
+
+    push(&signatures, false)
+}
+
 pub fn parse_hash(s: &str) -> Result<[u8; 64], String> {
     s.as_bytes()
         .try_into()
@@ -47,14 +156,7 @@ pub fn parse_hash(s: &str) -> Result<[u8; 64], String> {
 }
 
 pub fn clear() -> Result<()> {
-    let empty = Config {
-        size: 0,
-        hash: String::new(),
-    };
-
-    let string = serde_json::to_string_pretty(&empty)?;
-
-    fs::write(defs::DYNAMIC_MANAGER, string)?;
+    write_config(&Config::default())?;
 
     ksucalls::dynamic_manager_clear()?;
 
@@ -62,16 +164,59 @@ pub fn clear() -> Result<()> {
 }
 
 pub fn set(size: u32, hash: [u8; 64]) -> Result<()> {
-    let mut json_raw = Config {
+    let mut config = read_config()?;
+    config.upsert(Signature {
+        label: DEFAULT_LABEL.to_string(),
         size,
-        hash: String::new(),
-    };
-    json_raw.set_hash_from_bytes(hash);
+        hash: String::from_utf8_lossy(&hash).to_string(),
+    })?;
+    write_config(&config)?;
 
-    let string = serde_json::to_string_pretty(&json_raw)?;
+    apply(&config)
+}
 
-    fs::write(defs::DYNAMIC_MANAGER, string)?;
+pub fn set_apk(apk: &str) -> Result<()> {
+    add(DEFAULT_LABEL, apk)
+}
+
+/// Trust the signer of `apk` under `label`, next to the managers trusted already.
+pub fn add(label: &str, apk: &str) -> Result<()> {
+    ensure!(!label.is_empty(), "Label must not be empty");
+    let (size, hash) = apk_sign::get_apk_signature(apk)?;
+    parse_hash(&hash).map_err(|e| anyhow!("{e} from {apk}"))?;
+
+    let mut config = read_config()?;
+    config.upsert(Signature {
+        label: label.to_string(),
+        size,
+        hash,
+    })?;
+    write_config(&config)?;
+
+    apply(&config)?;
+    println!("trusted manager '{label}': size: {size:#x}");
+    Ok(())
+}
+
+pub fn remove(label: &str) -> Result<()> {
+    let mut config = read_config()?;
+    let before = config.managers.len();
+    config.managers.retain(|m| m.label != label);
+    if config.managers.len() == before {
+        bail!("No trusted manager labeled '{label}'");
+    }
+    write_config(&config)?;
 
-    ksucalls::dynamic_manager_set_synchronous(size, hash)?;
+    apply(&config)
+}
+
+pub fn list() -> Result<()> {
+    let config = read_config()?;
+    for manager in &config.managers {
+        println!(
+            "{}: size: {:#x}, hash: {}",
+            manager.label, manager.size, manager.hash
+        );
+    }
     Ok(())
 }
@@ -17,6 +17,11 @@ use crate::{
 };
 
 pub fn on_post_data_fs() -> Result<()> {
+    // as early as possible, the kernel log rotates
+    if let Err(e) = utils::save_lkm_status() {
+        warn!("save lkm status failed: {e:#}");
+    }
+
     if let Err(e) = ksucalls::ensure_uapi_version_matched() {
         error!("{e:#}, skip on_post_fs_data");
         return Ok(());
@@ -122,9 +127,9 @@ pub fn on_post_data_fs() -> Result<()> {
         warn!("load system.prop failed: {e}");
     }
 
-    // execute metamodule mount script
-    if let Err(e) = metamodule::exec_mount_script(module_dir) {
-        warn!("execute metamodule mount failed: {e}");
+    // mount modules with the selected engine, and the fallback if enabled
+    if let Err(e) = module::mount::mount_modules(module_dir) {
+        warn!("mount modules failed: {e:#}");
     }
 
     // Load umount config and apply to kernel
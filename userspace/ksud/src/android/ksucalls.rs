@@ -287,6 +287,22 @@ pub fn umount_list_add(path: &str, flags: u32) -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Add mount point to umount list, only applied to processes of `appid`
+pub fn umount_list_add_scoped(path: &str, flags: u32, appid: u32) -> anyhow::Result<()> {
+    let c_path = std::ffi::CString::new(path)?;
+    let mut entry = uapi::ksu_umount_scoped_entry {
+        path: c_path.as_ptr() as u64,
+        appid,
+    };
+    let mut cmd = uapi::ksu_manage_try_umount_cmd {
+        arg: &raw mut entry as u64,
+        flags,
+        mode: uapi::KSU_UMOUNT_ADD_SCOPED_RUST,
+    };
+    ksuctl(uapi::KSU_IOCTL_MANAGE_TRY_UMOUNT_RUST, &raw mut cmd)?;
+    Ok(())
+}
+
 /// Delete mount point from umount list
 pub fn umount_list_del(path: &str) -> anyhow::Result<()> {
     let c_path = std::ffi::CString::new(path)?;
@@ -341,6 +357,26 @@ pub fn dynamic_manager_set_synchronous(size: u32, hash: [u8; 64]) -> anyhow::Res
     Ok(())
 }
 
+pub fn dynamic_manager_add(size: u32, hash: [u8; 64]) -> anyhow::Result<()> {
+    let mut cmd = uapi::ksu_dynamic_manager_cmd {
+        operation: uapi::DYNAMIC_MANAGER_OP_ADD_RUST,
+        size,
+        hash,
+    };
+    ksuctl(uapi::KSU_IOCTL_DYNAMIC_MANAGER_RUST, &raw mut cmd)?;
+    Ok(())
+}
+
+pub fn dynamic_manager_add_synchronous(size: u32, hash: [u8; 64]) -> anyhow::Result<()> {
+    let mut cmd = uapi::ksu_dynamic_manager_cmd {
+        operation: uapi::DYNAMIC_MANAGER_OP_ADD_SYNCHRONOUS_RUST,
+        size,
+        hash,
+    };
+    ksuctl(uapi::KSU_IOCTL_DYNAMIC_MANAGER_RUST, &raw mut cmd)?;
+    Ok(())
+}
+
 pub fn dynamic_manager_get() -> anyhow::Result<(u32, [u8; 64])> {
     let mut cmd = uapi::ksu_dynamic_manager_cmd {
         operation: uapi::DYNAMIC_MANAGER_OP_GET_RUST,
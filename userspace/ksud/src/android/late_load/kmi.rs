@@ -0,0 +1,172 @@
+use std::{borrow::Cow, path::Path};
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+
+use crate::{assets, boot_patch};
+
+/// `android<release>-<major>.<minor>`, e.g. `android14-6.1`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct Kmi {
+    android: Option<u32>,
+    kernel: (u32, u32),
+}
+
+impl Kmi {
+    fn parse(kmi: &str) -> Option<Self> {
+        let (android, kernel) = kmi.split_once('-')?;
+        let android = android.strip_prefix("android")?.parse().ok()?;
+        let (major, minor) = kernel.split_once('.')?;
+        let minor = minor
+            .split(|c: char| !c.is_ascii_digit())
+            .next()?
+            .parse()
+            .ok()?;
+        Some(Self {
+            android: Some(android),
+            kernel: (major.parse().ok()?, minor),
+        })
+    }
+
+    // vendor-suffixed releases may not yield a KMI, the kernel version is always there and
+    // the release usually still names the android branch somewhere
+    fn running(kmi: Option<&str>) -> Result<Self> {
+        if let Some(kmi) = kmi.and_then(Self::parse) {
+            return Ok(kmi);
+        }
+        let (major, minor, _) = boot_patch::get_kernel_version()?;
+        let uname = rustix::system::uname();
+        Ok(Self {
+            android: android_release(&uname.release().to_string_lossy()),
+            kernel: (major.try_into()?, minor.try_into()?),
+        })
+    }
+
+    // GKI modules only work within one android release and kernel branch, an unknown
+    // release is left to the symbol checks
+    fn accepts(self, candidate: Self) -> bool {
+        self.kernel == candidate.kernel
+            && self
+                .android
+                .is_none_or(|android| candidate.android == Some(android))
+    }
+}
+
+/// `14` from `6.1.75-android14-11-g1234-ab5678-vendor`
+fn android_release(release: &str) -> Option<u32> {
+    release.match_indices("android").find_map(|(i, word)| {
+        let rest = &release[i + word.len()..];
+        let end = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        rest[..end].parse().ok()
+    })
+}
+
+/// Embedded KMIs of the running release and kernel branch.
+fn rank_embedded(kmi: Option<&str>) -> Result<Vec<String>> {
+    let running = Kmi::running(kmi)?;
+    info!("Looking for modules matching {running:?}");
+    let mut candidates: Vec<String> = assets::list_supported_kmi()
+        .into_iter()
+        .filter(|name| Kmi::parse(name).is_some_and(|kmi| running.accepts(kmi)))
+        .collect();
+    candidates.sort();
+    Ok(candidates)
+}
+
+/// Pick the kernelsu.ko to load: `module` if given, else the embedded one for `kmi`, else an
+/// embedded one of the same release and kernel branch whose symbols and vermagic fit the
+/// running kernel.
+/// Without a detected `kmi` only the running kernel is looked at.
+pub fn select_module(
+    kmi: Option<&str>,
+    module: Option<&Path>,
+) -> Result<(String, Cow<'static, [u8]>)> {
+    if let Some(module) = module {
+        let data = std::fs::read(module)
+            .with_context(|| format!("Failed to read {}", module.display()))?;
+        return Ok((module.display().to_string(), Cow::Owned(data)));
+    }
+
+    if let Some(kmi) = kmi {
+        let ko_name = format!("{kmi}_kernelsu.ko");
+        if let Ok(data) = assets::get_asset(&ko_name) {
+            return Ok((ko_name, data));
+        }
+        warn!("No kernelsu.ko for KMI {kmi}, looking for a compatible one");
+    }
+
+    for candidate in rank_embedded(kmi)? {
+        let ko_name = format!("{candidate}_kernelsu.ko");
+        let data = assets::get_asset(&ko_name)?;
+        let check = match ksuinit::check_module(&data) {
+            Ok(check) => check,
+            Err(e) => {
+                warn!("Cannot check {ko_name}: {e:#}");
+                continue;
+            }
+        };
+        if !check.is_loadable() {
+            info!(
+                "{ko_name} doesn't fit: {} missing symbols, vermagic {:?} for {}",
+                check.missing().count(),
+                check.vermagic,
+                check.kernel_release
+            );
+            continue;
+        }
+        return Ok((ko_name, data));
+    }
+    match kmi {
+        Some(kmi) => bail!("No embedded kernelsu.ko is compatible with KMI {kmi}"),
+        None => bail!("No embedded kernelsu.ko is compatible with the running kernel"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_kmi() {
+        assert_eq!(
+            Kmi::parse("android14-6.1"),
+            Some(Kmi {
+                android: Some(14),
+                kernel: (6, 1)
+            })
+        );
+        assert_eq!(Kmi::parse("6.1"), None);
+        assert_eq!(Kmi::parse("android-6.1"), None);
+    }
+
+    #[test]
+    fn release_of_vendor_kernels() {
+        assert_eq!(
+            android_release("6.1.75-android14-11-g1234-ab5678"),
+            Some(14)
+        );
+        assert_eq!(
+            android_release("5.10.198-android12-9-00001-vendor"),
+            Some(12)
+        );
+        assert_eq!(android_release("5.15.0-qgki-android"), None);
+        assert_eq!(android_release("6.6.30-perf"), None);
+    }
+
+    #[test]
+    fn same_release_and_branch_only() {
+        let running = Kmi::parse("android14-6.1").unwrap();
+        assert!(running.accepts(Kmi::parse("android14-6.1").unwrap()));
+        assert!(!running.accepts(Kmi::parse("android15-6.1").unwrap()));
+        assert!(!running.accepts(Kmi::parse("android14-5.15").unwrap()));
+
+        let unknown = Kmi {
+            android: None,
+            kernel: (6, 1),
+        };
+        assert!(unknown.accepts(Kmi::parse("android14-6.1").unwrap()));
+        assert!(!unknown.accepts(Kmi::parse("android15-6.6").unwrap()));
+    }
+}
@@ -1,6 +1,8 @@
+mod kmi;
 pub mod magica;
+mod steps;
 
-use std::process::Command;
+use std::{path::Path, process::Command};
 
 use anyhow::{Context, Result};
 use log::{info, warn};
@@ -9,12 +11,15 @@ use rustix::cstr;
 use crate::{
     android::{
         dynamic_manager, init_event,
-        module::{handle_updated_modules, metamodule, prune_modules},
+        module::{self, handle_updated_modules, prune_modules},
         restorecon, utils,
     },
-    assets, defs,
+    defs,
 };
 
+use steps::Progress;
+pub use steps::{Step, parse_step, print_status};
+
 fn dump_process_info(label: &str) {
     use rustix::process::{getgid, getgroups, getpid, getuid};
 
@@ -44,29 +49,49 @@ fn dump_process_info(label: &str) {
     );
 }
 
-pub fn run(package_name: &String, kmi: Option<String>, allow_shell: bool) -> Result<()> {
+pub fn run(
+    package_name: &String,
+    kmi: Option<String>,
+    allow_shell: bool,
+    module: Option<&Path>,
+    resume_from: Option<Step>,
+    skip: &[Step],
+) -> Result<()> {
     utils::daemonize(false)?;
     info!("late-load command triggered!");
+    // why ksuinit didn't load it at boot
+    if let Err(e) = utils::save_lkm_status() {
+        warn!("save lkm status failed: {e:#}");
+    }
     dump_process_info("late-load start");
 
-    // 1. Check if KernelSU is already loaded
-    if ksuinit::has_kernelsu() {
-        info!("KernelSU already loaded, skip loading ko");
-    } else {
-        // 2. Detect current KMI version
-        let kmi = kmi.map_or_else(
-            || crate::boot_patch::get_current_kmi().context("Failed to detect current KMI version"),
-            Ok,
-        )?;
-        info!("Detected KMI: {kmi}");
-
-        // 3. Get kernelsu.ko from embedded assets
-        let ko_name = format!("{kmi}_kernelsu.ko");
-        let ko_data = assets::get_asset(&ko_name)
-            .with_context(|| format!("Failed to get {ko_name} from assets"))?;
-
-        // 4. Load kernelsu.ko from memory with manual relocation
-        info!("Loading kernelsu.ko for KMI {kmi}...");
+    let mut progress = Progress::new(resume_from, skip);
+
+    progress.run(Step::LoadModule, || {
+        // Check if KernelSU is already loaded
+        if ksuinit::has_kernelsu() {
+            info!("KernelSU already loaded, skip loading ko");
+            return Ok(());
+        }
+
+        // Detect current KMI version, not needed for a user supplied module
+        // vendor-suffixed kernels may not expose one, the embedded modules are ranked then
+        let kmi = match (kmi, module) {
+            (Some(kmi), _) => Some(kmi),
+            (None, Some(_)) => None,
+            (None, None) => crate::boot_patch::get_current_kmi()
+                .inspect_err(|e| warn!("Failed to detect current KMI version: {e:#}"))
+                .ok(),
+        };
+        if let Some(kmi) = &kmi {
+            info!("Detected KMI: {kmi}");
+        }
+
+        // Get kernelsu.ko from the user or embedded assets
+        let (ko_name, ko_data) = kmi::select_module(kmi.as_deref(), module)?;
+
+        // Load kernelsu.ko from memory with manual relocation
+        info!("Loading {ko_name}...");
         let params = if allow_shell {
             cstr!("allow_shell=1")
         } else {
@@ -75,7 +100,8 @@ pub fn run(package_name: &String, kmi: Option<String>, allow_shell: bool) -> Res
         ksuinit::load_module(&ko_data, params).context("Failed to load kernelsu.ko")?;
         info!("kernelsu.ko loaded successfully!");
         dump_process_info("after load_module");
-    }
+        Ok(())
+    })?;
 
     // We need to reset stdin/stdout/stderr; otherwise, sending file descriptors via cmd transactions
     // will be blocked by SELinux because its fsec->sid is still u:r:su:s0 instead of u:r:ksu:s0.
@@ -83,73 +109,70 @@ pub fn run(package_name: &String, kmi: Option<String>, allow_shell: bool) -> Res
 
     utils::umask(0);
 
-    if let Err(e) = crate::android::module::module_config::clear_all_temp_configs() {
-        warn!("clear temp configs failed: {e}");
-    }
-
-    utils::install(None).context("Failed to install ksud")?;
-
-    // 5. Handle module updates
-    if let Err(e) = handle_updated_modules() {
-        warn!("handle updated modules failed: {e}");
-    }
-
-    if let Err(e) = prune_modules() {
-        warn!("prune modules failed: {e}");
-    }
-
-    if let Err(e) = restorecon::restorecon() {
-        warn!("restorecon failed: {e}");
-    }
-
-    // 6. Load SELinux rules
-    if crate::android::module::load_sepolicy_rule().is_err() {
-        warn!("load sepolicy.rule failed");
-    }
-
-    if let Err(e) = crate::android::profile::apply_sepolies() {
-        warn!("apply root profile sepolicy failed: {e}");
-    }
-
-    // 7. Initialize features
-    if let Err(e) = crate::android::feature::init_features() {
-        warn!("init features failed: {e}");
-    }
-
-    // 8. Execute late-load stage scripts (blocking)
-    init_event::run_stage("late-load", true);
-
-    // 9. Load system.prop
-    if let Err(e) = crate::android::module::load_system_prop() {
-        warn!("load system.prop failed: {e}");
-    }
-
-    // 10. Execute metamodule mount script (OverlayFS)
-    if let Err(e) = metamodule::exec_mount_script(defs::MODULE_DIR) {
-        warn!("execute metamodule mount failed: {e}");
-    }
-    // 11. Execute dynamic manager booted load
-    if let Err(e) = dynamic_manager::booted_load() {
-        warn!("set dynamic manager failed: {e}");
-    }
-
-    // 12. Execute post-mount stage scripts (blocking)
-    init_event::run_stage("post-mount", true);
-
-    // 13. Execute service stage scripts (non-blocking)
-    init_event::run_stage("service", false);
-
-    // 14. Execute boot-completed stage scripts (non-blocking)
-    init_event::run_stage("boot-completed", false);
-
-    // 15. Restart Manager so it gets a fresh ksu fd from the newly loaded kernel module
-    info!("Restarting KernelSU Manager {package_name}...");
-    let _ = Command::new("am")
-        .args(["force-stop", package_name])
-        .status();
-    let _ = Command::new("am")
-        .args(["start", "-n", &format!("{package_name}/.ui.MainActivity")])
-        .status();
-
+    progress.run(
+        Step::ClearTempConfigs,
+        crate::android::module::module_config::clear_all_temp_configs,
+    )?;
+
+    progress.run(Step::Install, || {
+        utils::install(None).context("Failed to install ksud")
+    })?;
+
+    progress.run(Step::ModuleUpdates, handle_updated_modules)?;
+    progress.run(Step::PruneModules, prune_modules)?;
+    progress.run(Step::Restorecon, restorecon::restorecon)?;
+
+    // Load SELinux rules
+    progress.run(Step::Sepolicy, crate::android::module::load_sepolicy_rule)?;
+    progress.run(
+        Step::ProfileSepolicy,
+        crate::android::profile::apply_sepolies,
+    )?;
+
+    progress.run(Step::Features, crate::android::feature::init_features)?;
+
+    // Execute late-load stage scripts (blocking)
+    progress.run(Step::LateLoadStage, || {
+        init_event::run_stage("late-load", true);
+        Ok(())
+    })?;
+
+    progress.run(Step::SystemProp, crate::android::module::load_system_prop)?;
+
+    // Execute metamodule mount script and check the result
+    progress.run(Step::Mount, || {
+        module::mount::mount_modules(defs::MODULE_DIR)
+    })?;
+    progress.run(Step::DynamicManager, dynamic_manager::booted_load)?;
+
+    // Execute post-mount stage scripts (blocking)
+    progress.run(Step::PostMountStage, || {
+        init_event::run_stage("post-mount", true);
+        Ok(())
+    })?;
+
+    // Execute service and boot-completed stage scripts (non-blocking)
+    progress.run(Step::ServiceStage, || {
+        init_event::run_stage("service", false);
+        Ok(())
+    })?;
+    progress.run(Step::BootCompletedStage, || {
+        init_event::run_stage("boot-completed", false);
+        Ok(())
+    })?;
+
+    // Restart Manager so it gets a fresh ksu fd from the newly loaded kernel module
+    progress.run(Step::RestartManager, || {
+        info!("Restarting KernelSU Manager {package_name}...");
+        let _ = Command::new("am")
+            .args(["force-stop", package_name])
+            .status();
+        let _ = Command::new("am")
+            .args(["start", "-n", &format!("{package_name}/.ui.MainActivity")])
+            .status();
+        Ok(())
+    })?;
+
+    progress.finish();
     Ok(())
 }
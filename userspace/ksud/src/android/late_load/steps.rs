@@ -0,0 +1,220 @@
+use std::fs;
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::defs;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Step {
+    LoadModule,
+    ClearTempConfigs,
+    Install,
+    ModuleUpdates,
+    PruneModules,
+    Restorecon,
+    Sepolicy,
+    ProfileSepolicy,
+    Features,
+    LateLoadStage,
+    SystemProp,
+    Mount,
+    DynamicManager,
+    PostMountStage,
+    ServiceStage,
+    BootCompletedStage,
+    RestartManager,
+}
+
+impl Step {
+    pub const ALL: [Self; 17] = [
+        Self::LoadModule,
+        Self::ClearTempConfigs,
+        Self::Install,
+        Self::ModuleUpdates,
+        Self::PruneModules,
+        Self::Restorecon,
+        Self::Sepolicy,
+        Self::ProfileSepolicy,
+        Self::Features,
+        Self::LateLoadStage,
+        Self::SystemProp,
+        Self::Mount,
+        Self::DynamicManager,
+        Self::PostMountStage,
+        Self::ServiceStage,
+        Self::BootCompletedStage,
+        Self::RestartManager,
+    ];
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::LoadModule => "load-module",
+            Self::ClearTempConfigs => "clear-temp-configs",
+            Self::Install => "install",
+            Self::ModuleUpdates => "module-updates",
+            Self::PruneModules => "prune-modules",
+            Self::Restorecon => "restorecon",
+            Self::Sepolicy => "sepolicy",
+            Self::ProfileSepolicy => "profile-sepolicy",
+            Self::Features => "features",
+            Self::LateLoadStage => "late-load-stage",
+            Self::SystemProp => "system-prop",
+            Self::Mount => "mount",
+            Self::DynamicManager => "dynamic-manager",
+            Self::PostMountStage => "post-mount-stage",
+            Self::ServiceStage => "service-stage",
+            Self::BootCompletedStage => "boot-completed-stage",
+            Self::RestartManager => "restart-manager",
+        }
+    }
+
+    // nothing after these can work if they fail
+    const fn is_fatal(self) -> bool {
+        matches!(self, Self::LoadModule | Self::Install)
+    }
+}
+
+pub fn parse_step(s: &str) -> Result<Step, String> {
+    Step::ALL
+        .into_iter()
+        .find(|step| step.name() == s)
+        .ok_or_else(|| {
+            let names: Vec<_> = Step::ALL.iter().map(|step| step.name()).collect();
+            format!("unknown step, expected one of: {}", names.join(", "))
+        })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum State {
+    Pending,
+    Running,
+    Ok,
+    Failed,
+    Skipped,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StepRecord {
+    name: String,
+    state: State,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Progress of the last late-load, kept in [`defs::LATE_LOAD_STATUS_PATH`].
+#[derive(Serialize, Deserialize)]
+pub struct Progress {
+    started: String,
+    #[serde(default)]
+    finished: Option<String>,
+    steps: Vec<StepRecord>,
+}
+
+impl Progress {
+    /// Start a run of every step from `resume_from` on, except those in `skip`.
+    pub fn new(resume_from: Option<Step>, skip: &[Step]) -> Self {
+        let first = resume_from.map_or(0, |from| {
+            Step::ALL.iter().position(|s| *s == from).unwrap_or(0)
+        });
+        let steps = Step::ALL
+            .iter()
+            .enumerate()
+            .map(|(index, step)| StepRecord {
+                name: step.name().to_string(),
+                state: if index < first || skip.contains(step) {
+                    State::Skipped
+                } else {
+                    State::Pending
+                },
+                error: None,
+            })
+            .collect();
+        Self {
+            started: chrono::Local::now().to_rfc3339(),
+            finished: None,
+            steps,
+        }
+    }
+
+    fn record(&mut self, step: Step) -> &mut StepRecord {
+        self.steps
+            .iter_mut()
+            .find(|r| r.name == step.name())
+            .expect("every step has a record")
+    }
+
+    /// Run `step` unless it is skipped. A failure is recorded and only returned if nothing
+    /// after the step can work without it.
+    pub fn run<F>(&mut self, step: Step, f: F) -> Result<()>
+    where
+        F: FnOnce() -> Result<()>,
+    {
+        if self.record(step).state == State::Skipped {
+            info!("late-load: skip {}", step.name());
+            return Ok(());
+        }
+
+        self.record(step).state = State::Running;
+        self.save();
+        let result = f();
+
+        let record = self.record(step);
+        match &result {
+            Ok(()) => record.state = State::Ok,
+            Err(e) => {
+                record.state = State::Failed;
+                record.error = Some(format!("{e:#}"));
+                warn!("late-load: {} failed: {e:#}", step.name());
+            }
+        }
+        self.save();
+
+        match result {
+            Err(e) if step.is_fatal() => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn finish(&mut self) {
+        self.finished = Some(chrono::Local::now().to_rfc3339());
+        self.save();
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(self)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(defs::LATE_LOAD_STATUS_PATH, json)?));
+        if let Err(e) = result {
+            warn!("save late-load status failed: {e:#}");
+        }
+    }
+}
+
+pub fn print_status() -> Result<()> {
+    let json = match fs::read_to_string(defs::LATE_LOAD_STATUS_PATH) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => bail!("late-load has not run yet"),
+        Err(e) => return Err(e).context("read late-load status"),
+    };
+    let progress: Progress = serde_json::from_str(&json).context("parse late-load status")?;
+
+    println!("started: {}", progress.started);
+    println!("finished: {}", progress.finished.as_deref().unwrap_or("no"));
+    for record in &progress.steps {
+        let state = match record.state {
+            State::Pending => "pending",
+            State::Running => "interrupted",
+            State::Ok => "ok",
+            State::Failed => "failed",
+            State::Skipped => "skipped",
+        };
+        match &record.error {
+            Some(error) => println!("{:<22} {state}: {error}", record.name),
+            None => println!("{:<22} {state}", record.name),
+        }
+    }
+    Ok(())
+}
@@ -1,3 +1,4 @@
+mod aid;
 pub mod cli;
 mod debug;
 mod dynamic_manager;
@@ -7,6 +8,7 @@ mod ksucalls;
 mod late_load;
 mod module;
 mod profile;
+mod pty;
 mod resetprop;
 mod restorecon;
 mod sepolicy;
@@ -0,0 +1,192 @@
+//! Config schema declared by a module in `config.schema.json`
+//!
+//! ```json
+//! {
+//!   "additionalProperties": false,
+//!   "properties": {
+//!     "mode": {
+//!       "type": "string",
+//!       "title": "Mode",
+//!       "description": "How aggressive to be",
+//!       "default": "normal",
+//!       "enum": ["quiet", "normal", "loud"]
+//!     },
+//!     "interval": { "type": "int", "minimum": 1, "maximum": 3600, "default": 60 }
+//!   }
+//! }
+//! ```
+
+use std::{collections::BTreeMap, fs, path::Path};
+
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    android::module::module_config::{ConfigValue, ValueType},
+    defs,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum SchemaType {
+    Bool,
+    Int,
+    String,
+    Json,
+}
+
+impl From<SchemaType> for ValueType {
+    fn from(value: SchemaType) -> Self {
+        match value {
+            SchemaType::Bool => Self::Bool,
+            SchemaType::Int => Self::Int,
+            SchemaType::String => Self::String,
+            SchemaType::Json => Self::Json,
+        }
+    }
+}
+
+/// Declaration of a single config key
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Property {
+    #[serde(rename = "type")]
+    value_type: SchemaType,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    description: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    default: Option<serde_json::Value>,
+    #[serde(default, rename = "enum", skip_serializing_if = "Option::is_none")]
+    allowed: Option<Vec<serde_json::Value>>,
+    /// Range of an int
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    minimum: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    maximum: Option<i64>,
+    /// Length range of a string
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    min_length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_length: Option<usize>,
+}
+
+impl Property {
+    pub fn value_type(&self) -> ValueType {
+        self.value_type.into()
+    }
+
+    /// The default as a config value
+    pub fn default_value(&self) -> Option<ConfigValue> {
+        ConfigValue::from_json(self.value_type(), self.default.as_ref()?).ok()
+    }
+
+    fn validate(&self, value: &ConfigValue) -> Result<()> {
+        if value.value_type() != self.value_type() {
+            bail!(
+                "expected type {}, got {}",
+                self.value_type().name(),
+                value.value_type().name()
+            );
+        }
+
+        if let Some(allowed) = &self.allowed
+            && !allowed.contains(&value.to_json())
+        {
+            let allowed: Vec<String> = allowed.iter().map(ToString::to_string).collect();
+            bail!("'{value}' is not one of {}", allowed.join(", "));
+        }
+
+        match value {
+            ConfigValue::Int(n) => {
+                if let Some(minimum) = self.minimum
+                    && *n < minimum
+                {
+                    bail!("{n} is less than the minimum {minimum}");
+                }
+                if let Some(maximum) = self.maximum
+                    && *n > maximum
+                {
+                    bail!("{n} is greater than the maximum {maximum}");
+                }
+            }
+            ConfigValue::String(s) => {
+                let len = s.chars().count();
+                if let Some(min_length) = self.min_length
+                    && len < min_length
+                {
+                    bail!("'{s}' is shorter than {min_length} characters");
+                }
+                if let Some(max_length) = self.max_length
+                    && len > max_length
+                {
+                    bail!("'{s}' is longer than {max_length} characters");
+                }
+            }
+            ConfigValue::Bool(_) | ConfigValue::Json(_) => {}
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Schema {
+    /// Whether keys not declared in `properties` may be set
+    #[serde(default = "default_additional_properties")]
+    additional_properties: bool,
+    #[serde(default)]
+    pub properties: BTreeMap<String, Property>,
+}
+
+const fn default_additional_properties() -> bool {
+    true
+}
+
+impl Schema {
+    /// Load the schema of the module in `module_dir`, `None` if it has none
+    pub fn load_from(module_dir: &Path) -> Result<Option<Self>> {
+        let path = module_dir.join(defs::MODULE_CONFIG_SCHEMA);
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {}", path.display())),
+        };
+        let schema: Self = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid config schema: {}", path.display()))?;
+        for (key, property) in &schema.properties {
+            if property.default.is_some() && property.default_value().is_none() {
+                bail!(
+                    "Invalid default for '{key}' in {}: not of type {}",
+                    path.display(),
+                    property.value_type().name()
+                );
+            }
+        }
+        Ok(Some(schema))
+    }
+
+    /// Load the schema of an installed module
+    pub fn load(module_id: &str) -> Result<Option<Self>> {
+        Self::load_from(&Path::new(defs::MODULE_DIR).join(module_id))
+    }
+
+    /// Check that `value` may be stored under `key`
+    pub fn validate(&self, key: &str, value: &ConfigValue) -> Result<()> {
+        match self.properties.get(key) {
+            Some(property) => property
+                .validate(value)
+                .with_context(|| format!("Invalid value for '{key}'")),
+            None if self.additional_properties => Ok(()),
+            None => bail!("Config key '{key}' is not declared in the module's config schema"),
+        }
+    }
+
+    /// Defaults of all keys that declare one
+    pub fn defaults(&self) -> impl Iterator<Item = (&str, ConfigValue)> {
+        self.properties
+            .iter()
+            .filter_map(|(key, property)| Some((key.as_str(), property.default_value()?)))
+    }
+}
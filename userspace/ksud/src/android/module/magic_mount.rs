@@ -0,0 +1,596 @@
+//! Built-in magic mount
+//!
+//! Merges the `system`, `vendor`, `product` and `system_ext` trees of modules onto the real
+//! partitions with bind mounts, the way Magisk does. A directory that has to gain, lose or
+//! change the type of an entry is rebuilt on a tmpfs: its real entries are mirrored there,
+//! the module's entries are added, and the result is bind mounted over the real directory.
+//!
+//! A directory containing `.replace` hides all of its real entries, a character device with
+//! device number 0:0 hides the real entry of the same name.
+//!
+//! Everything happens below a configurable root, so the engine can be tried on a fake root
+//! on a tmpfs before it touches the real partitions.
+
+use std::{
+    collections::BTreeMap,
+    ffi::{CString, OsStr, OsString},
+    fs::{self, File},
+    os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt, lchown, symlink},
+    path::{Path, PathBuf},
+    ptr,
+};
+
+use anyhow::{Context, Result, bail};
+use log::{debug, info, warn};
+
+use crate::{
+    android::{
+        ksucalls,
+        module::{metamodule, mount::needs_mount, read_module_prop},
+        restorecon::{lgetfilecon, lsetfilecon},
+    },
+    defs,
+};
+
+const REPLACE_FILE_NAME: &str = ".replace";
+
+// partitions a module can also ship at its top level instead of below `system`
+const PARTITIONS: &[&str] = &["vendor", "product", "system_ext"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum NodeType {
+    Directory,
+    File,
+    Symlink,
+    Whiteout,
+}
+
+impl NodeType {
+    fn of(metadata: &fs::Metadata) -> Option<Self> {
+        let file_type = metadata.file_type();
+        if file_type.is_dir() {
+            Some(Self::Directory)
+        } else if file_type.is_symlink() {
+            Some(Self::Symlink)
+        } else if file_type.is_char_device() && metadata.rdev() == 0 {
+            Some(Self::Whiteout)
+        } else if file_type.is_file() {
+            Some(Self::File)
+        } else {
+            None
+        }
+    }
+}
+
+/// One entry of the merged module tree.
+#[derive(Debug)]
+struct Node {
+    node_type: NodeType,
+    children: BTreeMap<OsString, Node>,
+    // the entry in the first module that provides it
+    source: Option<PathBuf>,
+    replace: bool,
+}
+
+impl Node {
+    fn new(node_type: NodeType) -> Self {
+        Self {
+            node_type,
+            children: BTreeMap::new(),
+            source: None,
+            replace: false,
+        }
+    }
+
+    /// Merge the module directory `dir` into this node. Entries of modules collected earlier
+    /// win over later ones, directories are merged.
+    fn collect(&mut self, dir: &Path) -> Result<()> {
+        if self.source.is_none() {
+            self.source = Some(dir.to_path_buf());
+        }
+        if dir.join(REPLACE_FILE_NAME).exists() {
+            self.replace = true;
+        }
+
+        for entry in fs::read_dir(dir).with_context(|| format!("read {}", dir.display()))? {
+            let entry = entry?;
+            let name = entry.file_name();
+            if name == REPLACE_FILE_NAME {
+                continue;
+            }
+            let path = entry.path();
+            let Some(node_type) = entry.metadata().ok().as_ref().and_then(NodeType::of) else {
+                warn!("magic mount: unsupported file type of {}", path.display());
+                continue;
+            };
+
+            let child = self
+                .children
+                .entry(name)
+                .or_insert_with(|| Node::new(node_type));
+            match (child.node_type, node_type) {
+                (NodeType::Directory, NodeType::Directory) => child.collect(&path)?,
+                _ if child.source.is_none() => child.source = Some(path),
+                _ => debug!("magic mount: {} is shadowed", path.display()),
+            }
+        }
+        Ok(())
+    }
+
+    fn is_empty(&self) -> bool {
+        self.children.is_empty() && !self.replace
+    }
+
+    /// Whether the parent directory, whose real entry for this node is `real`, has to be
+    /// rebuilt on a tmpfs to show this node.
+    fn needs_tmpfs(&self, real: &Path) -> bool {
+        match (self.node_type, fs::symlink_metadata(real)) {
+            (NodeType::Symlink, _) => true,
+            (NodeType::Whiteout, real) => real.is_ok(),
+            (_, Err(_)) => true,
+            (NodeType::Directory, Ok(real)) => !real.is_dir(),
+            (NodeType::File, Ok(real)) => real.is_dir() || real.file_type().is_symlink(),
+        }
+    }
+}
+
+fn mount(
+    source: Option<&Path>,
+    target: &Path,
+    fstype: Option<&str>,
+    flags: libc::c_ulong,
+    data: Option<&str>,
+) -> Result<()> {
+    let cstring = |s: &OsStr| CString::new(s.as_encoded_bytes());
+    let source = source.map(|s| cstring(s.as_os_str())).transpose()?;
+    let target_c = cstring(target.as_os_str())?;
+    let fstype = fstype.map(|s| cstring(OsStr::new(s))).transpose()?;
+    let data = data.map(|s| cstring(OsStr::new(s))).transpose()?;
+    let ret = unsafe {
+        libc::mount(
+            source.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            target_c.as_ptr(),
+            fstype.as_ref().map_or(ptr::null(), |s| s.as_ptr()),
+            flags,
+            data.as_ref().map_or(ptr::null(), |s| s.as_ptr().cast()),
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("mount {}", target.display()));
+    }
+    Ok(())
+}
+
+fn bind(source: &Path, target: &Path) -> Result<()> {
+    mount(
+        Some(source),
+        target,
+        None,
+        libc::MS_BIND | libc::MS_REC,
+        None,
+    )
+    .with_context(|| format!("bind {}", source.display()))
+}
+
+fn umount(target: &Path) -> Result<()> {
+    let target_c = CString::new(target.as_os_str().as_encoded_bytes())?;
+    if unsafe { libc::umount2(target_c.as_ptr(), libc::MNT_DETACH) } != 0 {
+        return Err(std::io::Error::last_os_error())
+            .with_context(|| format!("umount {}", target.display()));
+    }
+    Ok(())
+}
+
+/// Give `to` the mode, owner and SELinux context of `from`.
+fn copy_attributes(from: &Path, to: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if !metadata.file_type().is_symlink() {
+        fs::set_permissions(to, fs::Permissions::from_mode(metadata.mode() & 0o7777))?;
+    }
+    lchown(to, Some(metadata.uid()), Some(metadata.gid()))?;
+    // a fake root may live on a file system without SELinux labels
+    if let Ok(con) = lgetfilecon(from) {
+        lsetfilecon(to, &con)?;
+    }
+    Ok(())
+}
+
+fn clone_symlink(from: &Path, to: &Path) -> Result<()> {
+    let target = fs::read_link(from)?;
+    symlink(&target, to).with_context(|| format!("symlink {}", to.display()))?;
+    copy_attributes(from, to)
+}
+
+/// Make the real entry `real` appear at `work` inside a tmpfs.
+fn mirror(real: &Path, work: &Path) -> Result<()> {
+    let metadata = fs::symlink_metadata(real)?;
+    if metadata.file_type().is_symlink() {
+        clone_symlink(real, work)
+    } else if metadata.is_dir() {
+        fs::create_dir(work)?;
+        copy_attributes(real, work)?;
+        bind(real, work)
+    } else {
+        File::create(work)?;
+        copy_attributes(real, work)?;
+        bind(real, work)
+    }
+}
+
+pub struct MagicMount {
+    root: PathBuf,
+    work_dir: PathBuf,
+    register_umount: bool,
+}
+
+impl MagicMount {
+    /// Mount below `root`, using `work_dir` for the tmpfs. Mounts are only added to the
+    /// kernel umount list when `root` is `/`.
+    pub fn new(root: impl Into<PathBuf>, work_dir: impl Into<PathBuf>) -> Self {
+        let root = root.into();
+        Self {
+            register_umount: root == Path::new("/"),
+            root,
+            work_dir: work_dir.into(),
+        }
+    }
+
+    fn register(&self, target: &Path) {
+        if !self.register_umount {
+            return;
+        }
+        let target = target.to_string_lossy();
+        if let Err(e) = ksucalls::umount_list_add(&target, libc::MNT_DETACH as u32) {
+            warn!("magic mount: register {target} for umount failed: {e}");
+        }
+    }
+
+    fn work_path(&self, real: &Path) -> Result<PathBuf> {
+        Ok(self.work_dir.join(real.strip_prefix(&self.root)?))
+    }
+
+    /// Show `node` at `real`. `work` is where to create it if the parent is rebuilt on the
+    /// tmpfs.
+    fn mount_node(&self, node: &Node, real: &Path, work: Option<&Path>) -> Result<()> {
+        let source = node.source.as_deref();
+        match (node.node_type, work) {
+            (NodeType::File, Some(work)) => {
+                let source = source.context("file without source")?;
+                File::create(work)?;
+                bind(source, work)
+            }
+            (NodeType::File, None) => {
+                let source = source.context("file without source")?;
+                bind(source, real)?;
+                self.register(real);
+                Ok(())
+            }
+            (NodeType::Symlink, Some(work)) => {
+                clone_symlink(source.context("symlink without source")?, work)
+            }
+            (NodeType::Symlink, None) => bail!("symlink {} needs a tmpfs", real.display()),
+            // nothing to do: left out of the tmpfs, or there's no real entry to hide
+            (NodeType::Whiteout, _) => Ok(()),
+            (NodeType::Directory, work) => self.mount_dir(node, real, work),
+        }
+    }
+
+    fn mount_dir(&self, node: &Node, real: &Path, work: Option<&Path>) -> Result<()> {
+        let use_tmpfs = work.is_some()
+            || node.replace
+            || node
+                .children
+                .iter()
+                .any(|(name, child)| child.needs_tmpfs(&real.join(name)));
+        if !use_tmpfs {
+            for (name, child) in &node.children {
+                let real = real.join(name);
+                if let Err(e) = self.mount_node(child, &real, None) {
+                    warn!("magic mount: {} failed: {e:#}", real.display());
+                }
+            }
+            return Ok(());
+        }
+
+        let real_is_dir = real.is_dir();
+        let own_tmpfs = work.is_none();
+        let work = match work {
+            Some(work) => {
+                fs::create_dir(work)?;
+                work.to_path_buf()
+            }
+            None => {
+                if !real_is_dir {
+                    bail!("{} is not a directory", real.display());
+                }
+                let work = self.work_path(real)?;
+                fs::create_dir_all(&work)?;
+                work
+            }
+        };
+        // new directories take the attributes of the module's directory
+        let template = if real_is_dir {
+            real
+        } else {
+            node.source.as_deref().context("directory without source")?
+        };
+        copy_attributes(template, &work)?;
+
+        if real_is_dir && !node.replace {
+            for entry in fs::read_dir(real)? {
+                let name = entry?.file_name();
+                if !node.children.contains_key(&name) {
+                    mirror(&real.join(&name), &work.join(&name))?;
+                }
+            }
+        }
+        for (name, child) in &node.children {
+            self.mount_node(child, &real.join(name), Some(&work.join(name)))
+                .with_context(|| format!("mount {}", real.join(name).display()))?;
+        }
+
+        // only the directory the tmpfs was started at is mounted onto the real tree
+        if own_tmpfs {
+            bind(&work, real)?;
+            mount(
+                None,
+                real,
+                None,
+                libc::MS_BIND | libc::MS_REMOUNT | libc::MS_RDONLY,
+                None,
+            )?;
+            self.register(real);
+        }
+        Ok(())
+    }
+
+    /// Merge the trees of `modules`, earlier modules taking precedence, onto the root.
+    pub fn mount(&self, modules: &[PathBuf]) -> Result<()> {
+        let mut system = Node::new(NodeType::Directory);
+        for module in modules {
+            let dir = module.join("system");
+            if dir.is_dir() {
+                system.collect(&dir)?;
+            }
+            for partition in PARTITIONS {
+                let dir = module.join(partition);
+                if dir.is_dir() {
+                    system
+                        .children
+                        .entry(partition.into())
+                        .or_insert_with(|| Node::new(NodeType::Directory))
+                        .collect(&dir)?;
+                }
+            }
+        }
+
+        // on system-as-root devices the partitions are links below /system, mount them at
+        // their real mount points instead
+        let mut targets = Vec::new();
+        for partition in PARTITIONS {
+            let real = self.root.join(partition);
+            let in_system = self.root.join("system").join(partition);
+            if real.is_dir()
+                && !fs::symlink_metadata(&in_system).is_ok_and(|m| m.is_dir())
+                && let Some(node) = system.children.remove(OsStr::new(partition))
+                && node.node_type == NodeType::Directory
+            {
+                targets.push((real, node));
+            }
+        }
+        targets.push((self.root.join("system"), system));
+        targets.retain(|(_, node)| !node.is_empty());
+        if targets.is_empty() {
+            info!("magic mount: nothing to mount");
+            return Ok(());
+        }
+
+        fs::create_dir_all(&self.work_dir)?;
+        mount(
+            Some(Path::new("KSU")),
+            &self.work_dir,
+            Some("tmpfs"),
+            0,
+            Some("mode=0755"),
+        )?;
+        for (real, node) in &targets {
+            match self.mount_node(node, real, None) {
+                Ok(()) => info!("magic mount: {}", real.display()),
+                Err(e) => warn!("magic mount: {} failed: {e:#}", real.display()),
+            }
+        }
+        // the bind mounts keep the tmpfs alive
+        umount(&self.work_dir)?;
+        let _ = fs::remove_dir(&self.work_dir);
+        Ok(())
+    }
+}
+
+/// Enabled modules in `module_dir` that want to be mounted, in mount order.
+pub fn modules_in(module_dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut modules = Vec::new();
+    for entry in fs::read_dir(module_dir)? {
+        let module = entry?.path();
+        if !needs_mount(&module)
+            || [defs::DISABLE_FILE_NAME, defs::REMOVE_FILE_NAME]
+                .iter()
+                .any(|name| module.join(name).exists())
+        {
+            continue;
+        }
+        if read_module_prop(&module).is_ok_and(|props| metamodule::is_metamodule(&props)) {
+            continue;
+        }
+        modules.push(module);
+    }
+    modules.sort();
+    Ok(modules)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    use super::*;
+
+    /// A tmpfs with a fake root, modules and a work dir, detached when dropped.
+    struct FakeRoot {
+        base: PathBuf,
+    }
+
+    impl FakeRoot {
+        fn new() -> Self {
+            static COUNT: AtomicU32 = AtomicU32::new(0);
+            let base = std::env::temp_dir().join(format!(
+                "ksud_magic_mount_{}_{}",
+                std::process::id(),
+                COUNT.fetch_add(1, Ordering::Relaxed)
+            ));
+            fs::create_dir_all(&base).unwrap();
+            mount(Some(Path::new("tmpfs")), &base, Some("tmpfs"), 0, None)
+                .expect("mounting a tmpfs needs root");
+            // keep our mounts from propagating to the host
+            mount(None, &base, None, libc::MS_PRIVATE | libc::MS_REC, None).unwrap();
+            let root = Self { base };
+            for dir in ["root/system/etc", "root/system/app/Old", "root/vendor/lib"] {
+                root.mkdir(dir);
+            }
+            root.write("root/system/etc/hosts", "real hosts");
+            root.write("root/system/etc/gone", "real gone");
+            root.write("root/system/app/Old/old.apk", "real apk");
+            root.write("root/vendor/lib/libv.so", "real libv");
+            symlink("../vendor", root.path("root/system/vendor")).unwrap();
+            root
+        }
+
+        fn path(&self, rel: &str) -> PathBuf {
+            self.base.join(rel)
+        }
+
+        fn mkdir(&self, rel: &str) {
+            fs::create_dir_all(self.path(rel)).unwrap();
+        }
+
+        fn write(&self, rel: &str, content: &str) {
+            let path = self.path(rel);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+
+        fn read(&self, rel: &str) -> String {
+            fs::read_to_string(self.path(rel)).unwrap()
+        }
+
+        fn mount(&self, modules: &[&str]) {
+            let modules: Vec<PathBuf> = modules
+                .iter()
+                .map(|m| self.path(&format!("modules/{m}")))
+                .collect();
+            MagicMount::new(self.path("root"), self.path("work"))
+                .mount(&modules)
+                .unwrap();
+        }
+    }
+
+    impl Drop for FakeRoot {
+        fn drop(&mut self) {
+            let _ = umount(&self.base);
+            let _ = fs::remove_dir(&self.base);
+        }
+    }
+
+    #[test]
+    #[ignore = "needs root to mount, run with --ignored"]
+    fn added_file() {
+        let root = FakeRoot::new();
+        root.write("modules/a/system/etc/new.conf", "new");
+        root.write("modules/a/system/bin/tool", "tool");
+        root.mount(&["a"]);
+
+        assert_eq!(root.read("root/system/etc/new.conf"), "new");
+        assert_eq!(root.read("root/system/bin/tool"), "tool");
+        // real entries next to the added ones stay visible
+        assert_eq!(root.read("root/system/etc/hosts"), "real hosts");
+        assert!(root.path("root/system/vendor").is_symlink());
+        assert!(!root.path("work").exists());
+    }
+
+    #[test]
+    #[ignore = "needs root to mount, run with --ignored"]
+    fn replaced_file() {
+        let root = FakeRoot::new();
+        root.write("modules/a/system/etc/hosts", "hosts of a");
+        root.write("modules/b/system/etc/hosts", "hosts of b");
+        root.mount(&["a", "b"]);
+
+        // earlier modules win
+        assert_eq!(root.read("root/system/etc/hosts"), "hosts of a");
+        assert_eq!(root.read("root/system/etc/gone"), "real gone");
+    }
+
+    #[test]
+    #[ignore = "needs root to mount, run with --ignored"]
+    fn replace_dir() {
+        let root = FakeRoot::new();
+        root.write("modules/a/system/app/Old/.replace", "");
+        root.write("modules/a/system/app/Old/new.apk", "new apk");
+        root.mount(&["a"]);
+
+        let names: Vec<_> = fs::read_dir(root.path("root/system/app/Old"))
+            .unwrap()
+            .map(|e| e.unwrap().file_name())
+            .collect();
+        assert_eq!(names, [OsString::from("new.apk")]);
+        assert_eq!(root.read("root/system/app/Old/new.apk"), "new apk");
+    }
+
+    #[test]
+    #[ignore = "needs root to mount, run with --ignored"]
+    fn whiteout() {
+        let root = FakeRoot::new();
+        root.mkdir("modules/a/system/etc");
+        let whiteout = CString::new(
+            root.path("modules/a/system/etc/gone")
+                .into_os_string()
+                .into_encoded_bytes(),
+        )
+        .unwrap();
+        assert_eq!(
+            unsafe { libc::mknod(whiteout.as_ptr(), libc::S_IFCHR | 0o644, 0) },
+            0
+        );
+        root.mount(&["a"]);
+
+        assert!(!root.path("root/system/etc/gone").exists());
+        assert_eq!(root.read("root/system/etc/hosts"), "real hosts");
+    }
+
+    #[test]
+    #[ignore = "needs root to mount, run with --ignored"]
+    fn symlink_entry() {
+        let root = FakeRoot::new();
+        root.mkdir("modules/a/system/etc");
+        symlink("hosts", root.path("modules/a/system/etc/hosts.link")).unwrap();
+        root.mount(&["a"]);
+
+        let link = root.path("root/system/etc/hosts.link");
+        assert_eq!(fs::read_link(&link).unwrap(), Path::new("hosts"));
+        assert_eq!(fs::read_to_string(link).unwrap(), "real hosts");
+    }
+
+    #[test]
+    #[ignore = "needs root to mount, run with --ignored"]
+    fn top_level_vendor() {
+        let root = FakeRoot::new();
+        root.write("modules/a/vendor/lib/libv.so", "libv of a");
+        root.write("modules/a/vendor/lib/liba.so", "liba");
+        root.mount(&["a"]);
+
+        // mounted at /vendor, /system/vendor stays the link to it
+        assert!(root.path("root/system/vendor").is_symlink());
+        assert_eq!(root.read("root/vendor/lib/libv.so"), "libv of a");
+        assert_eq!(root.read("root/vendor/lib/liba.so"), "liba");
+        assert_eq!(root.read("root/system/vendor/lib/liba.so"), "liba");
+    }
+}
@@ -228,45 +228,79 @@ fn check_metamodule_script(script_name: &str) -> Option<PathBuf> {
     Some(script_path)
 }
 
-/// Execute metamodule's metauninstall.sh for a specific module
-pub fn exec_metauninstall_script(module_id: &str) -> Result<()> {
-    let Some(metauninstall_path) = check_metamodule_script(defs::METAMODULE_METAUNINSTALL_SCRIPT)
-    else {
+// with the metamodule debug flag, keep the script's output in `{log_prefix}.{out,err}.log`
+fn redirect_debug_logs(command: &mut Command, log_prefix: &str) -> Result<()> {
+    if fs::exists(defs::METAMODULE_DEBUG)? {
+        command
+            .stdout(File::create(format!("{log_prefix}.out.log"))?)
+            .stderr(File::create(format!("{log_prefix}.err.log"))?);
+    }
+    Ok(())
+}
+
+fn exec_module_hook(script_name: &str, log_prefix: &str, module_id: &str) -> Result<()> {
+    let Some(script_path) = check_metamodule_script(script_name) else {
         return Ok(());
     };
 
-    info!("Executing metamodule metauninstall.sh for module: {module_id}");
+    info!("Executing metamodule {script_name} for module: {module_id}");
 
     let mut command = Command::new(assets::BUSYBOX_PATH);
     command
-        .args(["sh", metauninstall_path.to_str().unwrap()])
-        .current_dir(metauninstall_path.parent().unwrap())
+        .args(["sh", script_path.to_str().unwrap()])
+        .current_dir(script_path.parent().unwrap())
         .envs(crate::android::module::get_common_script_envs(
             get_metamodule_id().as_deref(),
         ))
         .env("MODULE_ID", module_id);
-    if fs::exists(defs::METAMODULE_DEBUG)? {
-        command
-            .stdout(File::open(format!(
-                "{}.out.log",
-                defs::METAMODULE_METAUNINSTALL_SCRIPT_LOG
-            ))?)
-            .stderr(File::open(format!(
-                "{}.err.log",
-                defs::METAMODULE_METAUNINSTALL_SCRIPT_LOG
-            ))?);
-    }
+    redirect_debug_logs(&mut command, log_prefix)?;
     let result = command.status()?;
 
     ensure!(
         result.success(),
-        "Metamodule metauninstall.sh failed for module {module_id}",
+        "Metamodule {script_name} failed for module {module_id}",
     );
 
-    info!("Metamodule metauninstall.sh executed successfully for {module_id}");
+    info!("Metamodule {script_name} executed successfully for {module_id}");
     Ok(())
 }
 
+/// Execute metamodule's metauninstall.sh for a specific module
+pub fn exec_metauninstall_script(module_id: &str) -> Result<()> {
+    exec_module_hook(
+        defs::METAMODULE_METAUNINSTALL_SCRIPT,
+        defs::METAMODULE_METAUNINSTALL_SCRIPT_LOG,
+        module_id,
+    )
+}
+
+/// Execute metamodule's metaenable.sh after a module was enabled
+pub fn exec_metaenable_script(module_id: &str) -> Result<()> {
+    exec_module_hook(
+        defs::METAMODULE_METAENABLE_SCRIPT,
+        defs::METAMODULE_METAENABLE_SCRIPT_LOG,
+        module_id,
+    )
+}
+
+/// Execute metamodule's metadisable.sh after a module was disabled
+pub fn exec_metadisable_script(module_id: &str) -> Result<()> {
+    exec_module_hook(
+        defs::METAMODULE_METADISABLE_SCRIPT,
+        defs::METAMODULE_METADISABLE_SCRIPT_LOG,
+        module_id,
+    )
+}
+
+/// Execute metamodule's metaaction.sh after a module's action.sh was run
+pub fn exec_metaaction_script(module_id: &str) -> Result<()> {
+    exec_module_hook(
+        defs::METAMODULE_METAACTION_SCRIPT,
+        defs::METAMODULE_METAACTION_SCRIPT_LOG,
+        module_id,
+    )
+}
+
 /// Execute metamodule mount script
 pub fn exec_mount_script(module_dir: &str) -> Result<()> {
     let Some(mount_script) = check_metamodule_script(defs::METAMODULE_MOUNT_SCRIPT) else {
@@ -283,17 +317,7 @@ pub fn exec_mount_script(module_dir: &str) -> Result<()> {
         ))
         .env("MODULE_DIR", module_dir);
 
-    if fs::exists(defs::METAMODULE_DEBUG)? {
-        command
-            .stdout(File::open(format!(
-                "{}.out.log",
-                defs::METAMODULE_MOUNT_SCRIPT_LOG
-            ))?)
-            .stderr(File::open(format!(
-                "{}.err.log",
-                defs::METAMODULE_MOUNT_SCRIPT_LOG
-            ))?);
-    }
+    redirect_debug_logs(&mut command, defs::METAMODULE_MOUNT_SCRIPT_LOG)?;
     let result = command.status()?;
 
     ensure!(result.success(), "Metamodule mount script failed");
@@ -1,5 +1,8 @@
+pub mod config_schema;
+pub mod magic_mount;
 pub mod metamodule;
 pub mod module_config;
+pub mod mount;
 
 #[cfg(unix)]
 use std::os::unix::{prelude::PermissionsExt, process::CommandExt};
@@ -296,6 +299,10 @@ pub fn exec_common_scripts(dir: &str, wait: bool) -> Result<()> {
 }
 
 pub fn load_system_prop() -> Result<()> {
+    if let Err(e) = crate::android::resetprop::save_boot_snapshot() {
+        warn!("save property snapshot failed: {e}");
+    }
+
     foreach_active_module(|module| {
         let system_prop = module.join("system.prop");
         if !system_prop.exists() {
@@ -311,6 +318,45 @@ pub fn load_system_prop() -> Result<()> {
     Ok(())
 }
 
+/// Undo what boot did for the active modules, for unloading KernelSU without a reboot:
+/// restore the properties their system.prop changed. The modules themselves are kept, so
+/// their uninstall hooks only run with `run_uninstall`.
+pub fn revert_active_modules(run_uninstall: bool) -> Result<()> {
+    let mut system_props = Vec::new();
+    foreach_active_module(|module| {
+        let system_prop = module.join("system.prop");
+        if system_prop.exists() {
+            system_props.push(system_prop);
+        }
+        Ok(())
+    })?;
+    if !system_props.is_empty()
+        && let Err(e) = crate::android::resetprop::restore_boot_snapshot(&system_props)
+    {
+        warn!("restore module properties failed: {e}");
+    }
+
+    if !run_uninstall {
+        return Ok(());
+    }
+    foreach_active_module(|module| {
+        let module_id = module.file_name().and_then(|n| n.to_str()).unwrap_or("");
+        let is_metamodule =
+            read_module_prop(module).is_ok_and(|props| metamodule::is_metamodule(&props));
+        if !is_metamodule && let Err(e) = metamodule::exec_metauninstall_script(module_id) {
+            warn!("Failed to exec metamodule uninstall for {module_id}: {e}");
+        }
+
+        let uninstaller = module.join("uninstall.sh");
+        if uninstaller.exists()
+            && let Err(e) = exec_script(uninstaller, true)
+        {
+            warn!("Failed to exec uninstaller of {module_id}: {e}");
+        }
+        Ok(())
+    })
+}
+
 pub fn prune_modules() -> Result<()> {
     foreach_module(All, |module| {
         if !module.join(defs::REMOVE_FILE_NAME).exists() {
@@ -727,7 +773,21 @@ pub fn run_action(id: &str) -> Result<()> {
 
     let action_script_path = format!("/data/adb/modules/{id}/action.sh");
 
-    exec_script(&action_script_path, true)
+    exec_script(&action_script_path, true)?;
+
+    let module_path = Path::new(defs::MODULE_DIR).join(id);
+    if !is_metamodule_path(&module_path)
+        && let Err(e) = metamodule::exec_metaaction_script(id)
+    {
+        warn!("Failed to exec metamodule action hook for {id}: {e}");
+    }
+
+    Ok(())
+}
+
+// the metamodule's hooks are about regular modules
+fn is_metamodule_path(module_path: &Path) -> bool {
+    read_module_prop(module_path).is_ok_and(|props| metamodule::is_metamodule(&props))
 }
 
 pub fn enable_module(id: &str) -> Result<()> {
@@ -744,6 +804,12 @@ pub fn enable_module(id: &str) -> Result<()> {
         info!("Module {id} enabled");
     }
 
+    if !is_metamodule_path(&module_path)
+        && let Err(e) = metamodule::exec_metaenable_script(id)
+    {
+        warn!("Failed to exec metamodule enable hook for {id}: {e}");
+    }
+
     if let Err(e) = regenerate_preinit_rc() {
         warn!("regenerate preinit rc failed: {e}");
     }
@@ -760,6 +826,12 @@ pub fn disable_module(id: &str) -> Result<()> {
 
     info!("Module {id} disabled");
 
+    if !is_metamodule_path(&module_path)
+        && let Err(e) = metamodule::exec_metadisable_script(id)
+    {
+        warn!("Failed to exec metamodule disable hook for {id}: {e}");
+    }
+
     if let Err(e) = regenerate_preinit_rc() {
         warn!("regenerate preinit rc failed: {e}");
     }
@@ -886,6 +958,7 @@ fn list_module(path: &str) -> Vec<HashMap<String, String>> {
     };
 
     let mut modules: Vec<HashMap<String, String>> = Vec::new();
+    let mount_status = mount::read_status();
 
     for entry in dir.flatten() {
         let path = entry.path();
@@ -920,7 +993,7 @@ fn list_module(path: &str) -> Vec<HashMap<String, String>> {
         let remove = path.join(defs::REMOVE_FILE_NAME).exists();
         let web = path.join(defs::MODULE_WEB_DIR).exists();
         let action = path.join(defs::MODULE_ACTION_SH).exists();
-        let need_mount = path.join("system").exists() && !path.join("skip_mount").exists();
+        let need_mount = mount::needs_mount(&path);
 
         module_prop_map.insert("enabled".to_owned(), enabled.to_string());
         module_prop_map.insert("update".to_owned(), update.to_string());
@@ -928,6 +1001,26 @@ fn list_module(path: &str) -> Vec<HashMap<String, String>> {
         module_prop_map.insert("web".to_owned(), web.to_string());
         module_prop_map.insert("action".to_owned(), action.to_string());
         module_prop_map.insert("mount".to_owned(), need_mount.to_string());
+        if need_mount
+            && enabled
+            && let Some(status) = entry
+                .file_name()
+                .to_str()
+                .and_then(|id| mount_status.get(id))
+        {
+            module_prop_map.insert("mountStatus".to_owned(), status.as_str().to_owned());
+        }
+
+        match config_schema::Schema::load_from(&path) {
+            Ok(Some(schema)) => match serde_json::to_string(&schema) {
+                Ok(schema) => {
+                    module_prop_map.insert("configSchema".to_owned(), schema);
+                }
+                Err(e) => warn!("Failed to serialize config schema: {e}"),
+            },
+            Ok(None) => {}
+            Err(e) => warn!("{e:#}"),
+        }
 
         resolve_module_icon_path(&mut module_prop_map, "actionIcon", &path);
         resolve_module_icon_path(&mut module_prop_map, "webuiIcon", &path);
@@ -1,26 +1,44 @@
 use std::{
-    collections::HashMap,
-    fs::{self, File},
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{self, File, OpenOptions},
     io::{Read, Write},
+    os::fd::AsRawFd,
     path::{Path, PathBuf},
+    str::FromStr,
 };
 
 use anyhow::{Context, Result, bail};
-use log::{debug, warn};
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
 
 use crate::{
-    android::{module, utils::ensure_dir_exists},
+    android::{
+        module::{self, config_schema::Schema},
+        utils::ensure_dir_exists,
+    },
     defs,
 };
 
 #[allow(clippy::unreadable_literal)]
 const MODULE_CONFIG_MAGIC: u32 = 0x4b53554d; // "KSUM"
-const MODULE_CONFIG_VERSION: u32 = 1;
+// v1: untyped string values, v2: every value carries a type tag
+const MODULE_CONFIG_VERSION_V1: u32 = 1;
+const MODULE_CONFIG_VERSION: u32 = 2;
 
 // Validation limits
 pub const MAX_CONFIG_KEY_LEN: usize = 256;
 pub const MAX_CONFIG_VALUE_LEN: usize = 1024 * 1024; // 1MB
-pub const MAX_CONFIG_COUNT: usize = 32;
+
+// Default quota of a config file, a module can change it in module.prop
+pub const DEFAULT_MAX_CONFIG_COUNT: usize = 256;
+pub const DEFAULT_MAX_CONFIG_SIZE: usize = 4 * 1024 * 1024; // 4MB
+// Upper bounds for quotas requested by modules
+const MAX_CONFIG_COUNT_LIMIT: usize = 4096;
+const MAX_CONFIG_SIZE_LIMIT: usize = 16 * 1024 * 1024; // 16MB
+
+const QUOTA_COUNT_PROP: &str = "configMaxEntries";
+const QUOTA_SIZE_PROP: &str = "configMaxSize";
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ConfigType {
@@ -44,6 +62,151 @@ pub fn parse_bool_config(value: &str) -> bool {
     trimmed.eq_ignore_ascii_case("true") || trimmed == "1"
 }
 
+/// Type of a config value
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ValueType {
+    Bool,
+    Int,
+    #[default]
+    String,
+    Json,
+}
+
+impl ValueType {
+    const fn tag(self) -> u8 {
+        match self {
+            Self::Bool => 0,
+            Self::Int => 1,
+            Self::String => 2,
+            Self::Json => 3,
+        }
+    }
+
+    fn from_tag(tag: u8) -> Result<Self> {
+        Ok(match tag {
+            0 => Self::Bool,
+            1 => Self::Int,
+            2 => Self::String,
+            3 => Self::Json,
+            _ => bail!("Unknown config value type: {tag}"),
+        })
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            Self::Bool => "bool",
+            Self::Int => "int",
+            Self::String => "string",
+            Self::Json => "json",
+        }
+    }
+}
+
+impl FromStr for ValueType {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "bool" => Self::Bool,
+            "int" => Self::Int,
+            "string" => Self::String,
+            "json" => Self::Json,
+            _ => bail!("Unknown config value type: '{s}'. Expected bool, int, string or json"),
+        })
+    }
+}
+
+/// A typed config value
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigValue {
+    Bool(bool),
+    Int(i64),
+    String(String),
+    Json(serde_json::Value),
+}
+
+impl ConfigValue {
+    /// Parse the text form of a value of type `value_type`
+    pub fn parse(value_type: ValueType, value: &str) -> Result<Self> {
+        Ok(match value_type {
+            ValueType::Bool => match value.trim() {
+                v if v.eq_ignore_ascii_case("true") || v == "1" => Self::Bool(true),
+                v if v.eq_ignore_ascii_case("false") || v == "0" => Self::Bool(false),
+                _ => bail!("Invalid bool value: '{value}'. Expected true, false, 1 or 0"),
+            },
+            ValueType::Int => Self::Int(
+                value
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("Invalid int value: '{value}'"))?,
+            ),
+            ValueType::String => Self::String(value.to_string()),
+            ValueType::Json => Self::Json(
+                serde_json::from_str(value)
+                    .with_context(|| format!("Invalid json value: '{value}'"))?,
+            ),
+        })
+    }
+
+    pub const fn value_type(&self) -> ValueType {
+        match self {
+            Self::Bool(_) => ValueType::Bool,
+            Self::Int(_) => ValueType::Int,
+            Self::String(_) => ValueType::String,
+            Self::Json(_) => ValueType::Json,
+        }
+    }
+
+    /// Convert a JSON value to a value of type `value_type`, strings are parsed from their
+    /// text form
+    pub fn from_json(value_type: ValueType, value: &serde_json::Value) -> Result<Self> {
+        match value {
+            serde_json::Value::String(s) if value_type != ValueType::Json => {
+                Self::parse(value_type, s)
+            }
+            value => Self::parse(value_type, &value.to_string()),
+        }
+    }
+
+    /// Whether the text form `value` parsed as this value's type is equal to it, so json
+    /// `{"a": 1}` matches `{"a":1}` and int `007` matches `7`
+    pub fn matches(&self, value: &str) -> bool {
+        Self::parse(self.value_type(), value).is_ok_and(|value| value == *self)
+    }
+
+    /// The value as JSON, for machine readable output
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Self::Bool(v) => (*v).into(),
+            Self::Int(v) => (*v).into(),
+            Self::String(v) => v.clone().into(),
+            Self::Json(v) => v.clone(),
+        }
+    }
+}
+
+/// The text form, which is also how values are stored
+impl fmt::Display for ConfigValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bool(v) => write!(f, "{v}"),
+            Self::Int(v) => write!(f, "{v}"),
+            Self::String(v) => f.write_str(v),
+            Self::Json(v) => write!(f, "{v}"),
+        }
+    }
+}
+
+/// Expected current value for a compare-and-set
+#[derive(Debug, Clone, Copy)]
+pub enum Expected<'a> {
+    /// The key must not exist
+    Missing,
+    /// The key must exist with this value, in text form, compared as the type of the stored
+    /// value
+    Value(&'a str),
+}
+
 /// Validate config key
 /// Uses the same validation rules as module_id: ^[a-zA-Z][a-zA-Z0-9._-]+$
 /// - Must start with a letter (a-zA-Z)
@@ -86,15 +249,52 @@ pub fn validate_config_value(value: &str) -> Result<()> {
     Ok(())
 }
 
-/// Validate config count
-fn validate_config_count(config: &HashMap<String, String>) -> Result<()> {
-    if config.len() > MAX_CONFIG_COUNT {
-        bail!(
-            "Too many config entries: {} (max: {MAX_CONFIG_COUNT})",
-            config.len(),
-        );
+/// Limits of a single config file of a module
+#[derive(Debug, Clone, Copy)]
+pub struct Quota {
+    pub max_count: usize,
+    pub max_size: usize,
+}
+
+impl Quota {
+    /// Quota of `module_id`, raised or lowered by `configMaxEntries` and `configMaxSize`
+    /// in its module.prop, up to a hard limit
+    pub fn for_module(module_id: &str) -> Self {
+        let mut quota = Self {
+            max_count: DEFAULT_MAX_CONFIG_COUNT,
+            max_size: DEFAULT_MAX_CONFIG_SIZE,
+        };
+        let Ok(props) = module::read_module_prop(&Path::new(defs::MODULE_DIR).join(module_id))
+        else {
+            return quota;
+        };
+        let read = |key: &str| props.get(key).and_then(|v| v.trim().parse::<usize>().ok());
+        if let Some(count) = read(QUOTA_COUNT_PROP) {
+            quota.max_count = count.min(MAX_CONFIG_COUNT_LIMIT);
+        }
+        if let Some(size) = read(QUOTA_SIZE_PROP) {
+            quota.max_size = size.min(MAX_CONFIG_SIZE_LIMIT);
+        }
+        quota
+    }
+
+    fn check(self, config: &HashMap<String, ConfigValue>) -> Result<()> {
+        if config.len() > self.max_count {
+            bail!(
+                "Too many config entries: {} (max: {})",
+                config.len(),
+                self.max_count
+            );
+        }
+        let size: usize = config
+            .iter()
+            .map(|(key, value)| key.len() + value.to_string().len())
+            .sum();
+        if size > self.max_size {
+            bail!("Config too large: {size} bytes (max: {})", self.max_size);
+        }
+        Ok(())
     }
-    Ok(())
 }
 
 /// Get the config directory path for a module
@@ -107,6 +307,39 @@ fn get_config_path(module_id: &str, config_type: ConfigType) -> PathBuf {
     get_config_dir(module_id).join(config_type.filename())
 }
 
+/// Get the change stamp file path for a module
+pub fn get_stamp_path(module_id: &str) -> PathBuf {
+    get_config_dir(module_id).join(defs::CONFIG_STAMP_NAME)
+}
+
+/// Bump the change stamp of a module
+/// The file is rewritten in place, so an inotify watch on it sees IN_CLOSE_WRITE
+fn bump_stamp(module_id: &str) -> Result<()> {
+    let path = get_stamp_path(module_id);
+    let generation = fs::read_to_string(&path)
+        .ok()
+        .and_then(|s| s.trim().parse::<u64>().ok())
+        .unwrap_or(0);
+    fs::write(&path, format!("{}\n", generation.wrapping_add(1)))
+        .with_context(|| format!("Failed to write change stamp: {}", path.display()))
+}
+
+/// Take the lock serializing changes to a module's configs, released when dropped
+fn lock_config(module_id: &str) -> Result<File> {
+    let dir = ensure_config_dir(module_id)?;
+    let lock_path = dir.join(".lock");
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open lock file: {}", lock_path.display()))?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } != 0 {
+        return Err(std::io::Error::last_os_error()).context("Failed to lock config");
+    }
+    Ok(file)
+}
+
 /// Ensure the config directory exists
 fn ensure_config_dir(module_id: &str) -> Result<PathBuf> {
     let dir = get_config_dir(module_id);
@@ -114,8 +347,24 @@ fn ensure_config_dir(module_id: &str) -> Result<PathBuf> {
     Ok(dir)
 }
 
-/// Load config from binary file
-pub fn load_config(module_id: &str, config_type: ConfigType) -> Result<HashMap<String, String>> {
+fn read_u32(file: &mut File, what: impl FnOnce() -> String) -> Result<u32> {
+    let mut buf = [0u8; 4];
+    file.read_exact(&mut buf).with_context(what)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_string(file: &mut File, len: usize, what: impl Fn() -> String) -> Result<String> {
+    let mut buf = vec![0u8; len];
+    file.read_exact(&mut buf).with_context(&what)?;
+    String::from_utf8(buf).with_context(|| format!("Invalid UTF-8 in {}", what()))
+}
+
+/// Load typed config from binary file
+/// Version 1 files are read with every value as a string
+pub fn load_typed_config(
+    module_id: &str,
+    config_type: ConfigType,
+) -> Result<HashMap<String, ConfigValue>> {
     module::validate_module_id(module_id)?;
 
     let config_path = get_config_path(module_id, config_type);
@@ -129,59 +378,46 @@ pub fn load_config(module_id: &str, config_type: ConfigType) -> Result<HashMap<S
         .with_context(|| format!("Failed to open config file: {}", config_path.display()))?;
 
     // Read magic
-    let mut magic_buf = [0u8; 4];
-    file.read_exact(&mut magic_buf)
-        .with_context(|| "Failed to read magic")?;
-    let magic = u32::from_le_bytes(magic_buf);
-
+    let magic = read_u32(&mut file, || "Failed to read magic".to_string())?;
     if magic != MODULE_CONFIG_MAGIC {
         bail!("Invalid config magic: expected 0x{MODULE_CONFIG_MAGIC:08x}, got 0x{magic:08x}");
     }
 
     // Read version
-    let mut version_buf = [0u8; 4];
-    file.read_exact(&mut version_buf)
-        .with_context(|| "Failed to read version")?;
-    let version = u32::from_le_bytes(version_buf);
-
-    if version != MODULE_CONFIG_VERSION {
+    let version = read_u32(&mut file, || "Failed to read version".to_string())?;
+    if version != MODULE_CONFIG_VERSION && version != MODULE_CONFIG_VERSION_V1 {
         bail!("Unsupported config version: expected {MODULE_CONFIG_VERSION}, got {version}");
     }
 
     // Read count
-    let mut count_buf = [0u8; 4];
-    file.read_exact(&mut count_buf)
-        .with_context(|| "Failed to read count")?;
-    let count = u32::from_le_bytes(count_buf);
+    let count = read_u32(&mut file, || "Failed to read count".to_string())?;
 
     // Read entries
     let mut config = HashMap::new();
     for i in 0..count {
-        // Read key length
-        let mut key_len_buf = [0u8; 4];
-        file.read_exact(&mut key_len_buf)
-            .with_context(|| format!("Failed to read key length for entry {i}"))?;
-        let key_len = u32::from_le_bytes(key_len_buf) as usize;
-
-        // Read key data
-        let mut key_buf = vec![0u8; key_len];
-        file.read_exact(&mut key_buf)
-            .with_context(|| format!("Failed to read key data for entry {i}"))?;
-        let key = String::from_utf8(key_buf)
-            .with_context(|| format!("Invalid UTF-8 in key for entry {i}"))?;
-
-        // Read value length
-        let mut value_len_buf = [0u8; 4];
-        file.read_exact(&mut value_len_buf)
-            .with_context(|| format!("Failed to read value length for entry {i}"))?;
-        let value_len = u32::from_le_bytes(value_len_buf) as usize;
-
-        // Read value data
-        let mut value_buf = vec![0u8; value_len];
-        file.read_exact(&mut value_buf)
-            .with_context(|| format!("Failed to read value data for entry {i}"))?;
-        let value = String::from_utf8(value_buf)
-            .with_context(|| format!("Invalid UTF-8 in value for entry {i}"))?;
+        // Read key
+        let key_len = read_u32(&mut file, || {
+            format!("Failed to read key length for entry {i}")
+        })? as usize;
+        let key = read_string(&mut file, key_len, || format!("key data for entry {i}"))?;
+
+        // Read value type, v1 only has strings
+        let value_type = if version == MODULE_CONFIG_VERSION_V1 {
+            ValueType::String
+        } else {
+            let mut tag = [0u8; 1];
+            file.read_exact(&mut tag)
+                .with_context(|| format!("Failed to read value type for entry {i}"))?;
+            ValueType::from_tag(tag[0])?
+        };
+
+        // Read value
+        let value_len = read_u32(&mut file, || {
+            format!("Failed to read value length for entry {i}")
+        })? as usize;
+        let value = read_string(&mut file, value_len, || format!("value data for entry {i}"))?;
+        let value = ConfigValue::parse(value_type, &value)
+            .with_context(|| format!("Invalid value for entry {i}"))?;
 
         config.insert(key, value);
     }
@@ -194,21 +430,29 @@ pub fn load_config(module_id: &str, config_type: ConfigType) -> Result<HashMap<S
     Ok(config)
 }
 
-/// Save config to binary file
+/// Load config from binary file, with values in text form
+pub fn load_config(module_id: &str, config_type: ConfigType) -> Result<HashMap<String, String>> {
+    Ok(load_typed_config(module_id, config_type)?
+        .into_iter()
+        .map(|(key, value)| (key, value.to_string()))
+        .collect())
+}
+
+/// Save config to binary file and bump the change stamp
 pub fn save_config(
     module_id: &str,
     config_type: ConfigType,
-    config: &HashMap<String, String>,
+    config: &HashMap<String, ConfigValue>,
 ) -> Result<()> {
     module::validate_module_id(module_id)?;
 
-    // Validate config count
-    validate_config_count(config)?;
+    // Validate against the module's quota
+    Quota::for_module(module_id).check(config)?;
 
     // Validate all keys and values
     for (key, value) in config {
         validate_config_key(key).with_context(|| format!("Invalid config key: '{key}'"))?;
-        validate_config_value(value)
+        validate_config_value(&value.to_string())
             .with_context(|| format!("Invalid config value for key '{key}'"))?;
     }
 
@@ -246,8 +490,13 @@ pub fn save_config(
         file.write_all(key_bytes)
             .with_context(|| format!("Failed to write key data for '{key}'"))?;
 
+        // Write value type
+        file.write_all(&[value.value_type().tag()])
+            .with_context(|| format!("Failed to write value type for '{key}'"))?;
+
         // Write value length
-        let value_bytes = value.as_bytes();
+        let value_text = value.to_string();
+        let value_bytes = value_text.as_bytes();
         let value_len = value_bytes.len() as u32;
         file.write_all(&value_len.to_le_bytes())
             .with_context(|| format!("Failed to write value length for '{key}'"))?;
@@ -274,6 +523,10 @@ pub fn save_config(
         config.len(),
         config_path.display()
     );
+
+    if let Err(e) = bump_stamp(module_id) {
+        warn!("{e:#}");
+    }
     Ok(())
 }
 
@@ -288,19 +541,54 @@ pub fn get_config_value(
     Ok(config.get(key).cloned())
 }
 
-/// Set a single config value
+/// Set a single config value as a string
 pub fn set_config_value(
     module_id: &str,
     key: &str,
     value: &str,
     config_type: ConfigType,
+) -> Result<()> {
+    compare_and_set(
+        module_id,
+        key,
+        ConfigValue::String(value.to_string()),
+        config_type,
+        None,
+    )
+}
+
+/// Set a single typed config value
+/// The value must fit the module's config schema, if it has one
+/// With `expected`, the value is only set if the current one matches, atomically with
+/// respect to other config changes of the module
+pub fn compare_and_set(
+    module_id: &str,
+    key: &str,
+    value: ConfigValue,
+    config_type: ConfigType,
+    expected: Option<Expected>,
 ) -> Result<()> {
     // Validate input early for better error messages
     validate_config_key(key)?;
-    validate_config_value(value)?;
+    validate_config_value(&value.to_string())?;
+    module::validate_module_id(module_id)?;
+    if let Some(schema) = Schema::load(module_id)? {
+        schema.validate(key, &value)?;
+    }
 
-    let mut config = load_config(module_id, config_type)?;
-    config.insert(key.to_string(), value.to_string());
+    let _lock = lock_config(module_id)?;
+    let mut config = load_typed_config(module_id, config_type)?;
+
+    match (expected, config.get(key)) {
+        (None, _) | (Some(Expected::Missing), None) => {}
+        (Some(Expected::Value(expected)), Some(current)) if current.matches(expected) => {}
+        (Some(_), Some(current)) => {
+            bail!("Compare-and-set failed: '{key}' is '{current}'")
+        }
+        (Some(_), None) => bail!("Compare-and-set failed: '{key}' is not set"),
+    }
+
+    config.insert(key.to_string(), value);
 
     // Note: save_config will also validate, but this provides earlier feedback
     save_config(module_id, config_type, &config)?;
@@ -309,7 +597,9 @@ pub fn set_config_value(
 
 /// Delete a single config value
 pub fn delete_config_value(module_id: &str, key: &str, config_type: ConfigType) -> Result<()> {
-    let mut config = load_config(module_id, config_type)?;
+    module::validate_module_id(module_id)?;
+    let _lock = lock_config(module_id)?;
+    let mut config = load_typed_config(module_id, config_type)?;
 
     if config.remove(key).is_none() {
         bail!("Key '{key}' not found in config");
@@ -321,22 +611,25 @@ pub fn delete_config_value(module_id: &str, key: &str, config_type: ConfigType)
 
 /// Clear all config values
 pub fn clear_config(module_id: &str, config_type: ConfigType) -> Result<()> {
+    module::validate_module_id(module_id)?;
     let config_path = get_config_path(module_id, config_type);
 
     if config_path.exists() {
+        let _lock = lock_config(module_id)?;
         fs::remove_file(&config_path)
             .with_context(|| format!("Failed to remove config file: {}", config_path.display()))?;
         debug!("Cleared config: {}", config_path.display());
+        bump_stamp(module_id)?;
     }
 
     Ok(())
 }
 
-/// Merge persist and temp configs (temp takes priority)
-pub fn merge_configs(module_id: &str) -> Result<HashMap<String, String>> {
+/// Merge typed persist and temp configs (temp takes priority)
+pub fn merge_typed_configs(module_id: &str) -> Result<HashMap<String, ConfigValue>> {
     module::validate_module_id(module_id)?;
 
-    let mut merged = match load_config(module_id, ConfigType::Persist) {
+    let mut merged = match load_typed_config(module_id, ConfigType::Persist) {
         Ok(config) => config,
         Err(e) => {
             warn!("Failed to load persist config for module '{module_id}': {e}");
@@ -344,7 +637,7 @@ pub fn merge_configs(module_id: &str) -> Result<HashMap<String, String>> {
         }
     };
 
-    let temp = match load_config(module_id, ConfigType::Temp) {
+    let temp = match load_typed_config(module_id, ConfigType::Temp) {
         Ok(config) => config,
         Err(e) => {
             warn!("Failed to load temp config for module '{module_id}': {e}");
@@ -360,9 +653,29 @@ pub fn merge_configs(module_id: &str) -> Result<HashMap<String, String>> {
     Ok(merged)
 }
 
-/// Get all module configs (for iteration)
-/// Loads all configs in a single pass to minimize I/O overhead
-pub fn get_all_module_configs() -> Result<HashMap<String, HashMap<String, String>>> {
+/// Merge persist and temp configs (temp takes priority), with values in text form
+pub fn merge_configs(module_id: &str) -> Result<HashMap<String, String>> {
+    Ok(merge_typed_configs(module_id)?
+        .into_iter()
+        .map(|(key, value)| (key, value.to_string()))
+        .collect())
+}
+
+/// Merged configs with the defaults of the module's config schema filled in
+pub fn merge_configs_with_defaults(module_id: &str) -> Result<HashMap<String, ConfigValue>> {
+    let mut merged = merge_typed_configs(module_id)?;
+    if let Some(schema) = Schema::load(module_id)? {
+        for (key, value) in schema.defaults() {
+            merged.entry(key.to_string()).or_insert(value);
+        }
+    }
+    Ok(merged)
+}
+
+/// Load the configs of every module with `load`, skipping empty ones
+fn load_all_configs<T>(
+    load: impl Fn(&str) -> Result<HashMap<String, T>>,
+) -> Result<HashMap<String, HashMap<String, T>>> {
     let config_root = Path::new(defs::MODULE_CONFIG_DIR);
 
     if !config_root.exists() {
@@ -382,7 +695,7 @@ pub fn get_all_module_configs() -> Result<HashMap<String, HashMap<String, String
         }
 
         if let Some(module_id) = path.file_name().and_then(|n| n.to_str()) {
-            match merge_configs(module_id) {
+            match load(module_id) {
                 Ok(config) => {
                     if !config.is_empty() {
                         all_configs.insert(module_id.to_string(), config);
@@ -399,6 +712,157 @@ pub fn get_all_module_configs() -> Result<HashMap<String, HashMap<String, String
     Ok(all_configs)
 }
 
+/// Get all module configs (for iteration)
+/// Loads all configs in a single pass to minimize I/O overhead
+pub fn get_all_module_configs() -> Result<HashMap<String, HashMap<String, String>>> {
+    load_all_configs(merge_configs)
+}
+
+/// Get the typed persist configs of all modules
+pub fn get_all_persist_configs() -> Result<HashMap<String, HashMap<String, ConfigValue>>> {
+    load_all_configs(|module_id| load_typed_config(module_id, ConfigType::Persist))
+}
+
+const CONFIG_EXPORT_VERSION: u32 = 1;
+
+/// A typed value in a config export
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedValue {
+    #[serde(rename = "type")]
+    value_type: String,
+    value: serde_json::Value,
+}
+
+/// Portable document of persist configs, keyed by module id and config key
+#[derive(Debug, Serialize, Deserialize)]
+struct ConfigExport {
+    version: u32,
+    modules: BTreeMap<String, BTreeMap<String, ExportedValue>>,
+}
+
+/// How `import_configs` treats the existing configs of an imported module
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ImportMode {
+    /// Keep keys that are not in the document
+    #[default]
+    Merge,
+    /// Drop keys that are not in the document
+    Replace,
+}
+
+impl FromStr for ImportMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        Ok(match s {
+            "merge" => Self::Merge,
+            "replace" => Self::Replace,
+            _ => bail!("Unknown import mode: '{s}'. Expected merge or replace"),
+        })
+    }
+}
+
+/// Export the persist configs of `module_id`, or of all modules, as JSON
+/// Temp configs are device state and never exported
+pub fn export_configs(module_id: Option<&str>) -> Result<String> {
+    let configs = match module_id {
+        Some(module_id) => {
+            let config = load_typed_config(module_id, ConfigType::Persist)?;
+            HashMap::from([(module_id.to_string(), config)])
+        }
+        None => get_all_persist_configs()?,
+    };
+
+    let modules = configs
+        .into_iter()
+        .map(|(module_id, config)| {
+            let config = config
+                .into_iter()
+                .map(|(key, value)| {
+                    let exported = ExportedValue {
+                        value_type: value.value_type().name().to_string(),
+                        value: value.to_json(),
+                    };
+                    (key, exported)
+                })
+                .collect();
+            (module_id, config)
+        })
+        .collect();
+    let export = ConfigExport {
+        version: CONFIG_EXPORT_VERSION,
+        modules,
+    };
+    Ok(serde_json::to_string_pretty(&export)?)
+}
+
+fn merge_import(
+    module_id: &str,
+    config: HashMap<String, ConfigValue>,
+    mode: ImportMode,
+) -> Result<HashMap<String, ConfigValue>> {
+    Ok(match mode {
+        ImportMode::Replace => config,
+        ImportMode::Merge => {
+            let mut merged = load_typed_config(module_id, ConfigType::Persist)?;
+            merged.extend(config);
+            merged
+        }
+    })
+}
+
+/// Import persist configs from a document written by `export_configs`
+/// The whole document is validated before anything is written
+pub fn import_configs(json: &str, mode: ImportMode) -> Result<()> {
+    let export: ConfigExport = serde_json::from_str(json).context("Invalid config export")?;
+    if export.version != CONFIG_EXPORT_VERSION {
+        bail!(
+            "Unsupported config export version: expected {CONFIG_EXPORT_VERSION}, got {}",
+            export.version
+        );
+    }
+
+    let mut imports = Vec::new();
+    for (module_id, entries) in export.modules {
+        module::validate_module_id(&module_id)?;
+        let schema = Schema::load(&module_id)?;
+
+        let mut config = HashMap::new();
+        for (key, exported) in entries {
+            validate_config_key(&key).with_context(|| format!("Module '{module_id}'"))?;
+            let value = ConfigValue::from_json(exported.value_type.parse()?, &exported.value)
+                .with_context(|| format!("Invalid value for '{key}' of module '{module_id}'"))?;
+            validate_config_value(&value.to_string())
+                .with_context(|| format!("Invalid value for '{key}' of module '{module_id}'"))?;
+            if let Some(schema) = &schema {
+                schema
+                    .validate(&key, &value)
+                    .with_context(|| format!("Module '{module_id}'"))?;
+            }
+            config.insert(key, value);
+        }
+        // what would be written, so a module over its quota fails before any write
+        let config = merge_import(&module_id, config, mode)?;
+        Quota::for_module(&module_id)
+            .check(&config)
+            .with_context(|| format!("Module '{module_id}'"))?;
+        imports.push((module_id, config));
+    }
+
+    for (module_id, config) in imports {
+        let _lock = lock_config(&module_id)?;
+        // merged again under the lock, in case the config changed since
+        let config = merge_import(&module_id, config, mode)?;
+        save_config(&module_id, ConfigType::Persist, &config)
+            .with_context(|| format!("Failed to import config of module '{module_id}'"))?;
+        info!(
+            "Imported {} config entries of module '{module_id}'",
+            config.len()
+        );
+    }
+    Ok(())
+}
+
 /// Clear all temporary configs (called during post-fs-data)
 pub fn clear_all_temp_configs() -> Result<()> {
     let config_root = Path::new(defs::MODULE_CONFIG_DIR);
@@ -459,3 +923,20 @@ pub fn clear_module_configs(module_id: &str) -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_by_type() {
+        let json = ConfigValue::parse(ValueType::Json, r#"{"a": 1}"#).unwrap();
+        assert!(json.matches(r#"{"a":1}"#));
+        assert!(!json.matches(r#"{"a":2}"#));
+        assert!(!json.matches("not json"));
+
+        assert!(ConfigValue::Int(7).matches(" 007"));
+        assert!(ConfigValue::Bool(true).matches("1"));
+        assert!(!ConfigValue::String("a ".to_string()).matches("a"));
+    }
+}
@@ -0,0 +1,402 @@
+//! Module mounting and mount health
+//!
+//! Modules are mounted by the metamodule's mount script, or by the built-in magic mount if
+//! that is the selected engine. Afterwards check that the files of every module that wants
+//! to be mounted are really visible, record the result for `module list`, and optionally
+//! mount the missing ones with a built-in overlayfs fallback.
+//!
+//! The fallback only takes modules none of whose files are visible. A partially mounted
+//! module is left as the engine mounted it and reported, since an overlay on top could hide
+//! what the engine did mount.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::CString,
+    fs,
+    os::unix::fs::{FileTypeExt, MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    android::{
+        ksucalls,
+        module::{ModuleType::Active, foreach_module, magic_mount::MagicMount, metamodule},
+        utils,
+    },
+    defs,
+};
+
+// how many files of a module are compared against the real partitions
+const SAMPLE_FILES: usize = 32;
+
+// partitions that are symlinks below /system on system-as-root devices
+const PARTITIONS: &[&str] = &["vendor", "product", "system_ext", "odm"];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MountStatus {
+    /// All checked files are visible.
+    Mounted,
+    /// Only some of the checked files are visible.
+    Partial,
+    NotMounted,
+    /// Mounted by the built-in fallback.
+    Fallback,
+}
+
+impl MountStatus {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Mounted => "mounted",
+            Self::Partial => "partial",
+            Self::NotMounted => "not_mounted",
+            Self::Fallback => "fallback",
+        }
+    }
+}
+
+// module directories that are mounted onto the partition of the same name
+const MODULE_TREES: &[&str] = &["system", "vendor", "product", "system_ext"];
+
+/// Engine that mounts the files of modules.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum MountEngine {
+    /// The mount script of the installed metamodule.
+    #[default]
+    Metamodule,
+    /// The built-in magic mount.
+    Magic,
+}
+
+impl MountEngine {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Metamodule => "metamodule",
+            Self::Magic => "magic",
+        }
+    }
+
+    pub fn parse(s: &str) -> Result<Self, String> {
+        match s.trim() {
+            "metamodule" => Ok(Self::Metamodule),
+            "magic" => Ok(Self::Magic),
+            _ => Err("unknown mount engine, expected one of: metamodule, magic".to_string()),
+        }
+    }
+
+    /// The engine selected in [`defs::MOUNT_ENGINE_PATH`], the metamodule by default.
+    pub fn current() -> Self {
+        fs::read_to_string(defs::MOUNT_ENGINE_PATH)
+            .ok()
+            .and_then(|s| Self::parse(&s).ok())
+            .unwrap_or_default()
+    }
+
+    /// Select the engine used from the next boot on.
+    pub fn select(self) -> Result<()> {
+        fs::write(defs::MOUNT_ENGINE_PATH, self.as_str())
+            .with_context(|| format!("write {}", defs::MOUNT_ENGINE_PATH))
+    }
+}
+
+/// Whether the module ships files for any partition and doesn't opt out of mounting.
+pub fn needs_mount(module: &Path) -> bool {
+    MODULE_TREES.iter().any(|tree| module.join(tree).is_dir())
+        && !module.join(defs::SKIP_MOUNT_FILE_NAME).exists()
+}
+
+fn is_whiteout(metadata: &fs::Metadata) -> bool {
+    metadata.file_type().is_char_device() && metadata.rdev() == 0
+}
+
+fn sample_files(dir: &Path, rel: &Path, out: &mut Vec<PathBuf>) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        if out.len() >= SAMPLE_FILES {
+            return;
+        }
+        let Ok(metadata) = entry.metadata() else {
+            continue;
+        };
+        let rel = rel.join(entry.file_name());
+        if metadata.is_dir() {
+            sample_files(&entry.path(), &rel, out);
+        } else if metadata.is_file() {
+            out.push(rel);
+        }
+    }
+}
+
+// overlayfs and bind mounts both report the size and mtime of the module's file
+fn is_visible(module_file: &Path, target: &Path) -> bool {
+    match (fs::metadata(module_file), fs::symlink_metadata(target)) {
+        (Ok(source), Ok(target)) => {
+            !is_whiteout(&target)
+                && source.len() == target.len()
+                && source.mtime() == target.mtime()
+                && source.mtime_nsec() == target.mtime_nsec()
+        }
+        _ => false,
+    }
+}
+
+fn check_module(module: &Path) -> MountStatus {
+    // paths relative to the module, which are the same relative to the root
+    let mut files = Vec::new();
+    for tree in MODULE_TREES {
+        sample_files(&module.join(tree), Path::new(tree), &mut files);
+    }
+    if files.is_empty() {
+        // only directories or whiteouts, nothing we can tell apart
+        return MountStatus::Mounted;
+    }
+
+    let visible = files
+        .iter()
+        .filter(|rel| is_visible(&module.join(rel), &Path::new("/").join(rel)))
+        .count();
+    match visible {
+        0 => MountStatus::NotMounted,
+        n if n == files.len() => MountStatus::Mounted,
+        _ => MountStatus::Partial,
+    }
+}
+
+fn modules_to_mount() -> Vec<(String, PathBuf)> {
+    let mut modules = Vec::new();
+    let _ = foreach_module(Active, |module| {
+        if !needs_mount(module) {
+            return Ok(());
+        }
+        let is_metamodule =
+            super::read_module_prop(module).is_ok_and(|props| metamodule::is_metamodule(&props));
+        if let (false, Some(id)) = (is_metamodule, module.file_name().and_then(|n| n.to_str())) {
+            modules.push((id.to_string(), module.to_path_buf()));
+        }
+        Ok(())
+    });
+    modules.sort();
+    modules
+}
+
+// subdirectories of `source` that go on top of the real directories below `partition`
+fn partition_targets(source: &Path, partition: &Path, targets: &mut Vec<(PathBuf, PathBuf)>) {
+    let Ok(children) = fs::read_dir(source) else {
+        return;
+    };
+    for child in children.flatten() {
+        let target = partition.join(child.file_name());
+        if child.path().is_dir() && target.is_dir() {
+            targets.push((child.path(), target));
+        }
+    }
+}
+
+/// Directories of a module's partition trees and the real directories they go on top of.
+fn overlay_targets(module: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let mut targets = Vec::new();
+    let system = module.join("system");
+    if let Ok(entries) = fs::read_dir(&system) {
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let source = entry.path();
+            let target = Path::new("/system").join(&name);
+            if !source.is_dir() {
+                warn!(
+                    "fallback mount: {} is not in a directory, skip",
+                    source.display()
+                );
+                continue;
+            }
+
+            if target.is_symlink() && PARTITIONS.iter().any(|p| name == *p) {
+                partition_targets(&source, &Path::new("/").join(&name), &mut targets);
+            } else if target.is_dir() {
+                targets.push((source, target));
+            }
+        }
+    }
+    for tree in MODULE_TREES.iter().filter(|tree| **tree != "system") {
+        partition_targets(&module.join(tree), &Path::new("/").join(tree), &mut targets);
+    }
+    targets
+}
+
+fn mount_overlay(lowers: &[PathBuf], target: &Path) -> Result<()> {
+    let mut layers = lowers.to_vec();
+    layers.push(target.to_path_buf());
+    let layers: Vec<String> = layers
+        .iter()
+        .map(|p| p.to_string_lossy().into_owned())
+        .collect();
+    if layers.iter().any(|l| l.contains([':', ','])) {
+        bail!("path can't be used as overlayfs layer");
+    }
+
+    let options = CString::new(format!("lowerdir={}", layers.join(":")))?;
+    let target_c = CString::new(target.as_os_str().as_encoded_bytes())?;
+    let ret = unsafe {
+        libc::mount(
+            c"KSU".as_ptr(),
+            target_c.as_ptr(),
+            c"overlay".as_ptr(),
+            libc::MS_RDONLY,
+            options.as_ptr().cast(),
+        )
+    };
+    if ret != 0 {
+        return Err(std::io::Error::last_os_error()).context("mount overlay");
+    }
+    Ok(())
+}
+
+/// Mount the modules with read-only overlayfs on each directory they touch.
+fn fallback_mount(modules: &[&(String, PathBuf)]) {
+    let mut layers: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for (_, module) in modules {
+        for (source, target) in overlay_targets(module) {
+            layers.entry(target).or_default().push(source);
+        }
+    }
+
+    for (target, lowers) in &layers {
+        match mount_overlay(lowers, target) {
+            Ok(()) => {
+                info!("fallback mount: {}", target.display());
+                let target = target.to_string_lossy();
+                if let Err(e) = ksucalls::umount_list_add(&target, libc::MNT_DETACH as u32) {
+                    warn!("fallback mount: register {target} for umount failed: {e}");
+                }
+            }
+            Err(e) => warn!("fallback mount: {} failed: {e:#}", target.display()),
+        }
+    }
+}
+
+fn save_status(status: &BTreeMap<String, MountStatus>) -> Result<()> {
+    let json = serde_json::to_string_pretty(status)?;
+    fs::write(defs::MODULE_MOUNT_STATUS_PATH, json)
+        .with_context(|| format!("write {}", defs::MODULE_MOUNT_STATUS_PATH))
+}
+
+/// Mount status of every module as recorded at boot.
+pub fn read_status() -> HashMap<String, MountStatus> {
+    fs::read_to_string(defs::MODULE_MOUNT_STATUS_PATH)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+// (mount id, mount point) of every mount, in mount order
+fn mount_table() -> Vec<(u32, String)> {
+    let Ok(content) = fs::read_to_string("/proc/self/mountinfo") else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split(' ');
+            let id = fields.next()?.parse().ok()?;
+            Some((id, utils::unescape_mount_path(fields.nth(3)?)))
+        })
+        .collect()
+}
+
+fn save_mounts(mounts: &[String]) -> Result<()> {
+    let json = serde_json::to_string_pretty(mounts)?;
+    fs::write(defs::MODULE_MOUNTS_PATH, json)
+        .with_context(|| format!("write {}", defs::MODULE_MOUNTS_PATH))
+}
+
+/// Mount points added while mounting modules at boot, in mount order.
+pub fn read_mounts() -> Vec<String> {
+    fs::read_to_string(defs::MODULE_MOUNTS_PATH)
+        .ok()
+        .and_then(|json| serde_json::from_str(&json).ok())
+        .unwrap_or_default()
+}
+
+/// Run the selected mount engine, check the result and fall back to the built-in overlayfs
+/// mount for modules that didn't get mounted at all, if enabled. An engine failure is only
+/// returned if modules are still not mounted after the fallback.
+pub fn mount_modules(module_dir: &str) -> Result<()> {
+    let modules = modules_to_mount();
+    let before: HashSet<u32> = mount_table().into_iter().map(|(id, _)| id).collect();
+    let engine = MountEngine::current();
+    let script_result = match engine {
+        MountEngine::Metamodule => {
+            if !metamodule::has_metamodule() {
+                info!("no metamodule installed, modules are not mounted by it");
+            }
+            metamodule::exec_mount_script(module_dir)
+        }
+        MountEngine::Magic => {
+            if metamodule::has_metamodule() {
+                info!("magic mount is selected, not running the metamodule's mount script");
+            }
+            let paths: Vec<PathBuf> = modules.iter().map(|(_, module)| module.clone()).collect();
+            MagicMount::new("/", defs::MAGIC_MOUNT_WORK_DIR).mount(&paths)
+        }
+    };
+
+    let mut status: BTreeMap<String, MountStatus> = modules
+        .iter()
+        .map(|(id, module)| (id.clone(), check_module(module)))
+        .collect();
+
+    let missing: Vec<_> = modules
+        .iter()
+        .filter(|(id, _)| status.get(id) == Some(&MountStatus::NotMounted))
+        .collect();
+    if !missing.is_empty() && Path::new(defs::MOUNT_FALLBACK_FLAG).exists() {
+        info!("{} modules are not mounted, using fallback", missing.len());
+        fallback_mount(&missing);
+        for (id, module) in missing {
+            if check_module(module) == MountStatus::Mounted {
+                status.insert(id.clone(), MountStatus::Fallback);
+            }
+        }
+    }
+
+    for (id, module_status) in &status {
+        if *module_status != MountStatus::Mounted {
+            warn!("module {id}: {}", module_status.as_str());
+        }
+    }
+    if let Err(e) = save_status(&status) {
+        warn!("save module mount status failed: {e:#}");
+    }
+
+    // whatever the engine or the fallback mounted, so it can be undone on unload
+    let mounts: Vec<String> = mount_table()
+        .into_iter()
+        .filter(|(id, _)| !before.contains(id))
+        .map(|(_, mount_point)| mount_point)
+        .collect();
+    if let Err(e) = save_mounts(&mounts) {
+        warn!("save module mounts failed: {e:#}");
+    }
+
+    let unmounted: Vec<&str> = status
+        .iter()
+        .filter(|(_, module_status)| **module_status == MountStatus::NotMounted)
+        .map(|(id, _)| id.as_str())
+        .collect();
+    match script_result {
+        Err(e) if unmounted.is_empty() => {
+            warn!(
+                "{} mount failed, but every module got mounted: {e:#}",
+                engine.as_str()
+            );
+            Ok(())
+        }
+        Err(e) => Err(e).with_context(|| format!("modules not mounted: {}", unmounted.join(", "))),
+        Ok(()) => Ok(()),
+    }
+}
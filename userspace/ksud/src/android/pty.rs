@@ -0,0 +1,262 @@
+use std::{
+    ffi::CStr,
+    fs::OpenOptions,
+    io,
+    os::{
+        fd::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        unix::{fs::OpenOptionsExt, process::CommandExt},
+    },
+    process::{Command, ExitStatus, Stdio},
+    sync::atomic::{AtomicI32, Ordering},
+};
+
+use anyhow::{Context, Result};
+use libc::c_int;
+
+// write end of the self-pipe the signal handler reports to
+static SIGNAL_PIPE: AtomicI32 = AtomicI32::new(-1);
+
+const FORWARDED_SIGNALS: &[c_int] = &[
+    libc::SIGWINCH,
+    libc::SIGCHLD,
+    libc::SIGINT,
+    libc::SIGQUIT,
+    libc::SIGTERM,
+    libc::SIGHUP,
+];
+
+extern "C" fn on_signal(sig: c_int) {
+    let fd = SIGNAL_PIPE.load(Ordering::Relaxed);
+    if fd >= 0 {
+        let byte = sig as u8;
+        unsafe { libc::write(fd, (&raw const byte).cast(), 1) };
+    }
+}
+
+fn last_error<T>(what: &str) -> Result<T> {
+    Err(io::Error::last_os_error()).context(what.to_string())
+}
+
+/// Puts a terminal into raw mode and restores it when dropped.
+struct RawMode {
+    fd: RawFd,
+    saved: libc::termios,
+}
+
+impl RawMode {
+    fn enter(fd: RawFd) -> Result<Self> {
+        let mut saved = unsafe { std::mem::zeroed::<libc::termios>() };
+        if unsafe { libc::tcgetattr(fd, &raw mut saved) } != 0 {
+            return last_error("tcgetattr");
+        }
+        let mut raw = saved;
+        unsafe { libc::cfmakeraw(&raw mut raw) };
+        if unsafe { libc::tcsetattr(fd, libc::TCSAFLUSH, &raw const raw) } != 0 {
+            return last_error("tcsetattr");
+        }
+        Ok(Self { fd, saved })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        unsafe { libc::tcsetattr(self.fd, libc::TCSAFLUSH, &raw const self.saved) };
+    }
+}
+
+fn copy_winsize(from: RawFd, to: RawFd) {
+    let mut size = unsafe { std::mem::zeroed::<libc::winsize>() };
+    if unsafe { libc::ioctl(from, libc::TIOCGWINSZ, &raw mut size) } == 0 {
+        unsafe { libc::ioctl(to, libc::TIOCSWINSZ, &raw const size) };
+    }
+}
+
+fn open_pty() -> Result<(OwnedFd, OwnedFd)> {
+    let master = unsafe { libc::posix_openpt(libc::O_RDWR | libc::O_NOCTTY | libc::O_CLOEXEC) };
+    if master < 0 {
+        return last_error("posix_openpt");
+    }
+    let master = unsafe { OwnedFd::from_raw_fd(master) };
+    if unsafe { libc::grantpt(master.as_raw_fd()) } != 0
+        || unsafe { libc::unlockpt(master.as_raw_fd()) } != 0
+    {
+        return last_error("unlock pty");
+    }
+
+    let mut name = [0u8; 64];
+    if unsafe { libc::ptsname_r(master.as_raw_fd(), name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return last_error("ptsname");
+    }
+    let name = CStr::from_bytes_until_nul(&name)?
+        .to_string_lossy()
+        .into_owned();
+    let slave = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_NOCTTY)
+        .open(&name)
+        .with_context(|| format!("open {name}"))?;
+    Ok((master, slave.into()))
+}
+
+fn signal_pipe() -> Result<(OwnedFd, OwnedFd)> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } != 0 {
+        return last_error("pipe2");
+    }
+    Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+}
+
+fn read_fd(fd: RawFd, buf: &mut [u8]) -> isize {
+    unsafe { libc::read(fd, buf.as_mut_ptr().cast(), buf.len()) }
+}
+
+fn write_all(fd: RawFd, mut buf: &[u8]) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = unsafe { libc::write(fd, buf.as_ptr().cast(), buf.len()) };
+        if n < 0 {
+            let err = io::Error::last_os_error();
+            if err.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err);
+        }
+        buf = &buf[n as usize..];
+    }
+    Ok(())
+}
+
+pub fn exit_code(status: ExitStatus) -> i32 {
+    use std::os::unix::process::ExitStatusExt;
+    status
+        .code()
+        .or_else(|| status.signal().map(|sig| 128 + sig))
+        .unwrap_or(1)
+}
+
+/// Run `command` on a new pseudo-terminal and proxy it to our own terminal until it exits,
+/// forwarding window size changes and termination signals. Returns the exit code.
+pub fn run_session(mut command: Command) -> Result<i32> {
+    let (master, slave) = open_pty()?;
+    let master_fd = master.as_raw_fd();
+    copy_winsize(libc::STDIN_FILENO, master_fd);
+
+    command
+        .stdin(Stdio::from(slave.try_clone()?))
+        .stdout(Stdio::from(slave.try_clone()?))
+        .stderr(Stdio::from(slave));
+    unsafe {
+        command.pre_exec(|| {
+            // become session leader and take the pty as controlling terminal
+            if libc::setsid() < 0 || libc::ioctl(0, libc::TIOCSCTTY, 0) < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            Ok(())
+        })
+    };
+
+    let (sig_read, sig_write) = signal_pipe()?;
+    SIGNAL_PIPE.store(sig_write.as_raw_fd(), Ordering::Relaxed);
+    for &sig in FORWARDED_SIGNALS {
+        unsafe { libc::signal(sig, on_signal as *const () as libc::sighandler_t) };
+    }
+
+    let mut child = command.spawn().context("spawn shell")?;
+    // close our copies of the slave, so reading the master fails once the session is gone
+    drop(command);
+    let pid = child.id() as libc::pid_t;
+
+    let raw_mode = RawMode::enter(libc::STDIN_FILENO).ok();
+    let mut stdin_open = true;
+    let mut buf = [0u8; 4096];
+    let status = loop {
+        let mut fds = [
+            libc::pollfd {
+                fd: if stdin_open { libc::STDIN_FILENO } else { -1 },
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: master_fd,
+                events: libc::POLLIN,
+                revents: 0,
+            },
+            libc::pollfd {
+                fd: sig_read.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            },
+        ];
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as _, -1) } < 0 {
+            if io::Error::last_os_error().kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            break child.wait()?;
+        }
+
+        if fds[0].revents != 0 {
+            let n = read_fd(libc::STDIN_FILENO, &mut buf);
+            if n > 0 {
+                write_all(master_fd, &buf[..n as usize])?;
+            } else {
+                stdin_open = false;
+            }
+        }
+
+        if fds[1].revents != 0 {
+            let n = read_fd(master_fd, &mut buf);
+            if n <= 0 {
+                // EIO: every process of the session has closed the slave
+                break child.wait()?;
+            }
+            write_all(libc::STDOUT_FILENO, &buf[..n as usize])?;
+        }
+
+        if fds[2].revents != 0 {
+            let mut sigs = [0u8; 16];
+            let n = read_fd(sig_read.as_raw_fd(), &mut sigs).max(0);
+            for &sig in &sigs[..n as usize] {
+                match c_int::from(sig) {
+                    // the kernel signals the foreground process group of the pty itself
+                    libc::SIGWINCH => copy_winsize(libc::STDIN_FILENO, master_fd),
+                    libc::SIGCHLD => {}
+                    sig => unsafe {
+                        libc::kill(pid, sig);
+                    },
+                }
+            }
+            if let Some(status) = child.try_wait()? {
+                // flush what the shell printed before exiting
+                while read_ready(master_fd) {
+                    let n = read_fd(master_fd, &mut buf);
+                    if n <= 0 {
+                        break;
+                    }
+                    write_all(libc::STDOUT_FILENO, &buf[..n as usize])?;
+                }
+                break status;
+            }
+        }
+    };
+
+    drop(raw_mode);
+    SIGNAL_PIPE.store(-1, Ordering::Relaxed);
+    for &sig in FORWARDED_SIGNALS {
+        unsafe { libc::signal(sig, libc::SIG_DFL) };
+    }
+    Ok(exit_code(status))
+}
+
+fn read_ready(fd: RawFd) -> bool {
+    let mut pfd = libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    unsafe { libc::poll(&raw mut pfd, 1, 0) > 0 && pfd.revents & libc::POLLIN != 0 }
+}
+
+/// Whether an interactive session should get its own pseudo-terminal.
+pub fn wanted() -> bool {
+    unsafe { libc::isatty(libc::STDIN_FILENO) == 1 && libc::isatty(libc::STDOUT_FILENO) == 1 }
+}
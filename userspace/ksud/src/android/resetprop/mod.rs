@@ -1,16 +1,23 @@
+pub mod monitor;
+pub mod rules;
+pub mod snapshot;
+
 use std::{
+    collections::BTreeSet,
     fmt,
     fs::File,
     io::{BufRead, BufReader},
-    path::Path,
+    path::{Path, PathBuf},
     time::Duration,
 };
 
 use anyhow::{Context, Result, bail};
 use clap::{Parser, error::ErrorKind};
-use log::info;
+use log::{info, warn};
 use prop_rs_android::{resetprop::ResetProp, sys_prop};
 
+use crate::{defs, prop_area};
+
 #[derive(Debug)]
 pub struct WaitTimeoutError {
     name: String,
@@ -72,6 +79,38 @@ pub struct Args {
     #[arg(long = "force")]
     force: bool,
 
+    /// Print every property change, optionally only for names starting with one of PREFIX, until interrupted.
+    #[arg(long = "monitor", value_name = "PREFIX", num_args = 0..)]
+    monitor: Option<Vec<String>>,
+
+    /// Print --monitor events as JSON lines.
+    #[arg(long = "json", requires = "monitor")]
+    json: bool,
+
+    /// Operate on the property area files in DIR (a copy of /dev/__properties__) instead of the live properties.
+    #[arg(long = "root", value_name = "DIR")]
+    root: Option<PathBuf>,
+
+    /// Save all properties with their serials and SELinux contexts to FILE (with -p, also persistent storage).
+    #[arg(long = "snapshot", value_name = "FILE")]
+    snapshot: Option<String>,
+
+    /// Show properties changed since the snapshot in FILE.
+    #[arg(long = "diff", value_name = "FILE")]
+    diff: Option<String>,
+
+    /// Restore properties from the snapshot in FILE (with -p, also persistent storage).
+    #[arg(long = "restore", value_name = "FILE")]
+    restore: Option<String>,
+
+    /// With --restore, only restore properties whose name starts with PREFIX (can be repeated).
+    #[arg(long = "prefix", value_name = "PREFIX", requires = "restore")]
+    prefixes: Vec<String>,
+
+    /// With --restore, also delete properties under the given prefixes that are missing from the snapshot.
+    #[arg(long = "prune", requires = "restore", requires = "prefixes")]
+    prune: bool,
+
     #[arg(
         allow_hyphen_values = true,
         trailing_var_arg = true,
@@ -138,6 +177,31 @@ pub fn run(cli: &Args) -> Result<()> {
 /// Execute resetprop logic
 /// Subcommand will direct call that, skip run_from_args
 fn execute(cli: &Args) -> Result<()> {
+    if let Some(root) = &cli.root {
+        let live_only = cli.wait
+            || cli.persistent
+            || cli.persist_only
+            || cli.file.is_some()
+            || cli.snapshot.is_some()
+            || cli.diff.is_some()
+            || cli.restore.is_some()
+            || cli.monitor.is_some();
+        if live_only {
+            bail!("--root only supports get, set, list, -d and -c");
+        }
+        return prop_area::run(
+            root,
+            &prop_area::Request {
+                delete: cli.delete,
+                rebuild: cli.rebuild,
+                show_context: cli.show_context,
+                force: cli.force,
+                name: cli.name().map(String::as_str),
+                value: cli.value().map(String::as_str),
+            },
+        );
+    }
+
     sys_prop::init().context("Failed to initialize system property API")?;
 
     let rp = ResetProp {
@@ -150,7 +214,13 @@ fn execute(cli: &Args) -> Result<()> {
     };
 
     // Validate: at most one special mode
-    let special_modes = u8::from(cli.wait) + u8::from(cli.delete) + u8::from(cli.file.is_some());
+    let special_modes = u8::from(cli.wait)
+        + u8::from(cli.delete)
+        + u8::from(cli.file.is_some())
+        + u8::from(cli.snapshot.is_some())
+        + u8::from(cli.diff.is_some())
+        + u8::from(cli.restore.is_some())
+        + u8::from(cli.monitor.is_some());
     if special_modes > 1 {
         bail!("multiple operation modes detected");
     }
@@ -184,6 +254,22 @@ fn execute(cli: &Args) -> Result<()> {
         return Ok(());
     }
 
+    if let Some(prefixes) = &cli.monitor {
+        return monitor::monitor(prefixes, cli.json);
+    }
+
+    if let Some(path) = &cli.snapshot {
+        return snapshot::save(&rp, Path::new(path));
+    }
+
+    if let Some(path) = &cli.diff {
+        return snapshot::diff(&rp, Path::new(path));
+    }
+
+    if let Some(path) = &cli.restore {
+        return snapshot::restore(&rp, Path::new(path), &cli.prefixes, cli.prune);
+    }
+
     // -d: delete
     if cli.delete {
         let name = cli.name().context("--delete requires a property name")?;
@@ -244,9 +330,20 @@ fn execute(cli: &Args) -> Result<()> {
     Ok(())
 }
 
+fn read_rules(path: &Path) -> Result<Vec<rules::Rule>> {
+    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
+    let lines = BufReader::new(file)
+        .lines()
+        .collect::<std::io::Result<Vec<_>>>()
+        .with_context(|| format!("Failed to read {}", path.display()))?;
+    rules::parse(&path.display().to_string(), lines)
+        .with_context(|| format!("Invalid rules in {}", path.display()))
+}
+
 /// Load system.prop file using internal resetprop API.
 ///
-/// Equivalent to `resetprop -n --file <path>`.
+/// Plain lines behave like `resetprop -n --file <path>`, see [`rules`] for the directives
+/// that are also accepted.
 pub fn load_system_prop_file(path: &Path) -> Result<()> {
     sys_prop::init().context("Failed to initialize system property API")?;
 
@@ -259,11 +356,69 @@ pub fn load_system_prop_file(path: &Path) -> Result<()> {
         rebuild: false,
     };
 
-    let file = File::open(path).with_context(|| format!("Failed to open {}", path.display()))?;
-    let reader = BufReader::new(file);
-    rp.load_props(reader.lines())
+    let rules = read_rules(path)?;
+    rules::apply(&rp, &rules)
         .with_context(|| format!("Failed to load properties from {}", path.display()))?;
 
     info!("Loaded system.prop from {}", path.display());
     Ok(())
 }
+
+/// Snapshot the properties before modules touch them, see `resetprop --diff`.
+pub fn save_boot_snapshot() -> Result<()> {
+    sys_prop::init().context("Failed to initialize system property API")?;
+
+    let rp = ResetProp {
+        skip_svc: true,
+        persistent: false,
+        persist_only: false,
+        verbose: false,
+        show_context: false,
+        rebuild: false,
+    };
+    snapshot::save(&rp, Path::new(defs::PROP_SNAPSHOT_PATH))
+}
+
+/// Put the properties written by the given system.prop files back to their values in the boot
+/// snapshot, deleting those that didn't exist then. Other properties are left alone.
+pub fn restore_boot_snapshot(system_props: &[PathBuf]) -> Result<()> {
+    sys_prop::init().context("Failed to initialize system property API")?;
+
+    let rp = ResetProp {
+        skip_svc: true,
+        persistent: false,
+        persist_only: false,
+        verbose: false,
+        show_context: false,
+        rebuild: false,
+    };
+    let snapshot = snapshot::load(Path::new(defs::PROP_SNAPSHOT_PATH))?;
+
+    let mut names = BTreeSet::new();
+    for path in system_props {
+        for rule in read_rules(path)? {
+            if let Some(name) = rule.target() {
+                names.insert(name.to_string());
+            }
+        }
+    }
+
+    for name in &names {
+        let current = rp.get(name);
+        match snapshot.props.get(name) {
+            Some(entry) if current.as_deref() != Some(entry.value.as_str()) => {
+                if let Err(e) = rp.set(name, &entry.value) {
+                    warn!("Failed to restore {name}: {e}");
+                }
+            }
+            None if current.is_some() => {
+                if let Err(e) = rp.delete(name) {
+                    warn!("Failed to delete {name}: {e}");
+                }
+            }
+            _ => {}
+        }
+    }
+    info!("Restored {} module properties", names.len());
+    Ok(())
+}
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, ptr};
+
+use anyhow::{Result, bail};
+use serde::Serialize;
+
+use super::snapshot;
+
+unsafe extern "C" {
+    fn __system_property_area_serial() -> u32;
+    fn __system_property_wait(
+        pi: *const libc::prop_info,
+        old_serial: u32,
+        new_serial: *mut u32,
+        timeout: *const libc::timespec,
+    ) -> bool;
+}
+
+#[derive(Serialize)]
+struct Change<'a> {
+    time: String,
+    name: &'a str,
+    old: Option<&'a str>,
+    new: Option<&'a str>,
+}
+
+impl Change<'_> {
+    fn print(&self, json: bool) -> Result<()> {
+        if json {
+            println!("{}", serde_json::to_string(self)?);
+        } else {
+            println!(
+                "{} [{}]: [{}] -> [{}]",
+                self.time,
+                self.name,
+                self.old.unwrap_or("<unset>"),
+                self.new.unwrap_or("<deleted>")
+            );
+        }
+        Ok(())
+    }
+}
+
+fn read_matching(prefixes: &[String]) -> HashMap<String, (String, u32)> {
+    snapshot::read_values()
+        .into_iter()
+        .filter(|(name, _, _)| prefixes.is_empty() || prefixes.iter().any(|p| name.starts_with(p)))
+        .map(|(name, value, serial)| (name, (value, serial)))
+        .collect()
+}
+
+/// Print every change of the properties starting with one of `prefixes` (all if empty),
+/// until killed. A property written again with the same value is reported too.
+pub fn monitor(prefixes: &[String], json: bool) -> Result<()> {
+    let mut serial = unsafe { __system_property_area_serial() };
+    let mut known = read_matching(prefixes);
+
+    loop {
+        let mut new_serial = 0;
+        if !unsafe { __system_property_wait(ptr::null(), serial, &raw mut new_serial, ptr::null()) }
+        {
+            bail!("waiting for property changes failed");
+        }
+        serial = new_serial;
+
+        let time = chrono::Local::now().to_rfc3339();
+        let current = read_matching(prefixes);
+        let mut changes: Vec<Change> = current
+            .iter()
+            .filter_map(|(name, (value, serial))| {
+                let old = known.get(name);
+                if old.is_some_and(|(_, old_serial)| old_serial == serial) {
+                    return None;
+                }
+                Some(Change {
+                    time: time.clone(),
+                    name,
+                    old: old.map(|(v, _)| v.as_str()),
+                    new: Some(value),
+                })
+            })
+            .collect();
+        changes.extend(
+            known
+                .iter()
+                .filter(|(name, _)| !current.contains_key(*name))
+                .map(|(name, (value, _))| Change {
+                    time: time.clone(),
+                    name,
+                    old: Some(value),
+                    new: None,
+                }),
+        );
+        changes.sort_by_key(|c| c.name);
+        for change in &changes {
+            change.print(json)?;
+        }
+
+        known = current;
+    }
+}
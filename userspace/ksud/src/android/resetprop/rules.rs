@@ -0,0 +1,284 @@
+//! Directives understood in module system.prop files, in addition to plain `name=value` lines:
+//!
+//! ```text
+//! @delete <name>                     delete the property
+//! @default <name>=<value>            set only if the property does not exist
+//! @if <name> <regex> <rule>          apply <rule> only if the current value matches <regex>
+//! @wait <name> [<old value>]         wait for the property to exist, or to change from <old value>
+//! @timeout <seconds>                 timeout for the following @wait directives
+//! ```
+//!
+//! e.g. `@if ro.boot.verifiedbootstate ^orange$ ro.boot.verifiedbootstate=green`.
+//! The directives are validated before any property is touched, malformed plain lines are
+//! skipped with a warning like resetprop does.
+
+use std::time::Duration;
+
+use anyhow::{Result, bail};
+use log::{info, warn};
+use prop_rs_android::resetprop::ResetProp;
+use regex_lite::Regex;
+
+const DEFAULT_WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug)]
+pub enum Rule {
+    /// A plain `name=value` line, handed to resetprop as is.
+    Set {
+        line: String,
+    },
+    Default {
+        name: String,
+        value: String,
+    },
+    Delete {
+        name: String,
+    },
+    If {
+        name: String,
+        pattern: Regex,
+        rule: Box<Self>,
+    },
+    Wait {
+        name: String,
+        old_value: Option<String>,
+        timeout: Duration,
+    },
+}
+
+impl Rule {
+    /// The property the rule may write, if any.
+    pub fn target(&self) -> Option<&str> {
+        match self {
+            Self::Set { line } => line.split_once('=').map(|(name, _)| name.trim()),
+            Self::Default { name, .. } | Self::Delete { name } => Some(name),
+            Self::If { rule, .. } => rule.target(),
+            Self::Wait { .. } => None,
+        }
+    }
+}
+
+fn check_name(name: &str) -> Result<()> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '=') {
+        bail!("invalid property name '{name}'");
+    }
+    Ok(())
+}
+
+fn parse_assignment(s: &str) -> Result<(String, String)> {
+    let Some((name, value)) = s.split_once('=') else {
+        bail!("expected <name>=<value>");
+    };
+    let name = name.trim();
+    check_name(name)?;
+    Ok((name.to_string(), value.trim().to_string()))
+}
+
+fn parse_rule(line: &str, timeout: Duration) -> Result<Rule> {
+    let Some(directive) = line.strip_prefix('@') else {
+        parse_assignment(line)?;
+        return Ok(Rule::Set {
+            line: line.to_string(),
+        });
+    };
+
+    let (keyword, rest) = directive
+        .split_once(char::is_whitespace)
+        .map_or((directive, ""), |(k, r)| (k, r.trim()));
+    match keyword {
+        "delete" => {
+            check_name(rest)?;
+            Ok(Rule::Delete {
+                name: rest.to_string(),
+            })
+        }
+        "default" => {
+            let (name, value) = parse_assignment(rest)?;
+            Ok(Rule::Default { name, value })
+        }
+        "if" => {
+            let mut parts = rest.splitn(3, char::is_whitespace);
+            let (Some(name), Some(pattern), Some(rule)) =
+                (parts.next(), parts.next(), parts.next())
+            else {
+                bail!("expected @if <name> <regex> <rule>");
+            };
+            check_name(name)?;
+            let pattern = Regex::new(pattern).map_err(|e| anyhow::anyhow!("bad regex: {e}"))?;
+            let rule = parse_rule(rule.trim(), timeout)?;
+            if matches!(rule, Rule::Wait { .. }) {
+                bail!("@wait can't be conditional");
+            }
+            Ok(Rule::If {
+                name: name.to_string(),
+                pattern,
+                rule: Box::new(rule),
+            })
+        }
+        "wait" => {
+            let (name, old_value) = rest
+                .split_once(char::is_whitespace)
+                .map_or((rest, None), |(n, v)| (n, Some(v.trim().to_string())));
+            check_name(name)?;
+            Ok(Rule::Wait {
+                name: name.to_string(),
+                old_value,
+                timeout,
+            })
+        }
+        _ => bail!("unknown directive @{keyword}"),
+    }
+}
+
+/// Parse every line, reporting all directive errors at once. `source` names the file in
+/// warnings about skipped lines.
+pub fn parse<I>(source: &str, lines: I) -> Result<Vec<Rule>>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut rules = Vec::new();
+    let mut errors = Vec::new();
+    let mut timeout = DEFAULT_WAIT_TIMEOUT;
+
+    for (index, line) in lines.into_iter().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(secs) = line.strip_prefix("@timeout") {
+            match secs.trim().parse::<f64>().map(Duration::try_from_secs_f64) {
+                Ok(Ok(t)) => timeout = t,
+                _ => errors.push(format!("line {}: invalid timeout", index + 1)),
+            }
+            continue;
+        }
+
+        match parse_rule(line, timeout) {
+            Ok(rule) => rules.push(rule),
+            Err(e) if line.starts_with('@') => errors.push(format!("line {}: {e}", index + 1)),
+            Err(e) => warn!("{source}:{}: {e}, skipped", index + 1),
+        }
+    }
+
+    if !errors.is_empty() {
+        bail!("{}", errors.join("\n"));
+    }
+    Ok(rules)
+}
+
+fn apply_rule(rp: &ResetProp, rule: &Rule) -> Result<()> {
+    match rule {
+        Rule::Set { line } => {
+            rp.load_props(std::iter::once(Ok::<_, std::io::Error>(line.clone())))?;
+        }
+        Rule::Default { name, value } => {
+            if rp.get(name).is_none() {
+                rp.set(name, value)?;
+            }
+        }
+        Rule::Delete { name } => {
+            rp.delete(name)?;
+        }
+        Rule::If {
+            name,
+            pattern,
+            rule,
+        } => {
+            let current = rp.get(name).unwrap_or_default();
+            if pattern.is_match(&current) {
+                apply_rule(rp, rule)?;
+            }
+        }
+        Rule::Wait {
+            name,
+            old_value,
+            timeout,
+        } => {
+            if !rp.wait(name, old_value.as_deref(), Some(*timeout))? {
+                warn!("timeout waiting for {name}, continuing");
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn apply(rp: &ResetProp, rules: &[Rule]) -> Result<()> {
+    for rule in rules {
+        apply_rule(rp, rule)?;
+    }
+    info!("Applied {} property rules", rules.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<String> {
+        text.lines().map(str::to_string).collect()
+    }
+
+    #[test]
+    fn plain_lines() {
+        let rules = parse(
+            "system.prop",
+            lines("# comment\n\nro.a=1\n ro.b = two words \nro.c=\n"),
+        )
+        .unwrap();
+        let targets: Vec<_> = rules.iter().filter_map(Rule::target).collect();
+        assert_eq!(targets, ["ro.a", "ro.b", "ro.c"]);
+    }
+
+    #[test]
+    fn malformed_plain_lines_are_skipped() {
+        let rules = parse(
+            "system.prop",
+            lines("ro.a=1\nnot a property\n=value\nro b=1\nro.c=3"),
+        )
+        .unwrap();
+        let targets: Vec<_> = rules.iter().filter_map(Rule::target).collect();
+        assert_eq!(targets, ["ro.a", "ro.c"]);
+    }
+
+    #[test]
+    fn directives() {
+        let rules = parse(
+            "system.prop",
+            lines(
+                "@timeout 2.5\n@wait sys.boot_completed\n@delete ro.x\n@default ro.y=1\n\
+                 @if ro.boot.verifiedbootstate ^orange$ ro.boot.verifiedbootstate=green",
+            ),
+        )
+        .unwrap();
+        assert!(matches!(
+            &rules[0],
+            Rule::Wait { name, old_value: None, timeout }
+                if name == "sys.boot_completed" && *timeout == Duration::from_millis(2500)
+        ));
+        let targets: Vec<_> = rules.iter().filter_map(Rule::target).collect();
+        assert_eq!(targets, ["ro.x", "ro.y", "ro.boot.verifiedbootstate"]);
+    }
+
+    #[test]
+    fn bad_directives_fail() {
+        for line in [
+            "@unknown ro.a",
+            "@delete",
+            "@default ro.a",
+            "@if ro.a ( ro.a=1",
+            "@if ro.a .* @wait ro.b",
+            "@timeout soon",
+        ] {
+            let result = parse("system.prop", lines(&format!("ro.ok=1\n{line}")));
+            assert!(result.is_err(), "{line}");
+        }
+
+        let error = parse("system.prop", lines("@delete\nbad line\n@nope"))
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("line 1"));
+        assert!(error.contains("line 3"));
+        assert!(!error.contains("line 2"));
+    }
+}
@@ -0,0 +1,313 @@
+use std::{
+    collections::BTreeMap,
+    ffi::{CStr, c_char, c_void},
+    fs,
+    path::Path,
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use log::{info, warn};
+use prop_rs_android::{resetprop::ResetProp, sys_prop};
+use serde::{Deserialize, Serialize};
+
+const SNAPSHOT_VERSION: u32 = 1;
+
+// the top byte of a serial holds the value length and bit 0 is the dirty flag,
+// the rest is a counter bumped on every write
+const SERIAL_COUNTER_MASK: u32 = 0x00ff_fffe;
+
+unsafe extern "C" {
+    fn __system_property_read_callback(
+        pi: *const libc::prop_info,
+        callback: unsafe extern "C" fn(*mut c_void, *const c_char, *const c_char, u32),
+        cookie: *mut c_void,
+    );
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PropEntry {
+    pub value: String,
+    pub serial: u32,
+    #[serde(default)]
+    pub context: String,
+}
+
+impl PropEntry {
+    fn rewritten_since(&self, old: &Self) -> bool {
+        self.serial & SERIAL_COUNTER_MASK != old.serial & SERIAL_COUNTER_MASK
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub timestamp: String,
+    pub props: BTreeMap<String, PropEntry>,
+    /// Contents of persistent property storage, only captured with `-p`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub persistent: Option<BTreeMap<String, String>>,
+}
+
+unsafe extern "C" fn read_prop(
+    cookie: *mut c_void,
+    name: *const c_char,
+    value: *const c_char,
+    serial: u32,
+) {
+    let props = unsafe { &mut *cookie.cast::<Vec<(String, String, u32)>>() };
+    let name = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .into_owned();
+    let value = unsafe { CStr::from_ptr(value) }
+        .to_string_lossy()
+        .into_owned();
+    props.push((name, value, serial));
+}
+
+unsafe extern "C" fn visit_prop(pi: *const libc::prop_info, cookie: *mut c_void) {
+    unsafe { __system_property_read_callback(pi, read_prop, cookie) };
+}
+
+/// Every property currently visible in the property areas as (name, value, serial).
+pub fn read_values() -> Vec<(String, String, u32)> {
+    let mut props: Vec<(String, String, u32)> = Vec::new();
+    unsafe {
+        libc::__system_property_foreach(visit_prop, (&raw mut props).cast());
+    }
+    props
+}
+
+/// Read every property currently visible in the property areas.
+pub fn read_live() -> BTreeMap<String, PropEntry> {
+    read_values()
+        .into_iter()
+        .map(|(name, value, serial)| {
+            let context = sys_prop::get_context(&name).unwrap_or_default();
+            (
+                name,
+                PropEntry {
+                    value,
+                    serial,
+                    context,
+                },
+            )
+        })
+        .collect()
+}
+
+fn persist_reader(rp: &ResetProp) -> ResetProp {
+    ResetProp {
+        skip_svc: rp.skip_svc,
+        persistent: true,
+        persist_only: true,
+        verbose: rp.verbose,
+        show_context: false,
+        rebuild: false,
+    }
+}
+
+fn read_persistent(rp: &ResetProp) -> Result<BTreeMap<String, String>> {
+    let props = persist_reader(rp)
+        .list_all()
+        .context("Failed to list persistent properties")?;
+    Ok(props
+        .iter()
+        .map(|(name, value)| (name.to_string(), value.to_string()))
+        .collect())
+}
+
+pub fn capture(rp: &ResetProp) -> Result<Snapshot> {
+    let persistent = if rp.persistent {
+        Some(read_persistent(rp)?)
+    } else {
+        None
+    };
+    Ok(Snapshot {
+        version: SNAPSHOT_VERSION,
+        timestamp: chrono::Local::now().to_rfc3339(),
+        props: read_live(),
+        persistent,
+    })
+}
+
+pub fn load(path: &Path) -> Result<Snapshot> {
+    let content =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let snapshot: Snapshot = serde_json::from_str(&content)
+        .with_context(|| format!("Invalid property snapshot {}", path.display()))?;
+    ensure!(
+        snapshot.version <= SNAPSHOT_VERSION,
+        "Unsupported property snapshot version {}",
+        snapshot.version
+    );
+    Ok(snapshot)
+}
+
+pub fn save(rp: &ResetProp, path: &Path) -> Result<()> {
+    let snapshot = capture(rp)?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, serde_json::to_string_pretty(&snapshot)?)
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    info!(
+        "Saved {} properties to {}",
+        snapshot.props.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn print_value_diff<'a, I>(old: &BTreeMap<String, String>, new: I, prefix: &str) -> usize
+where
+    I: IntoIterator<Item = (&'a String, &'a String)>,
+{
+    let new: BTreeMap<_, _> = new.into_iter().collect();
+    let mut changes = 0;
+    for (name, value) in old {
+        match new.get(name) {
+            None => println!("- {prefix}[{name}]: [{value}]"),
+            Some(current) if *current != value => {
+                println!("~ {prefix}[{name}]: [{value}] -> [{current}]");
+            }
+            Some(_) => continue,
+        }
+        changes += 1;
+    }
+    for (name, value) in &new {
+        if !old.contains_key(*name) {
+            println!("+ {prefix}[{name}]: [{value}]");
+            changes += 1;
+        }
+    }
+    changes
+}
+
+/// Print the differences between a snapshot and the live state.
+///
+/// `+` added, `-` removed, `~` value changed, `=` written again with the same value,
+/// `Z` SELinux context changed.
+pub fn diff(rp: &ResetProp, path: &Path) -> Result<()> {
+    let snapshot = load(path)?;
+    let live = read_live();
+
+    let old_values = snapshot
+        .props
+        .iter()
+        .map(|(name, entry)| (name.clone(), entry.value.clone()))
+        .collect();
+    let mut changes = print_value_diff(
+        &old_values,
+        live.iter().map(|(name, entry)| (name, &entry.value)),
+        "",
+    );
+
+    for (name, old) in &snapshot.props {
+        let Some(new) = live.get(name) else {
+            continue;
+        };
+        if new.value == old.value && new.rewritten_since(old) {
+            println!(
+                "= [{name}]: [{}] (serial {:#x} -> {:#x})",
+                new.value, old.serial, new.serial
+            );
+            changes += 1;
+        }
+        if !old.context.is_empty() && new.context != old.context {
+            println!("Z [{name}]: {} -> {}", old.context, new.context);
+            changes += 1;
+        }
+    }
+
+    if let Some(old_persistent) = &snapshot.persistent {
+        let persistent = read_persistent(rp)?;
+        changes += print_value_diff(old_persistent, &persistent, "storage:");
+    } else if rp.persistent {
+        warn!("{} has no persistent properties, skipped", path.display());
+    }
+
+    if changes == 0 {
+        println!("No changes since {}", snapshot.timestamp);
+    }
+    Ok(())
+}
+
+fn selected(prefixes: &[String], name: &str) -> bool {
+    prefixes.is_empty()
+        || prefixes
+            .iter()
+            .any(|prefix| name.starts_with(prefix.as_str()))
+}
+
+/// Put properties back to their values in the snapshot, only those starting with one of
+/// `prefixes` if any are given. Properties missing from the snapshot are only deleted with
+/// `prune`, which needs `prefixes`. Serials can't be restored, only values.
+pub fn restore(rp: &ResetProp, path: &Path, prefixes: &[String], prune: bool) -> Result<()> {
+    ensure!(
+        !prune || !prefixes.is_empty(),
+        "Deleting properties needs the prefixes to restore"
+    );
+    let snapshot = load(path)?;
+    let live = read_live();
+    let mut failed = 0;
+    let mut restored = 0;
+
+    for (name, entry) in &snapshot.props {
+        if !selected(prefixes, name) || live.get(name).is_some_and(|e| e.value == entry.value) {
+            continue;
+        }
+        if let Err(e) = rp.set(name, &entry.value) {
+            warn!("Failed to restore {name}: {e}");
+            failed += 1;
+        } else {
+            restored += 1;
+        }
+    }
+
+    let added = live
+        .keys()
+        .filter(|n| prune && selected(prefixes, n) && !snapshot.props.contains_key(*n));
+    for name in added {
+        match rp.delete(name) {
+            Ok(_) => restored += 1,
+            Err(e) => {
+                warn!("Failed to delete {name}: {e}");
+                failed += 1;
+            }
+        }
+    }
+
+    if let Some(old_persistent) = snapshot.persistent.as_ref().filter(|_| rp.persistent) {
+        let storage = persist_reader(rp);
+        let persistent = read_persistent(rp)?;
+        for (name, value) in old_persistent {
+            if !selected(prefixes, name) || persistent.get(name) == Some(value) {
+                continue;
+            }
+            if let Err(e) = storage.set(name, value) {
+                warn!("Failed to restore persistent {name}: {e}");
+                failed += 1;
+            } else {
+                restored += 1;
+            }
+        }
+        let added = persistent
+            .keys()
+            .filter(|n| prune && selected(prefixes, n) && !old_persistent.contains_key(*n));
+        for name in added {
+            match storage.delete(name) {
+                Ok(_) => restored += 1,
+                Err(e) => {
+                    warn!("Failed to delete persistent {name}: {e}");
+                    failed += 1;
+                }
+            }
+        }
+    }
+
+    info!("Restored {restored} properties from {}", path.display());
+    if failed > 0 {
+        bail!("{failed} properties could not be restored");
+    }
+    Ok(())
+}
@@ -3,9 +3,10 @@ use std::os::unix::process::CommandExt;
 use std::{
     cmp::Ordering,
     env,
-    ffi::{CStr, CString},
+    ffi::CStr,
     path::PathBuf,
     process::Command,
+    sync::atomic::{AtomicI32, Ordering as AtomicOrdering},
 };
 
 use anyhow::{Context, Ok, Result, bail};
@@ -13,13 +14,18 @@ use getopts::Options;
 use libc::c_int;
 use log::error;
 use rustix::{
-    process::getuid,
-    thread::{Gid, Uid, set_thread_res_gid, set_thread_res_uid},
+    process::{getgid, getgroups, getuid},
+    thread::{
+        CapabilitySet, CapabilitySets, Gid, Uid, capabilities, configure_capability_in_ambient_set,
+        set_capabilities, set_keep_capabilities, set_thread_res_gid, set_thread_res_uid,
+    },
 };
 
 use crate::{
     android::{
+        aid,
         ksucalls::{get_wrapped_fd, set_ksu_no_new_privs},
+        pty, sulog,
         utils::{self, umask},
     },
     defs,
@@ -47,7 +53,30 @@ fn print_usage(program: &str, opts: &Options) {
     print!("{}", opts.usage(&brief));
 }
 
-fn set_identity(uid: u32, gid: u32, groups: &[u32]) {
+/// The identity the kernel switched us to from the caller's root profile.
+struct ProfileIdentity {
+    gid: u32,
+    groups: Vec<u32>,
+    caps: CapabilitySet,
+}
+
+impl ProfileIdentity {
+    fn current() -> Result<Self> {
+        Ok(Self {
+            gid: getgid().as_raw(),
+            groups: getgroups()?.into_iter().map(Gid::as_raw).collect(),
+            caps: capabilities(None)?.permitted,
+        })
+    }
+}
+
+fn set_identity(uid: u32, gid: u32, groups: &[u32], caps: Option<CapabilitySet>) {
+    // a non-root profile uid only keeps its capabilities across exec through the ambient set
+    let keep_caps = caps.filter(|_| uid != 0);
+    if keep_caps.is_some() {
+        set_keep_capabilities(true).ok();
+    }
+
     rustix::thread::set_thread_groups(
         groups
             .iter()
@@ -60,6 +89,18 @@ fn set_identity(uid: u32, gid: u32, groups: &[u32]) {
     let uid = Uid::from_raw(uid);
     set_thread_res_gid(gid, gid, gid).ok();
     set_thread_res_uid(uid, uid, uid).ok();
+
+    if let Some(caps) = keep_caps {
+        let sets = CapabilitySets {
+            effective: caps,
+            permitted: caps,
+            inheritable: caps,
+        };
+        set_capabilities(None, sets).ok();
+        for cap in caps.iter() {
+            configure_capability_in_ambient_set(cap, true).ok();
+        }
+    }
 }
 
 fn wrap_tty(fd: c_int) {
@@ -82,39 +123,44 @@ fn wrap_tty(fd: c_int) {
     }
 }
 
-#[allow(clippy::similar_names)]
-pub fn root_shell() -> Result<()> {
-    // we are root now, this was set in kernel!
+const VALUE_SHORT_OPTS: &[char] = &['g', 'G', 's', 'Z', 't'];
+const VALUE_LONG_OPTS: &[&str] = &["group", "supp-group", "shell", "context", "target"];
 
-    use anyhow::anyhow;
-    let env_args: Vec<String> = env::args().collect();
-    let program = env_args[0].clone();
-    let mut executable: Option<String> = None;
-    let mut exec_args: Option<Vec<String>> = None;
+// whether the argument after `arg` is its value
+fn takes_value(arg: &str) -> bool {
+    if let Some(long) = arg.strip_prefix("--") {
+        return VALUE_LONG_OPTS.contains(&long);
+    }
+    let Some(cluster) = arg.strip_prefix('-') else {
+        return false;
+    };
+    // "-lg 1000" takes the next argument, "-g1000" and "-gl" don't
+    cluster
+        .find(|c| VALUE_SHORT_OPTS.contains(&c))
+        .is_some_and(|i| i == cluster.len() - 1)
+}
+
+/// Split `su [options] [-] [user [argument...]]` into the options getopts should see,
+/// and the executable and its arguments if given after the user.
+/// Everything after `-c` is joined into a single command.
+fn split_args(env_args: Vec<String>) -> (Vec<String>, Option<String>, Option<Vec<String>>) {
     let first_option_c = env_args
         .iter()
-        .position(|arg| arg == "-c")
+        .position(|arg| arg == "-c" || arg == "--command")
         .unwrap_or(usize::MAX);
     let first_non_option = env_args
         .windows(3)
         .position(|arg| {
-            !arg[1].starts_with('-')
-                && !arg[2].starts_with('-')
-                && !(arg[0].starts_with("-g")
-                    || arg[0].starts_with("-G")
-                    || arg[0].starts_with("-s")
-                    || arg[0] == "--group"
-                    || arg[0] == "--supp-group="
-                    || arg[0] == "--shell=")
+            !arg[1].starts_with('-') && !arg[2].starts_with('-') && !takes_value(&arg[0])
         })
         .map_or(usize::MAX, |idx| idx + 1);
-    let args = match first_non_option.cmp(&first_option_c) {
-        Ordering::Equal => env_args,
-        Ordering::Less => {
-            executable = Some(env_args[first_non_option + 1].clone());
-            exec_args = Some(env_args[first_non_option + 2..].to_vec());
-            env_args[..=first_non_option].to_vec()
-        }
+    match first_non_option.cmp(&first_option_c) {
+        Ordering::Equal => (env_args, None, None),
+        Ordering::Less => (
+            env_args[..=first_non_option].to_vec(),
+            Some(env_args[first_non_option + 1].clone()),
+            Some(env_args[first_non_option + 2..].to_vec()),
+        ),
         Ordering::Greater => {
             let rest = env_args[first_option_c + 1..].to_vec();
             let mut new_args = env_args[..first_option_c].to_vec();
@@ -122,10 +168,12 @@ pub fn root_shell() -> Result<()> {
             if !rest.is_empty() {
                 new_args.push(rest.join(" "));
             }
-            new_args
+            (new_args, None, None)
         }
-    };
+    }
+}
 
+fn su_options() -> Options {
     let mut opts = Options::new();
     opts.optopt(
         "c",
@@ -160,12 +208,42 @@ pub fn root_shell() -> Result<()> {
         "Specify a supplementary group. The first specified supplementary group is also used as a primary group if the option -g is not specified.",
         "GROUP",
     );
+    opts.optopt(
+        "Z",
+        "context",
+        "run the command in the SELinux context CONTEXT",
+        "CONTEXT",
+    );
+    opts.optopt(
+        "t",
+        "target",
+        "run in the mount namespace of process PID",
+        "PID",
+    );
     opts.optflag("W", "no-wrapper", "don't use ksu fd wrapper");
+    opts.optflag(
+        "",
+        "no-pty",
+        "don't allocate a pseudo-terminal when attached to a terminal",
+    );
     opts.optflag(
         "",
         "ksu-no-new-privs",
         "Prevent this process (and its children) from privilege re-escalation via KernelSU",
     );
+    opts
+}
+
+#[allow(clippy::similar_names)]
+pub fn root_shell() -> Result<()> {
+    // we are root now, this was set in kernel!
+
+    use anyhow::anyhow;
+    let env_args: Vec<String> = env::args().collect();
+    let program = env_args[0].clone();
+    let (args, executable, exec_args) = split_args(env_args);
+
+    let opts = su_options();
 
     // Replace -cn with -z, -mm with -M for supporting getopt_long
     let args = args
@@ -212,18 +290,28 @@ pub fn root_shell() -> Result<()> {
     let preserve_env = matches.opt_present("p");
     let mount_master = matches.opt_present("M");
     let use_fd_wrapper = !matches.opt_present("W");
+    let use_pty = !matches.opt_present("no-pty") && pty::wanted();
     let ksu_no_new_privs = matches.opt_present("ksu-no-new-privs");
 
-    let groups = matches
+    let context = matches.opt_str("Z");
+    let target_pid = matches
+        .opt_str("t")
+        .map(|pid| match pid.parse::<i32>() {
+            Result::Ok(pid) if pid > 0 => Ok(pid),
+            _ => Err(anyhow!("Invalid PID: {pid}")),
+        })
+        .transpose()?;
+
+    let mut groups = matches
         .opt_strs("G")
-        .into_iter()
-        .map(|g| g.parse::<u32>().map_err(|_| anyhow!("Invalid GID: {g}")))
+        .iter()
+        .map(|g| aid::resolve_gid(g))
         .collect::<Result<Vec<_>, _>>()?;
 
     // if -g provided, use it.
     let mut gid = matches
         .opt_str("g")
-        .map(|g| g.parse::<u32>().map_err(|_| anyhow!("Invalid GID: {g}")))
+        .map(|g| aid::resolve_gid(&g))
         .transpose()?;
 
     // otherwise, use the first gid of groups.
@@ -247,19 +335,21 @@ pub fn root_shell() -> Result<()> {
 
     // use current uid if no user specified, these has been done in kernel!
     let mut uid = getuid().as_raw();
+    let mut profile = None;
     if free_idx < matches.free.len() {
-        let name = &matches.free[free_idx];
-        uid = unsafe {
-            let pw = CString::new(name.as_str())
-                .ok()
-                .and_then(|c_name| libc::getpwnam(c_name.as_ptr()).as_ref());
-
-            pw.map_or_else(|| name.parse::<u32>().unwrap_or(0), |pw| pw.pw_uid)
-        }
+        uid = aid::resolve_uid(&matches.free[free_idx])?;
+    } else {
+        profile = Some(ProfileIdentity::current()?);
     }
 
-    // if there is no gid provided, use uid.
-    let gid = gid.unwrap_or(uid);
+    // if there is no gid provided, use the profile's or uid.
+    let gid = gid.unwrap_or_else(|| profile.as_ref().map_or(uid, |p| p.gid));
+    if !matches.opt_present("G")
+        && let Some(profile) = &profile
+    {
+        groups.clone_from(&profile.groups);
+    }
+    let caps = profile.map(|p| p.caps);
     let executable = executable.as_ref().unwrap_or(&shell);
     // https://github.com/topjohnwu/Magisk/blob/master/native/src/su/su_daemon.cpp#L408
     let arg0 = if is_login { "-" } else { executable };
@@ -307,25 +397,107 @@ pub fn root_shell() -> Result<()> {
             umask(0o22);
             utils::switch_cgroups();
 
-            // switch to global mount namespace
-            if mount_master {
+            // switch to the target's or the global mount namespace
+            if let Some(pid) = target_pid {
+                utils::switch_mnt_ns(pid).map_err(std::io::Error::other)?;
+            } else if mount_master {
                 let _ = utils::switch_mnt_ns(1);
             }
 
-            if use_fd_wrapper {
+            // with a pty, the session gets our own terminal and we wrap the caller's instead
+            if use_fd_wrapper && !use_pty {
                 wrap_tty(0);
                 wrap_tty(1);
                 wrap_tty(2);
             }
 
-            set_identity(uid, gid, &groups);
+            // applied by the kernel on exec, so it must be written before dropping root
+            if let Some(context) = &context {
+                std::fs::write("/proc/thread-self/attr/exec", context)?;
+            }
+
+            set_identity(uid, gid, &groups, caps);
 
             Result::Ok(())
         })
     };
 
+    let command_line = std::iter::once(executable.as_str())
+        .chain(args.iter().map(String::as_str))
+        .collect::<Vec<_>>()
+        .join(" ");
     command.args(args).arg0(arg0);
-    Err(command.exec().into())
+
+    // stay around as the parent, so the audit log can tell how the session ended
+    let audit = sulog::SessionAudit::start(uid, command_line);
+    let code = if use_pty {
+        if use_fd_wrapper {
+            wrap_tty(0);
+            wrap_tty(1);
+            wrap_tty(2);
+        }
+        pty::run_session(command)?
+    } else {
+        run_attached(command)?
+    };
+    audit.finish(code);
+    std::process::exit(code);
+}
+
+static SESSION_PID: AtomicI32 = AtomicI32::new(0);
+
+extern "C" fn forward_signal(sig: c_int) {
+    let pid = SESSION_PID.load(AtomicOrdering::Relaxed);
+    if pid > 0 {
+        unsafe { libc::kill(pid, sig) };
+    }
+}
+
+// run the session on our own stdio and wait for it, like system(3)
+fn run_attached(mut command: Command) -> Result<i32> {
+    let child = command.spawn().context("spawn shell")?;
+    let pid = child.id() as libc::pid_t;
+    SESSION_PID.store(pid, AtomicOrdering::Relaxed);
+    unsafe {
+        // the terminal delivers these to the shell's process group already
+        libc::signal(libc::SIGINT, libc::SIG_IGN);
+        libc::signal(libc::SIGQUIT, libc::SIG_IGN);
+        // we stop once the session reports it stopped, see wait_attached
+        libc::signal(libc::SIGTSTP, libc::SIG_IGN);
+        libc::signal(
+            libc::SIGTERM,
+            forward_signal as *const () as libc::sighandler_t,
+        );
+        libc::signal(
+            libc::SIGHUP,
+            forward_signal as *const () as libc::sighandler_t,
+        );
+    }
+    wait_attached(pid)
+}
+
+// A stopped session stops us too, so the calling shell sees the job stopped instead of
+// waiting on us forever, and continuing us continues the session.
+fn wait_attached(pid: libc::pid_t) -> Result<i32> {
+    use std::os::unix::process::ExitStatusExt;
+    loop {
+        let mut status = 0;
+        if unsafe { libc::waitpid(pid, &raw mut status, libc::WUNTRACED) } < 0 {
+            let err = std::io::Error::last_os_error();
+            if err.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            return Err(err).context("wait for shell");
+        }
+        if !libc::WIFSTOPPED(status) {
+            return Ok(pty::exit_code(std::process::ExitStatus::from_raw(status)));
+        }
+        unsafe {
+            libc::raise(libc::SIGSTOP);
+            // a shell with job control runs in its own process group and misses our SIGCONT
+            libc::kill(pid, libc::SIGCONT);
+        }
+    }
 }
 
 fn add_path_to_env(path: &str) -> Result<()> {
@@ -337,3 +509,104 @@ fn add_path_to_env(path: &str) -> Result<()> {
     unsafe { env::set_var("PATH", new_path_env) };
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(ToString::to_string).collect()
+    }
+
+    #[test]
+    fn clustered_short_options() {
+        assert!(takes_value("-lg"));
+        assert!(!takes_value("-g1000"));
+        assert!(!takes_value("-gl"));
+        assert!(takes_value("-Z"));
+        assert!(!takes_value("-l"));
+
+        let (opts, executable, exec_args) = split_args(args(&["su", "-lg", "1000", "root", "id"]));
+        assert_eq!(opts, args(&["su", "-lg", "1000", "root"]));
+        assert_eq!(executable.as_deref(), Some("id"));
+        assert_eq!(exec_args, Some(vec![]));
+
+        let (opts, executable, _) = split_args(args(&["su", "-g1000", "root", "id", "-u"]));
+        assert_eq!(opts, args(&["su", "-g1000", "root"]));
+        assert_eq!(executable.as_deref(), Some("id"));
+
+        let matches = su_options().parse(&args(&["-lg", "1000"])).unwrap();
+        assert!(matches.opt_present("l"));
+        assert_eq!(matches.opt_str("g").as_deref(), Some("1000"));
+    }
+
+    #[test]
+    fn long_option_with_equals() {
+        assert!(takes_value("--group"));
+        assert!(!takes_value("--group=1000"));
+
+        let (opts, executable, exec_args) =
+            split_args(args(&["su", "--group=1000", "shell", "ls", "-l"]));
+        assert_eq!(opts, args(&["su", "--group=1000", "shell"]));
+        assert_eq!(executable.as_deref(), Some("ls"));
+        assert_eq!(exec_args, Some(args(&["-l"])));
+
+        let matches = su_options().parse(&args(&["--group=inet"])).unwrap();
+        assert_eq!(matches.opt_str("g").as_deref(), Some("inet"));
+    }
+
+    #[test]
+    fn command_joins_rest() {
+        let (opts, executable, exec_args) =
+            split_args(args(&["su", "-c", "echo", "a", "-b", "root"]));
+        assert_eq!(opts, args(&["su", "-c", "echo a -b root"]));
+        assert_eq!(executable, None);
+        assert_eq!(exec_args, None);
+
+        let (opts, _, _) = split_args(args(&["su", "-p", "--command", "id", "-u"]));
+        assert_eq!(opts, args(&["su", "-p", "-c", "id -u"]));
+
+        let (opts, _, _) = split_args(args(&["su", "-c"]));
+        assert_eq!(opts, args(&["su", "-c"]));
+    }
+
+    #[test]
+    fn user_after_options() {
+        let (opts, executable, exec_args) = split_args(args(&["su", "-g", "1000", "shell"]));
+        assert_eq!(opts, args(&["su", "-g", "1000", "shell"]));
+        assert_eq!(executable, None);
+        assert_eq!(exec_args, None);
+
+        let matches = su_options().parse(&opts[1..]).unwrap();
+        assert_eq!(matches.opt_str("g").as_deref(), Some("1000"));
+        assert_eq!(matches.free, args(&["shell"]));
+    }
+
+    #[test]
+    fn context_and_target_values() {
+        let (opts, executable, exec_args) = split_args(args(&[
+            "su",
+            "-Z",
+            "u:r:su:s0",
+            "-t",
+            "1234",
+            "root",
+            "sh",
+            "-x",
+        ]));
+        assert_eq!(opts, args(&["su", "-Z", "u:r:su:s0", "-t", "1234", "root"]));
+        assert_eq!(executable.as_deref(), Some("sh"));
+        assert_eq!(exec_args, Some(args(&["-x"])));
+
+        let matches = su_options().parse(&opts[1..]).unwrap();
+        assert_eq!(matches.opt_str("Z").as_deref(), Some("u:r:su:s0"));
+        assert_eq!(matches.opt_str("t").as_deref(), Some("1234"));
+        assert_eq!(matches.free, args(&["root"]));
+
+        let matches = su_options()
+            .parse(&args(&["--context=u:r:shell:s0", "--target", "1"]))
+            .unwrap();
+        assert_eq!(matches.opt_str("Z").as_deref(), Some("u:r:shell:s0"));
+        assert_eq!(matches.opt_str("t").as_deref(), Some("1"));
+    }
+}
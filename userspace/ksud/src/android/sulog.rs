@@ -13,7 +13,7 @@ use std::{
     path::{Path, PathBuf},
     process::{Command, Stdio},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use anyhow::{Context, Result, bail, ensure};
@@ -753,6 +753,101 @@ fn run_sulog_session(restart_count: u64) -> Result<SessionExitReason> {
     }
 }
 
+fn monotonic_ns() -> u64 {
+    let mut ts = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // same clock as the kernel's ktime_get_ns(), so records can be correlated with grants
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &raw mut ts) };
+    u64::try_from(ts.tv_sec).unwrap_or(0) * 1_000_000_000 + u64::try_from(ts.tv_nsec).unwrap_or(0)
+}
+
+// the kernel has already switched our credentials, so the caller is our parent
+fn caller_uid() -> Option<u32> {
+    let status = fs::read_to_string(format!(
+        "/proc/{}/status",
+        std::os::unix::process::parent_id()
+    ))
+    .ok()?;
+    status
+        .lines()
+        .find_map(|line| line.strip_prefix("Uid:"))?
+        .split_whitespace()
+        .next()?
+        .parse()
+        .ok()
+}
+
+fn tty_name() -> String {
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return "none".to_string();
+    }
+    fs::read_link("/proc/self/fd/0")
+        .map_or_else(|_| "unknown".to_string(), |path| path.display().to_string())
+}
+
+fn append_audit_line(line: &str) -> Result<()> {
+    ensure_private_dir_exists(Path::new(defs::LOG_DIR))?;
+    let config = ensure_sulog_config()?;
+    let (_, _, mut writer) = open_log_writer_for_day(&current_log_day(), config.max_file_size)?;
+    writer.write_all(line.as_bytes())?;
+    writer.write_all(b"\n")?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Start and end records of a `ksud su` session, written next to the sulogd records.
+/// Failing to write them never prevents the session.
+pub struct SessionAudit {
+    started: Instant,
+    pid: u32,
+    caller_uid: Option<u32>,
+    target_uid: u32,
+    tty: String,
+    command: String,
+}
+
+impl SessionAudit {
+    pub fn start(target_uid: u32, command: String) -> Self {
+        let audit = Self {
+            started: Instant::now(),
+            pid: std::process::id(),
+            caller_uid: caller_uid(),
+            target_uid,
+            tty: tty_name(),
+            command,
+        };
+        audit.write("su_session_start", "");
+        audit
+    }
+
+    pub fn finish(self, exit_status: i32) {
+        let duration_ms = self.started.elapsed().as_millis();
+        self.write(
+            "su_session_end",
+            &format!(" duration_ms={duration_ms} exit_status={exit_status}"),
+        );
+    }
+
+    fn write(&self, event: &str, extra: &str) {
+        let caller_uid = self
+            .caller_uid
+            .map_or_else(|| "unknown".to_string(), |uid| uid.to_string());
+        let line = format!(
+            "ts_ns={} type={event} pid={} caller_uid={caller_uid} target_uid={} tty=\"{}\" command=\"{}\"{extra}",
+            monotonic_ns(),
+            self.pid,
+            self.target_uid,
+            escape_field(&self.tty),
+            escape_field(&self.command),
+        );
+        if let Err(err) = append_audit_line(&line) {
+            log::warn!("failed to write su session audit record: {err:#}");
+        }
+    }
+}
+
 pub fn run_sulogd() -> Result<()> {
     let Some(_lock_guard) = SulogdLockGuard::acquire()? else {
         log::info!("sulogd lock is held, skipping start");
@@ -1,17 +1,77 @@
-use std::{collections::HashMap, fs, path::Path};
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet},
+    fs,
+    path::Path,
+};
 
 use anyhow::Result;
-use log::info;
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    android::ksucalls,
+    android::{ksucalls, utils},
     defs::{self, MountInfo},
 };
 
+const PER_USER_RANGE: u32 = 100_000;
+
+/// Restricts an entry to some apps, matched by app id in every user
+#[derive(Serialize, Default, Deserialize, Clone)]
+struct Scope {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    packages: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    uids: Vec<u32>,
+}
+
+impl Scope {
+    fn is_empty(&self) -> bool {
+        self.packages.is_empty() && self.uids.is_empty()
+    }
+
+    fn resolve_appids(&self, packages: &HashMap<String, u32>) -> Vec<u32> {
+        let mut appids: Vec<u32> = self.uids.iter().map(|uid| uid % PER_USER_RANGE).collect();
+        for package in &self.packages {
+            match packages.get(package) {
+                Some(uid) => appids.push(uid % PER_USER_RANGE),
+                None => warn!("umount config: package {package} is not installed"),
+            }
+        }
+        appids.sort_unstable();
+        appids.dedup();
+        appids
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScopedEntry {
+    flags: u32,
+    #[serde(flatten)]
+    scope: Scope,
+}
+
 #[derive(Serialize, Default, Deserialize)]
 struct Config {
-    paths: HashMap<String, u32>,
+    paths: BTreeMap<String, u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    scoped: BTreeMap<String, ScopedEntry>,
+}
+
+#[derive(Serialize)]
+struct ConfigEntry {
+    path: String,
+    flags: u32,
+    #[serde(flatten)]
+    scope: Scope,
+}
+
+#[derive(Serialize)]
+struct ResolvedEntry {
+    path: String,
+    flags: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    appid: Option<u32>,
 }
 
 fn read_config() -> Result<Config> {
@@ -27,42 +87,236 @@ fn write_config(config: &Config) -> Result<()> {
     Ok(())
 }
 
+/// Whether a configured path is a glob/prefix rule rather than an exact mount point.
+/// `*` and `?` match within a single path component, `**` matches any number of components.
+fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?'])
+}
+
+fn match_component(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.first(), name.first()) {
+        (None, None) => true,
+        (Some(b'*'), _) => {
+            match_component(&pattern[1..], name)
+                || (!name.is_empty() && match_component(pattern, &name[1..]))
+        }
+        (Some(b'?'), Some(_)) => match_component(&pattern[1..], &name[1..]),
+        (Some(p), Some(n)) if p == n => match_component(&pattern[1..], &name[1..]),
+        _ => false,
+    }
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match pattern.split_first() {
+        None => path.is_empty(),
+        Some((&"**", rest)) => (0..=path.len()).any(|skip| match_components(rest, &path[skip..])),
+        Some((first, rest)) => path.split_first().is_some_and(|(name, path_rest)| {
+            match_component(first.as_bytes(), name.as_bytes()) && match_components(rest, path_rest)
+        }),
+    }
+}
+
+fn glob_match(pattern: &str, path: &str) -> bool {
+    let pattern: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    let path: Vec<&str> = path.split('/').filter(|c| !c.is_empty()).collect();
+    match_components(&pattern, &path)
+}
+
+// exact paths first, then patterns with more literal characters, so the most specific rule
+// decides the flags of a mount point that several rules match
+fn specificity(path: &str) -> (bool, Reverse<usize>) {
+    let literal = path
+        .chars()
+        .filter(|c| !matches!(c, '*' | '?' | '/'))
+        .count();
+    (is_pattern(path), Reverse(literal))
+}
+
+fn expand_path<'a>(path: &'a str, mount_points: &'a [String]) -> Vec<&'a str> {
+    if !is_pattern(path) {
+        return vec![path];
+    }
+    mount_points
+        .iter()
+        .map(String::as_str)
+        .filter(|mount_point| glob_match(path, mount_point))
+        .collect()
+}
+
+/// Resolve the config into concrete umount entries, expanding patterns against the current
+/// mount table and package selectors against packages.list.
+fn resolve_config(config: &Config) -> Result<Vec<ResolvedEntry>> {
+    let has_pattern = config
+        .paths
+        .keys()
+        .chain(config.scoped.keys())
+        .any(|p| is_pattern(p));
+    let mount_points = if has_pattern {
+        utils::get_mount_points()?
+    } else {
+        Vec::new()
+    };
+    let packages = if config.scoped.values().any(|e| !e.scope.packages.is_empty()) {
+        utils::read_packages_list()?
+    } else {
+        HashMap::new()
+    };
+
+    let mut rules: Vec<(&String, u32, Vec<Option<u32>>)> = config
+        .paths
+        .iter()
+        .map(|(path, flags)| (path, *flags, vec![None]))
+        .chain(config.scoped.iter().map(|(path, entry)| {
+            let appids = entry.scope.resolve_appids(&packages);
+            (path, entry.flags, appids.into_iter().map(Some).collect())
+        }))
+        .collect();
+    // stable, ties keep the sorted order of the config maps
+    rules.sort_by_key(|(path, _, _)| specificity(path));
+
+    let mut entries = Vec::new();
+    let mut seen = HashSet::new();
+    for (path, flags, appids) in rules {
+        for mount_point in expand_path(path, &mount_points) {
+            for appid in &appids {
+                if seen.insert((mount_point, *appid)) {
+                    entries.push(ResolvedEntry {
+                        path: mount_point.to_string(),
+                        flags,
+                        appid: *appid,
+                    });
+                }
+            }
+        }
+    }
+
+    if has_pattern {
+        // the kernel prepends new entries, so adding them in mount order
+        // makes nested mounts get detached before their parents
+        entries.sort_by_key(|e| mount_points.iter().position(|m| *m == e.path));
+    }
+
+    Ok(entries)
+}
+
+fn os_error(e: &anyhow::Error) -> Option<i32> {
+    e.downcast_ref::<std::io::Error>()
+        .and_then(std::io::Error::raw_os_error)
+}
+
+/// Push configured entries to the kernel umount list.
+/// Entries already present in the kernel list are skipped, so this can be called again
+/// after modules are mounted to pick up new pattern matches. An entry the kernel rejects
+/// doesn't keep the others from being added.
 pub fn load_umount_config() -> Result<()> {
     let json_raw = read_config()?;
     let mut count = 0;
+    let mut scoped_supported = true;
 
-    for (path, flags) in json_raw.paths {
-        ksucalls::umount_list_add(path.as_str(), flags)?;
-        count += 1;
+    for entry in resolve_config(&json_raw)? {
+        let result = match entry.appid {
+            Some(_) if !scoped_supported => continue,
+            Some(appid) => {
+                ksucalls::umount_list_add_scoped(entry.path.as_str(), entry.flags, appid)
+            }
+            None => ksucalls::umount_list_add(entry.path.as_str(), entry.flags),
+        };
+        match result {
+            Ok(()) => count += 1,
+            Err(e) if os_error(&e) == Some(libc::EEXIST) => {}
+            // kernels without KSU_UMOUNT_ADD_SCOPED reject the mode itself
+            Err(e) if entry.appid.is_some() && os_error(&e) == Some(libc::EINVAL) => {
+                warn!("Kernel doesn't support app scoped umount entries, skipping them");
+                scoped_supported = false;
+            }
+            Err(e) => warn!("Failed to add umount entry {}: {e:#}", entry.path),
+        }
     }
     info!("Loaded {count} umount entries from config");
     Ok(())
 }
 
-pub fn list_umount() -> Result<()> {
+pub fn list_umount(expand: bool) -> Result<()> {
     let json_raw = read_config()?;
 
-    let output: Vec<MountInfo> = json_raw
-        .paths
-        .into_iter()
-        .map(|(path, flags)| MountInfo { path, flags })
-        .collect();
-
-    let json_output = serde_json::to_string(&output)?;
+    let json_output = if expand {
+        serde_json::to_string(&resolve_config(&json_raw)?)?
+    } else {
+        let output: Vec<ConfigEntry> = json_raw
+            .paths
+            .into_iter()
+            .map(|(path, flags)| ConfigEntry {
+                path,
+                flags,
+                scope: Scope::default(),
+            })
+            .chain(
+                json_raw
+                    .scoped
+                    .into_iter()
+                    .map(|(path, entry)| ConfigEntry {
+                        path,
+                        flags: entry.flags,
+                        scope: entry.scope,
+                    }),
+            )
+            .collect();
+        serde_json::to_string(&output)?
+    };
     println!("{json_output}");
 
     Ok(())
 }
 
-pub fn add_umount(target_path: &str, flags: u32) -> Result<()> {
+pub fn add_umount(
+    target_path: &str,
+    flags: u32,
+    packages: Vec<String>,
+    uids: Vec<u32>,
+) -> Result<()> {
     let mut json_raw = read_config()?;
-    json_raw.paths.insert(target_path.to_string(), flags);
+    if packages.is_empty() && uids.is_empty() {
+        json_raw.paths.insert(target_path.to_string(), flags);
+    } else {
+        let entry = json_raw
+            .scoped
+            .entry(target_path.to_string())
+            .or_insert_with(|| ScopedEntry {
+                flags,
+                scope: Scope::default(),
+            });
+        entry.flags = flags;
+        for package in packages {
+            if !entry.scope.packages.contains(&package) {
+                entry.scope.packages.push(package);
+            }
+        }
+        for uid in uids {
+            if !entry.scope.uids.contains(&uid) {
+                entry.scope.uids.push(uid);
+            }
+        }
+    }
     write_config(&json_raw)
 }
 
-pub fn del_umount(target_path: &str) -> Result<()> {
+/// Delete an entry, or only the given package/uid selectors of a scoped entry
+pub fn del_umount(target_path: &str, packages: &[String], uids: &[u32]) -> Result<()> {
     let mut json_raw = read_config()?;
-    if json_raw.paths.remove(target_path).is_some() {
+    let changed = if packages.is_empty() && uids.is_empty() {
+        let global = json_raw.paths.remove(target_path).is_some();
+        json_raw.scoped.remove(target_path).is_some() || global
+    } else if let Some(entry) = json_raw.scoped.get_mut(target_path) {
+        entry.scope.packages.retain(|p| !packages.contains(p));
+        entry.scope.uids.retain(|u| !uids.contains(u));
+        if entry.scope.is_empty() {
+            json_raw.scoped.remove(target_path);
+        }
+        true
+    } else {
+        false
+    };
+    if changed {
         write_config(&json_raw)?;
     }
     Ok(())
@@ -71,9 +325,154 @@ pub fn del_umount(target_path: &str) -> Result<()> {
 pub fn wipe_umount() -> Result<()> {
     let mut json_raw = read_config()?;
     json_raw.paths.clear();
+    json_raw.scoped.clear();
     write_config(&json_raw)
 }
 
+#[derive(Serialize, Default)]
+struct UmountDiff {
+    only_on_disk: Vec<MountInfo>,
+    only_in_kernel: Vec<MountInfo>,
+    flags_mismatch: Vec<FlagsMismatch>,
+    /// Paths of app scoped entries, left out since the kernel list doesn't report app ids
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    scoped: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct FlagsMismatch {
+    path: String,
+    disk_flags: u32,
+    kernel_flags: u32,
+}
+
+fn is_scoped(config: &Config, path: &str) -> bool {
+    config
+        .scoped
+        .keys()
+        .any(|scoped| scoped == path || (is_pattern(scoped) && glob_match(scoped, path)))
+}
+
+/// Compare the resolved config with the live kernel umount list by path and flags.
+/// Only global entries are compared, a path with an app scoped entry is reported as such.
+fn compute_diff(config: &Config) -> Result<UmountDiff> {
+    let mut scoped = BTreeSet::new();
+    let mut disk = BTreeMap::new();
+    for entry in resolve_config(config)? {
+        if entry.appid.is_some() || is_scoped(config, &entry.path) {
+            scoped.insert(entry.path);
+        } else {
+            disk.entry(entry.path).or_insert(entry.flags);
+        }
+    }
+    let mut kernel = BTreeMap::new();
+    for entry in ksucalls::umount_list_list()? {
+        if is_scoped(config, &entry.path) {
+            scoped.insert(entry.path);
+        } else {
+            kernel.entry(entry.path).or_insert(entry.flags);
+        }
+    }
+
+    let mut diff = UmountDiff {
+        scoped: scoped.into_iter().collect(),
+        ..UmountDiff::default()
+    };
+    for (path, &flags) in &disk {
+        match kernel.get(path) {
+            None => diff.only_on_disk.push(MountInfo {
+                path: path.clone(),
+                flags,
+            }),
+            Some(&kernel_flags) if kernel_flags != flags => {
+                diff.flags_mismatch.push(FlagsMismatch {
+                    path: path.clone(),
+                    disk_flags: flags,
+                    kernel_flags,
+                });
+            }
+            Some(_) => {}
+        }
+    }
+    for (path, &flags) in &kernel {
+        if !disk.contains_key(path) {
+            diff.only_in_kernel.push(MountInfo {
+                path: path.clone(),
+                flags,
+            });
+        }
+    }
+    Ok(diff)
+}
+
+pub fn diff_umount() -> Result<()> {
+    let json_raw = read_config()?;
+    let diff = compute_diff(&json_raw)?;
+    println!("{}", serde_json::to_string(&diff)?);
+    Ok(())
+}
+
+/// Reconcile the config file and the kernel umount list.
+/// By default the kernel is updated from the config; with `from_kernel` the config is
+/// updated from the kernel. Extra entries on the target side are only removed with `prune`.
+/// App scoped entries are never synced in either direction.
+pub fn sync_umount(from_kernel: bool, prune: bool) -> Result<()> {
+    let mut json_raw = read_config()?;
+    let diff = compute_diff(&json_raw)?;
+
+    if from_kernel {
+        let added = diff.only_in_kernel.len();
+        let mut updated = 0;
+        let mut removed = 0;
+        for entry in diff.only_in_kernel {
+            json_raw.paths.insert(entry.path, entry.flags);
+        }
+        for mismatch in diff.flags_mismatch {
+            if let Some(flags) = json_raw.paths.get_mut(&mismatch.path) {
+                *flags = mismatch.kernel_flags;
+                updated += 1;
+            } else {
+                warn!("{} is matched by a pattern, keep its flags", mismatch.path);
+            }
+        }
+        if prune {
+            for entry in diff.only_on_disk {
+                if json_raw.paths.remove(&entry.path).is_some() {
+                    removed += 1;
+                } else {
+                    warn!("{} is matched by a pattern, keep it", entry.path);
+                }
+            }
+        }
+        write_config(&json_raw)?;
+        println!("Synced from kernel: {added} added, {updated} updated, {removed} removed");
+    } else {
+        let added = diff.only_on_disk.len();
+        let updated = diff.flags_mismatch.len();
+        let mut removed = 0;
+        // re-added with the configured flags by load_umount_config below
+        for mismatch in &diff.flags_mismatch {
+            ksucalls::umount_list_del(&mismatch.path)?;
+        }
+        if prune {
+            for entry in &diff.only_in_kernel {
+                ksucalls::umount_list_del(&entry.path)?;
+                removed += 1;
+            }
+        }
+        load_umount_config()?;
+        println!("Synced to kernel: {added} added, {updated} updated, {removed} removed");
+    }
+    if !diff.scoped.is_empty() {
+        println!(
+            "{} app scoped paths left alone, the kernel list doesn't report app ids",
+            diff.scoped.len()
+        );
+    }
+
+    Ok(())
+}
+
 fn ensure_config() -> Result<()> {
     let path = Path::new(defs::UMOUNT_CONFIG_PATH);
 
@@ -91,3 +490,65 @@ fn ensure_config() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn most_specific_rule_first() {
+        let mut paths = vec![
+            "/data/**",
+            "/data/adb/modules/*",
+            "/data/adb/*/foo",
+            "/data/adb/modules/foo",
+            "/mnt/*",
+        ];
+        paths.sort_by_key(|path| specificity(path));
+        assert_eq!(
+            paths,
+            [
+                "/data/adb/modules/foo",
+                "/data/adb/modules/*",
+                "/data/adb/*/foo",
+                "/data/**",
+                "/mnt/*",
+            ]
+        );
+    }
+
+    #[test]
+    fn scoped_paths() {
+        let mut config = Config::default();
+        config.paths.insert("/system/etc/hosts".to_string(), 0);
+        for path in ["/data/adb/modules/foo", "/mnt/vendor/*"] {
+            config.scoped.insert(
+                path.to_string(),
+                ScopedEntry {
+                    flags: 2,
+                    scope: Scope {
+                        packages: Vec::new(),
+                        uids: vec![10_123],
+                    },
+                },
+            );
+        }
+        assert!(is_scoped(&config, "/data/adb/modules/foo"));
+        assert!(is_scoped(&config, "/mnt/vendor/persist"));
+        assert!(!is_scoped(&config, "/system/etc/hosts"));
+        assert!(!is_scoped(&config, "/data/adb/modules/bar"));
+    }
+
+    #[test]
+    fn glob() {
+        assert!(glob_match("/data/adb/modules/*", "/data/adb/modules/foo"));
+        assert!(!glob_match(
+            "/data/adb/modules/*",
+            "/data/adb/modules/foo/bar"
+        ));
+        assert!(glob_match("/data/**", "/data/adb/modules/foo/bar"));
+        assert!(glob_match("/data/**", "/data"));
+        assert!(glob_match("/sys/fs/?use", "/sys/fs/fuse"));
+        assert!(!glob_match("/sys/fs/?use", "/sys/fs/use"));
+    }
+}
@@ -1,9 +1,35 @@
-use std::{fs, process::Command};
+use std::{
+    collections::HashSet,
+    fs,
+    path::Path,
+    process::Command,
+    thread,
+    time::{Duration, Instant},
+};
 
 use anyhow::Result;
 use log::{info, warn};
 
-use crate::android::utils;
+use crate::{
+    android::{module, utils},
+    defs,
+};
+
+// holding a ksu fd in one of these means the framework has to be restarted
+const FRAMEWORK_PROCESSES: &[&str] = &["system_server", "zygote", "zygote64", "webview_zygote"];
+
+pub struct Options {
+    /// Only print what would be done.
+    pub dry_run: bool,
+    /// How long processes get to exit after SIGTERM before they are killed.
+    pub grace: Duration,
+    /// Skip `stop`/`start` unless a framework process holds a ksu fd.
+    pub keep_framework: bool,
+    /// Also undo module mounts and properties, see [`cleanup`].
+    pub cleanup: bool,
+    /// With `cleanup`, also run the uninstall hooks of the modules, which stay installed.
+    pub run_uninstall: bool,
+}
 
 /// Find PIDs of processes running in the KernelSU su domain (u:r:ksu:s0).
 /// Returns a list of PIDs excluding our own.
@@ -83,6 +109,71 @@ fn kill_pids(pids: &[i32], signal: i32) {
     }
 }
 
+fn is_alive(pid: i32) -> bool {
+    unsafe { libc::kill(pid, 0) == 0 }
+}
+
+/// Send SIGTERM, then SIGKILL whatever is still alive after `grace`.
+fn terminate_pids(pids: &[i32], grace: Duration) {
+    if grace.is_zero() {
+        kill_pids(pids, libc::SIGKILL);
+        return;
+    }
+
+    kill_pids(pids, libc::SIGTERM);
+    let deadline = Instant::now() + grace;
+    let mut alive: Vec<i32> = pids.to_vec();
+    while !alive.is_empty() && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(100));
+        alive.retain(|&pid| is_alive(pid));
+    }
+    if !alive.is_empty() {
+        info!(
+            "unload: {} processes ignored SIGTERM, sending SIGKILL",
+            alive.len()
+        );
+        kill_pids(&alive, libc::SIGKILL);
+    }
+}
+
+fn cmdline(pid: i32) -> String {
+    let cmdline = fs::read(format!("/proc/{pid}/cmdline")).unwrap_or_default();
+    let cmdline = cmdline
+        .split(|b| *b == 0)
+        .filter(|arg| !arg.is_empty())
+        .map(String::from_utf8_lossy)
+        .collect::<Vec<_>>()
+        .join(" ");
+    if cmdline.is_empty() {
+        // kernel threads and zombies
+        fs::read_to_string(format!("/proc/{pid}/comm"))
+            .map(|comm| format!("[{}]", comm.trim()))
+            .unwrap_or_default()
+    } else {
+        cmdline
+    }
+}
+
+fn is_framework_process(pid: i32) -> bool {
+    let cmdline = cmdline(pid);
+    let name = cmdline.split_whitespace().next().unwrap_or_default();
+    FRAMEWORK_PROCESSES.contains(&name)
+}
+
+fn print_victims(su_pids: &[i32], fd_pids: &[i32]) {
+    let mut pids: Vec<i32> = su_pids.iter().chain(fd_pids).copied().collect();
+    pids.sort_unstable();
+    pids.dedup();
+    for pid in pids {
+        let reason = match (su_pids.contains(&pid), fd_pids.contains(&pid)) {
+            (true, true) => "su domain, ksu fd",
+            (true, false) => "su domain",
+            _ => "ksu fd",
+        };
+        println!("{pid:>7}  {reason:<17}  {}", cmdline(pid));
+    }
+}
+
 /// Close all ksu_driver and ksu_fdwrapper fds held by the current process.
 fn close_ksu_fds() {
     let Ok(entries) = fs::read_dir("/proc/self/fd") else {
@@ -105,52 +196,161 @@ fn close_ksu_fds() {
     }
 }
 
-pub fn unload() -> Result<()> {
+struct MountEntry {
+    root: String,
+    mount_point: String,
+    source: String,
+    super_options: String,
+}
+
+fn read_mountinfo() -> Vec<MountEntry> {
+    let Ok(content) = fs::read_to_string("/proc/self/mountinfo") else {
+        return Vec::new();
+    };
+    content
+        .lines()
+        .filter_map(|line| {
+            let (fields, fs_fields) = line.split_once(" - ")?;
+            let mut fields = fields.split(' ');
+            let root = utils::unescape_mount_path(fields.nth(3)?);
+            let mount_point = utils::unescape_mount_path(fields.next()?);
+            let mut fs_fields = fs_fields.split(' ').skip(1);
+            Some(MountEntry {
+                root,
+                mount_point,
+                source: fs_fields.next().unwrap_or_default().to_string(),
+                super_options: fs_fields.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect()
+}
+
+fn is_under(path: &str, dir: &str) -> bool {
+    Path::new(path).starts_with(dir)
+}
+
+/// Mount points that come from modules: those recorded when modules were mounted at boot,
+/// binds out of the modules directory and overlays and tmpfs using it.
+fn find_module_mounts() -> Vec<String> {
+    let module_dir = defs::MODULE_DIR.trim_end_matches('/');
+    // binds from /data show the path relative to the data partition as root
+    let module_root = module_dir.trim_start_matches("/data");
+    let recorded: HashSet<String> = module::mount::read_mounts().into_iter().collect();
+    let mut mounts: Vec<String> = read_mountinfo()
+        .into_iter()
+        .filter(|entry| {
+            recorded.contains(&entry.mount_point)
+                || is_under(&entry.root, module_root)
+                || is_under(&entry.source, module_dir)
+                || entry
+                    .super_options
+                    .split([',', ':', '='])
+                    .any(|option| is_under(option, module_dir))
+        })
+        .map(|entry| entry.mount_point)
+        .collect();
+    // latest mounts last in mountinfo, undo them first
+    mounts.reverse();
+    mounts.dedup();
+    mounts
+}
+
+fn unmount(mount_point: &str) -> std::io::Result<()> {
+    let path = std::ffi::CString::new(mount_point)?;
+    if unsafe { libc::umount2(path.as_ptr(), libc::MNT_DETACH) } != 0 {
+        return Err(std::io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Reverse what boot did, as far as possible without rebooting: restore properties the
+/// modules changed, unmount their mounts and, if asked, run their uninstall hooks.
+fn cleanup(run_uninstall: bool) {
+    if let Err(e) = utils::switch_mnt_ns(1) {
+        warn!("unload: switch to init mount namespace failed: {e}");
+    }
+
+    if let Err(e) = module::revert_active_modules(run_uninstall) {
+        warn!("unload: revert modules failed: {e}");
+    }
+
+    for mount_point in find_module_mounts() {
+        match unmount(&mount_point) {
+            Ok(()) => info!("unload: unmounted {mount_point}"),
+            Err(e) => warn!("unload: unmount {mount_point} failed: {e}"),
+        }
+    }
+}
+
+pub fn unload(options: &Options) -> Result<()> {
+    let su_pids = find_su_domain_pids();
+    let fd_pids = find_ksu_fd_holders();
+    let restart_framework =
+        !options.keep_framework || fd_pids.iter().any(|&pid| is_framework_process(pid));
+
+    if options.dry_run {
+        if options.cleanup {
+            println!("would unmount:");
+            for mount_point in find_module_mounts() {
+                println!("  {mount_point}");
+            }
+        }
+        println!("would stop and restart Android services: {restart_framework}");
+        println!("would terminate:");
+        print_victims(&su_pids, &fd_pids);
+        return Ok(());
+    }
+
     info!("unload: starting KernelSU unload sequence");
 
     // 0. Switch cgroups so we don't get killed along with our parent shell
     utils::switch_cgroups();
 
     // 1. stop (Android init stop command - stops all services)
-    info!("unload: stopping Android services...");
-    let _ = Command::new("stop").status();
+    if restart_framework {
+        info!("unload: stopping Android services...");
+        let _ = Command::new("stop").status();
+    } else {
+        info!("unload: no framework process holds a ksu fd, keep Android services running");
+    }
 
-    // 2. Kill all su domain processes and processes holding ksu fds (except ourselves)
-    info!("unload: killing su domain processes...");
-    let su_pids = find_su_domain_pids();
-    if !su_pids.is_empty() {
+    // 2. Terminate all su domain processes and processes holding ksu fds (except ourselves),
+    // looking again since stopping services may have changed the picture
+    let mut pids = find_su_domain_pids();
+    pids.extend(find_ksu_fd_holders());
+    pids.sort_unstable();
+    pids.dedup();
+    if !pids.is_empty() {
         info!(
-            "unload: found {} su domain processes, sending SIGKILL",
-            su_pids.len()
+            "unload: terminating {} su domain processes and ksu fd holders",
+            pids.len()
         );
-        kill_pids(&su_pids, libc::SIGKILL);
+        terminate_pids(&pids, options.grace);
     }
 
-    info!("unload: killing processes holding ksu fds...");
-    let fd_pids = find_ksu_fd_holders();
-    if !fd_pids.is_empty() {
-        info!(
-            "unload: found {} processes holding ksu fds, sending SIGKILL",
-            fd_pids.len()
-        );
-        kill_pids(&fd_pids, libc::SIGKILL);
+    // 3. Undo module side effects while KernelSU is still there to run the hooks
+    if options.cleanup {
+        info!("unload: cleaning up module mounts and properties...");
+        cleanup(options.run_uninstall);
     }
 
-    // 3. Close all our own ksu_driver and ksu_fdwrapper fds
+    // 4. Close all our own ksu_driver and ksu_fdwrapper fds
     info!("unload: closing all ksu fds...");
     close_ksu_fds();
 
-    // 4. delete_module("kernelsu")
+    // 5. delete_module("kernelsu")
     info!("unload: removing kernelsu module...");
     if let Err(e) = rustix::system::delete_module(c"kernelsu", 0) {
         warn!("unload: delete_module kernelsu failed: {e}");
     }
 
-    // 5. start (Android init start command - restarts all services)
-    info!("unload: restarting Android services...");
-    let _ = Command::new("start").status();
+    // 6. start (Android init start command - restarts all services)
+    if restart_framework {
+        info!("unload: restarting Android services...");
+        let _ = Command::new("start").status();
+    }
 
-    // 6. Exit
+    // 7. Exit
     info!("unload: done, exiting ksud");
     std::process::exit(0);
 }
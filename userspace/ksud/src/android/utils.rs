@@ -1,6 +1,7 @@
 #[cfg(unix)]
 use std::os::unix::prelude::PermissionsExt;
 use std::{
+    collections::HashMap,
     fs::{File, OpenOptions, Permissions, create_dir_all, remove_file, set_permissions, write},
     io::{
         ErrorKind::{AlreadyExists, NotFound},
@@ -130,6 +131,78 @@ pub fn get_zip_uncompressed_size(zip_path: &str) -> Result<u64> {
     Ok(total)
 }
 
+/// Dump the kernel log buffer, like `dmesg`.
+pub fn read_kmsg() -> Result<String> {
+    const SYSLOG_ACTION_READ_ALL: i32 = 3;
+    const SYSLOG_ACTION_SIZE_BUFFER: i32 = 10;
+
+    let size = unsafe { libc::klogctl(SYSLOG_ACTION_SIZE_BUFFER, std::ptr::null_mut(), 0) };
+    if size <= 0 {
+        bail!("get kernel log size: {}", std::io::Error::last_os_error());
+    }
+    let mut buf = vec![0u8; size as usize];
+    let len = unsafe { libc::klogctl(SYSLOG_ACTION_READ_ALL, buf.as_mut_ptr().cast(), size) };
+    if len < 0 {
+        bail!("read kernel log: {}", std::io::Error::last_os_error());
+    }
+    buf.truncate(len as usize);
+    Ok(String::from_utf8_lossy(&buf).into_owned())
+}
+
+fn boot_id() -> String {
+    std::fs::read_to_string("/proc/sys/kernel/random/boot_id")
+        .unwrap_or_default()
+        .trim()
+        .to_string()
+}
+
+// the status saved during this boot, if any
+fn saved_lkm_status() -> Option<String> {
+    let content = std::fs::read_to_string(defs::LKM_STATUS_PATH).ok()?;
+    let (boot, status) = content.split_once('\n')?;
+    (boot == boot_id()).then(|| status.trim().to_string())
+}
+
+/// Copy the LKM status ksuinit reported to the kernel log to a file, before the log rotates.
+pub fn save_lkm_status() -> Result<()> {
+    if saved_lkm_status().is_some() {
+        return Ok(());
+    }
+    let kmsg = read_kmsg()?;
+    let Some(status) = ksuinit::find_lkm_status(&kmsg) else {
+        return Ok(());
+    };
+    write(defs::LKM_STATUS_PATH, format!("{}\n{status}\n", boot_id()))
+        .with_context(|| format!("write {}", defs::LKM_STATUS_PATH))
+}
+
+/// The LKM status ksuinit reported this boot, from the saved copy or else the kernel log.
+pub fn lkm_status() -> Option<String> {
+    if let Some(status) = saved_lkm_status() {
+        return Some(status);
+    }
+    match read_kmsg() {
+        Ok(kmsg) => ksuinit::find_lkm_status(&kmsg).map(str::to_owned),
+        Err(e) => {
+            log::warn!("{e:#}");
+            None
+        }
+    }
+}
+
+/// The LKM status ksuinit wrote to pstore during the previous boot. This is where a failure
+/// is left when the LKM didn't load, as ksud doesn't run then.
+pub fn previous_lkm_status() -> Option<String> {
+    let entries = std::fs::read_dir(defs::PSTORE_DIR).ok()?;
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with("pmsg-"))
+        .filter_map(|entry| std::fs::read(entry.path()).ok())
+        .find_map(|pmsg| {
+            ksuinit::find_lkm_status(&String::from_utf8_lossy(&pmsg)).map(str::to_owned)
+        })
+}
+
 pub fn switch_mnt_ns(pid: i32) -> Result<()> {
     use rustix::{
         fd::AsFd,
@@ -333,3 +406,51 @@ pub fn detach_process_group(use_init_pgrp: bool) {
         log::error!("failed to set process group: {e2:?}");
     }
 }
+
+/// Read all mount points from `/proc/self/mountinfo`, in mount order
+pub fn get_mount_points() -> Result<Vec<String>> {
+    let content = std::fs::read_to_string("/proc/self/mountinfo")
+        .context("Failed to read /proc/self/mountinfo")?;
+    Ok(content
+        .lines()
+        .filter_map(|line| line.split(' ').nth(4))
+        .map(unescape_mount_path)
+        .collect())
+}
+
+// mountinfo escapes space, tab, newline and backslash as `\ooo`
+pub fn unescape_mount_path(path: &str) -> String {
+    let bytes = path.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'\\')
+            .then(|| bytes.get(i + 1..i + 4))
+            .flatten()
+            .and_then(|oct| std::str::from_utf8(oct).ok())
+            .and_then(|oct| u8::from_str_radix(oct, 8).ok());
+        if let Some(b) = escaped {
+            out.push(b);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+/// Read package name to uid mapping from packages.list
+pub fn read_packages_list() -> Result<HashMap<String, u32>> {
+    let content = std::fs::read_to_string(defs::PACKAGES_LIST_PATH)
+        .with_context(|| format!("Failed to read {}", defs::PACKAGES_LIST_PATH))?;
+    Ok(content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let package = fields.next()?;
+            let uid = fields.next()?.parse().ok()?;
+            Some((package.to_string(), uid))
+        })
+        .collect())
+}
@@ -1,116 +1,682 @@
-use std::io::{Read, Seek, SeekFrom};
+//! APK Signature Scheme v2/v3/v3.1 verification
+//!
+//! The kernel only trusts the hash of the first certificate in the v2 block, so before
+//! handing it out we verify every signer against the APK contents: content digests,
+//! signatures over the signed data, the certificate/public key binding and, for v3,
+//! the proof-of-rotation lineage.
 
-use anyhow::{Result, ensure};
+use std::{collections::HashMap, fs::File};
 
-pub fn get_apk_signature(apk: &str) -> Result<(u32, String)> {
-    let mut buffer = [0u8; 0x10];
-    let mut size4 = [0u8; 4];
-    let mut size8 = [0u8; 8];
-    let mut size_of_block = [0u8; 8];
-
-    let mut f = std::fs::File::open(apk)?;
-
-    let mut i = 0;
-    loop {
-        let mut n = [0u8; 2];
-        f.seek(SeekFrom::End(-i - 2))?;
-        f.read_exact(&mut n)?;
-
-        let n = u16::from_le_bytes(n);
-        if i64::from(n) == i {
-            f.seek(SeekFrom::Current(-22))?;
-            f.read_exact(&mut size4)?;
-
-            if u32::from_le_bytes(size4) ^ 0xcafe_babe_u32 == 0xccfb_f1ee_u32 {
-                if i > 0 {
-                    println!("warning: comment length is {i}");
-                }
-                break;
+use anyhow::{Context, Result, bail, ensure};
+use memmap2::Mmap;
+use p256::ecdsa::signature::hazmat::PrehashVerifier;
+use rsa::{Pkcs1v15Sign, Pss, RsaPublicKey, pkcs8::DecodePublicKey};
+use sha2::{Digest, Sha256, Sha512};
+
+const APK_SIG_BLOCK_MAGIC: &[u8; 16] = b"APK Sig Block 42";
+const EOCD_MAGIC: u32 = 0x0605_4b50;
+const EOCD_MIN_SIZE: usize = 22;
+
+const V2_BLOCK_ID: u32 = 0x7109_871a;
+const V3_BLOCK_ID: u32 = 0xf053_68c0;
+// credits to vvb2060
+const V3_1_BLOCK_ID: u32 = 0x1b93_ad61;
+const PROOF_OF_ROTATION_ATTR_ID: u32 = 0x3ba0_6f8c;
+
+const SIG_RSA_PSS_SHA256: u32 = 0x0101;
+const SIG_RSA_PSS_SHA512: u32 = 0x0102;
+const SIG_RSA_PKCS1_SHA256: u32 = 0x0103;
+const SIG_RSA_PKCS1_SHA512: u32 = 0x0104;
+const SIG_ECDSA_SHA256: u32 = 0x0201;
+const SIG_ECDSA_SHA512: u32 = 0x0202;
+const SIG_DSA_SHA256: u32 = 0x0301;
+const SIG_VERITY_RSA_PKCS1_SHA256: u32 = 0x0421;
+const SIG_VERITY_ECDSA_SHA256: u32 = 0x0423;
+const SIG_VERITY_DSA_SHA256: u32 = 0x0425;
+
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    V2,
+    V3,
+    V31,
+}
+
+impl std::fmt::Display for Scheme {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::V2 => "v2",
+            Self::V3 => "v3",
+            Self::V31 => "v3.1",
+        })
+    }
+}
+
+/// A certificate identified the same way the kernel does: DER size and sha256
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CertId {
+    pub size: u32,
+    pub hash: String,
+}
+
+impl CertId {
+    fn new(cert: &[u8]) -> Self {
+        Self {
+            size: cert.len() as u32,
+            hash: sha256::digest(cert),
+        }
+    }
+}
+
+/// One step of a v3 proof-of-rotation lineage, oldest first
+#[derive(Debug, Clone)]
+pub struct LineageNode {
+    pub cert: CertId,
+    pub flags: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct Signer {
+    pub scheme: Scheme,
+    pub cert: CertId,
+    /// SDK range of v3/v3.1 signers
+    pub sdk_range: Option<(u32, u32)>,
+    pub lineage: Vec<LineageNode>,
+}
+
+#[derive(Debug, Default)]
+pub struct ApkSignatures {
+    pub signers: Vec<Signer>,
+}
+
+impl ApkSignatures {
+    /// The signer Android would use on `sdk`: v3.1, then v3 signers whose range covers
+    /// it, then the first v2 signer.
+    pub fn signer_for_sdk(&self, sdk: u32) -> Option<&Signer> {
+        let in_range = |scheme| {
+            self.signers.iter().find(|s| {
+                s.scheme == scheme
+                    && s.sdk_range
+                        .is_some_and(|(min, max)| (min..=max).contains(&sdk))
+            })
+        };
+        in_range(Scheme::V31)
+            .or_else(|| in_range(Scheme::V3))
+            .or_else(|| self.signers.iter().find(|s| s.scheme == Scheme::V2))
+    }
+}
+
+/// Little-endian reader over length-prefixed APK signing block structures
+struct Reader<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    const fn new(buf: &'a [u8]) -> Self {
+        Self { buf }
+    }
+
+    const fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        ensure!(self.buf.len() >= len, "truncated signing block");
+        let (head, tail) = self.buf.split_at(len);
+        self.buf = tail;
+        Ok(head)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn length_prefixed(&mut self) -> Result<Reader<'a>> {
+        let len = self.u32()? as usize;
+        Ok(Reader::new(self.bytes(len)?))
+    }
+
+    fn length_prefixed_bytes(&mut self) -> Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.bytes(len)
+    }
+}
+
+/// Offsets of the zip sections covered by the content digests
+struct ZipSections {
+    sig_block_offset: usize,
+    central_dir_offset: usize,
+    eocd_offset: usize,
+}
+
+fn find_eocd(apk: &[u8]) -> Result<usize> {
+    ensure!(apk.len() >= EOCD_MIN_SIZE, "not a zip file");
+    let max_comment = (apk.len() - EOCD_MIN_SIZE).min(0xffff);
+    for comment_len in 0..=max_comment {
+        let offset = apk.len() - EOCD_MIN_SIZE - comment_len;
+        let magic = u32::from_le_bytes(apk[offset..offset + 4].try_into()?);
+        let len = u16::from_le_bytes(apk[offset + 20..offset + 22].try_into()?);
+        if magic == EOCD_MAGIC && usize::from(len) == comment_len {
+            if comment_len > 0 {
+                println!("warning: comment length is {comment_len}");
             }
+            return Ok(offset);
         }
+    }
+    bail!("not a zip file")
+}
+
+/// Locate the APK signing block and return its id-value pairs
+fn read_signing_block(apk: &[u8]) -> Result<(ZipSections, HashMap<u32, &[u8]>)> {
+    let eocd_offset = find_eocd(apk)?;
+    let central_dir_offset =
+        u32::from_le_bytes(apk[eocd_offset + 16..eocd_offset + 20].try_into()?) as usize;
+    ensure!(
+        central_dir_offset >= 0x18 && central_dir_offset <= eocd_offset,
+        "invalid central directory offset"
+    );
 
-        ensure!(n != 0xffff, "not a zip file");
+    let footer = &apk[central_dir_offset - 0x18..central_dir_offset];
+    ensure!(
+        &footer[8..] == APK_SIG_BLOCK_MAGIC,
+        "Can not found sig block"
+    );
+    let block_size = usize::try_from(Reader::new(footer).u64()?)?;
+    ensure!(block_size >= 0x18, "invalid sig block size");
+    // the leading size field isn't counted in the block size
+    let sig_block_offset = block_size
+        .checked_add(8)
+        .and_then(|len| central_dir_offset.checked_sub(len))
+        .context("invalid sig block size")?;
+    let mut block = Reader::new(&apk[sig_block_offset..central_dir_offset - 0x18]);
+    ensure!(
+        usize::try_from(block.u64()?)? == block_size,
+        "not a signed apk"
+    );
 
-        i += 1;
+    let mut pairs = HashMap::new();
+    while !block.is_empty() {
+        let len = usize::try_from(block.u64()?)?;
+        ensure!(len >= 4, "invalid sig block entry");
+        let mut pair = Reader::new(block.bytes(len)?);
+        let id = pair.u32()?;
+        pairs.insert(id, pair.buf);
     }
 
-    f.seek(SeekFrom::Current(12))?;
-    // offset
-    f.read_exact(&mut size4)?;
-    f.seek(SeekFrom::Start(u64::from(u32::from_le_bytes(size4)) - 0x18))?;
+    Ok((
+        ZipSections {
+            sig_block_offset,
+            central_dir_offset,
+            eocd_offset,
+        },
+        pairs,
+    ))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum ContentDigest {
+    Sha256,
+    Sha512,
+}
+
+/// Digest used over the APK contents for a signature algorithm, None for verity or
+/// unsupported algorithms
+const fn content_digest_of(algorithm: u32) -> Option<ContentDigest> {
+    match algorithm {
+        SIG_RSA_PSS_SHA256 | SIG_RSA_PKCS1_SHA256 | SIG_ECDSA_SHA256 => Some(ContentDigest::Sha256),
+        SIG_RSA_PSS_SHA512 | SIG_RSA_PKCS1_SHA512 | SIG_ECDSA_SHA512 => Some(ContentDigest::Sha512),
+        _ => None,
+    }
+}
+
+fn chunked_digest<D: Digest>(sections: &[&[u8]]) -> Vec<u8> {
+    let chunk_count: usize = sections.iter().map(|s| s.len().div_ceil(CHUNK_SIZE)).sum();
+    let mut top = D::new();
+    top.update([0x5a]);
+    top.update((chunk_count as u32).to_le_bytes());
+    for section in sections {
+        for chunk in section.chunks(CHUNK_SIZE) {
+            let mut hasher = D::new();
+            hasher.update([0xa5]);
+            hasher.update((chunk.len() as u32).to_le_bytes());
+            hasher.update(chunk);
+            top.update(hasher.finalize());
+        }
+    }
+    top.finalize().to_vec()
+}
+
+fn compute_content_digest(apk: &[u8], zip: &ZipSections, digest: ContentDigest) -> Vec<u8> {
+    // the EOCD is digested as if the central directory started at the signing block
+    let mut eocd = apk[zip.eocd_offset..].to_vec();
+    eocd[16..20].copy_from_slice(&(zip.sig_block_offset as u32).to_le_bytes());
+    let sections = [
+        &apk[..zip.sig_block_offset],
+        &apk[zip.central_dir_offset..zip.eocd_offset],
+        &eocd[..],
+    ];
+    match digest {
+        ContentDigest::Sha256 => chunked_digest::<Sha256>(&sections),
+        ContentDigest::Sha512 => chunked_digest::<Sha512>(&sections),
+    }
+}
+
+/// Read a DER element, returning (content, whole element, rest)
+fn der_element(input: &[u8]) -> Result<(&[u8], &[u8], &[u8])> {
+    ensure!(input.len() >= 2, "truncated certificate");
+    let (len, header) = match input[1] {
+        len if len < 0x80 => (usize::from(len), 2),
+        len => {
+            let count = usize::from(len & 0x7f);
+            ensure!(
+                (1..=4).contains(&count) && input.len() >= 2 + count,
+                "invalid certificate length"
+            );
+            let len = input[2..2 + count]
+                .iter()
+                .fold(0usize, |acc, b| (acc << 8) | usize::from(*b));
+            (len, 2 + count)
+        }
+    };
+    ensure!(
+        header
+            .checked_add(len)
+            .is_some_and(|end| input.len() >= end),
+        "truncated certificate"
+    );
+    Ok((
+        &input[header..header + len],
+        &input[..header + len],
+        &input[header + len..],
+    ))
+}
 
-    f.read_exact(&mut size8)?;
-    f.read_exact(&mut buffer)?;
+/// Extract the DER SubjectPublicKeyInfo of an X.509 certificate
+fn cert_public_key(cert: &[u8]) -> Result<&[u8]> {
+    let (cert, _, _) = der_element(cert)?;
+    let (mut tbs, _, _) = der_element(cert)?;
+    // optional explicit [0] version
+    if tbs.first() == Some(&0xa0) {
+        tbs = der_element(tbs)?.2;
+    }
+    // serialNumber, signature, issuer, validity, subject
+    for _ in 0..5 {
+        tbs = der_element(tbs)?.2;
+    }
+    Ok(der_element(tbs)?.1)
+}
 
-    ensure!(&buffer == b"APK Sig Block 42", "Can not found sig block");
+fn verify_ecdsa(public_key: &[u8], prehash: &[u8], signature: &[u8]) -> Result<()> {
+    if let Ok(key) = p256::ecdsa::VerifyingKey::from_public_key_der(public_key) {
+        let signature = p256::ecdsa::Signature::from_der(signature)?;
+        key.verify_prehash(prehash, &signature)?;
+    } else {
+        let key = p384::ecdsa::VerifyingKey::from_public_key_der(public_key)
+            .context("unsupported EC public key")?;
+        let signature = p384::ecdsa::Signature::from_der(signature)?;
+        key.verify_prehash(prehash, &signature)?;
+    }
+    Ok(())
+}
 
-    let pos = u64::from(u32::from_le_bytes(size4)) - (u64::from_le_bytes(size8) + 0x8);
-    f.seek(SeekFrom::Start(pos))?;
-    f.read_exact(&mut size_of_block)?;
+fn verify_signature(
+    algorithm: u32,
+    public_key: &[u8],
+    data: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let rsa_key =
+        || RsaPublicKey::from_public_key_der(public_key).context("invalid RSA public key");
+    match algorithm {
+        SIG_RSA_PSS_SHA256 => {
+            rsa_key()?.verify(Pss::new::<Sha256>(), &Sha256::digest(data), signature)?;
+        }
+        SIG_RSA_PSS_SHA512 => {
+            rsa_key()?.verify(Pss::new::<Sha512>(), &Sha512::digest(data), signature)?;
+        }
+        SIG_RSA_PKCS1_SHA256 | SIG_VERITY_RSA_PKCS1_SHA256 => {
+            rsa_key()?.verify(
+                Pkcs1v15Sign::new::<Sha256>(),
+                &Sha256::digest(data),
+                signature,
+            )?;
+        }
+        SIG_RSA_PKCS1_SHA512 => {
+            rsa_key()?.verify(
+                Pkcs1v15Sign::new::<Sha512>(),
+                &Sha512::digest(data),
+                signature,
+            )?;
+        }
+        SIG_ECDSA_SHA256 | SIG_VERITY_ECDSA_SHA256 => {
+            verify_ecdsa(public_key, &Sha256::digest(data), signature)?;
+        }
+        SIG_ECDSA_SHA512 => verify_ecdsa(public_key, &Sha512::digest(data), signature)?,
+        SIG_DSA_SHA256 | SIG_VERITY_DSA_SHA256 => bail!("DSA signatures are not supported"),
+        _ => bail!("unknown signature algorithm {algorithm:#06x}"),
+    }
+    Ok(())
+}
 
-    ensure!(size_of_block == size8, "not a signed apk");
+/// Verify a v3 proof-of-rotation lineage, returning its nodes oldest first
+fn verify_lineage(mut attr: Reader) -> Result<Vec<LineageNode>> {
+    let _version = attr.u32()?;
+    let mut nodes = Vec::new();
+    let mut previous: Option<(&[u8], u32)> = None;
 
-    let mut v2_signing: Option<(u32, String)> = None;
-    let mut v3_signing_exist = false;
-    let mut v3_1_signing_exist = false;
+    while !attr.is_empty() {
+        let mut node = attr.length_prefixed()?;
+        let signed_data = node.length_prefixed_bytes()?;
+        let flags = node.u32()?;
+        let algorithm = node.u32()?;
+        let signature = node.length_prefixed_bytes()?;
 
-    loop {
-        let mut id = [0u8; 4];
-        let mut offset = 4u32;
+        let mut signed = Reader::new(signed_data);
+        let cert = signed.length_prefixed_bytes()?;
+        let next_algorithm = signed.u32()?;
 
-        f.read_exact(&mut size8)?; // sequence length
-        if size8 == size_of_block {
-            break;
+        // every node but the first is signed by the previous certificate
+        if let Some((previous_cert, previous_algorithm)) = previous {
+            ensure!(
+                previous_algorithm == algorithm,
+                "lineage signature algorithm mismatch"
+            );
+            verify_signature(
+                algorithm,
+                cert_public_key(previous_cert)?,
+                signed_data,
+                signature,
+            )
+            .context("invalid lineage signature")?;
         }
 
-        f.read_exact(&mut id)?; // id
+        nodes.push(LineageNode {
+            cert: CertId::new(cert),
+            flags,
+        });
+        previous = Some((cert, next_algorithm));
+    }
+
+    Ok(nodes)
+}
+
+fn verify_signer(
+    scheme: Scheme,
+    mut signer: Reader,
+    apk: &[u8],
+    zip: &ZipSections,
+    content_digests: &mut HashMap<ContentDigest, Vec<u8>>,
+) -> Result<Signer> {
+    let signed_data = signer.length_prefixed_bytes()?;
+    let outer_sdk_range = if scheme == Scheme::V2 {
+        None
+    } else {
+        Some((signer.u32()?, signer.u32()?))
+    };
+    let mut signatures = signer.length_prefixed()?;
+    let public_key = signer.length_prefixed_bytes()?;
 
-        let id = u32::from_le_bytes(id);
-        if id == 0x7109_871a_u32 {
-            v2_signing = Some(calc_cert_sha256(&mut f, &mut size4, &mut offset)?);
-        } else if id == 0xf053_68c0_u32 {
-            // v3 signature scheme
-            v3_signing_exist = true;
-        } else if id == 0x1b93_ad61_u32 {
-            // v3.1 signature scheme: credits to vvb2060
-            v3_1_signing_exist = true;
+    let mut signed = Reader::new(signed_data);
+    let mut digests_seq = signed.length_prefixed()?;
+    let mut certs_seq = signed.length_prefixed()?;
+    let sdk_range = if scheme == Scheme::V2 {
+        None
+    } else {
+        Some((signed.u32()?, signed.u32()?))
+    };
+    ensure!(sdk_range == outer_sdk_range, "SDK range mismatch");
+    let mut attrs = signed.length_prefixed()?;
+
+    let mut digests = HashMap::new();
+    while !digests_seq.is_empty() {
+        let mut digest = digests_seq.length_prefixed()?;
+        let algorithm = digest.u32()?;
+        digests.insert(algorithm, digest.length_prefixed_bytes()?);
+    }
+
+    // verify every signature we understand, at least one is required
+    let mut verified = 0;
+    while !signatures.is_empty() {
+        let mut signature = signatures.length_prefixed()?;
+        let algorithm = signature.u32()?;
+        let signature = signature.length_prefixed_bytes()?;
+        let Some(digest_kind) = content_digest_of(algorithm) else {
+            continue;
+        };
+
+        verify_signature(algorithm, public_key, signed_data, signature)
+            .with_context(|| format!("{scheme} signature {algorithm:#06x} is invalid"))?;
+
+        let expected = digests
+            .get(&algorithm)
+            .with_context(|| format!("{scheme} digest {algorithm:#06x} is missing"))?;
+        let actual = content_digests
+            .entry(digest_kind)
+            .or_insert_with(|| compute_content_digest(apk, zip, digest_kind));
+        ensure!(
+            *expected == actual.as_slice(),
+            "{scheme} content digest mismatch, apk has been modified"
+        );
+        verified += 1;
+    }
+    ensure!(verified > 0, "{scheme} signer has no supported signature");
+
+    let cert = certs_seq
+        .length_prefixed_bytes()
+        .with_context(|| format!("{scheme} signer has no certificate"))?;
+    ensure!(
+        cert_public_key(cert)? == public_key,
+        "{scheme} public key does not match certificate"
+    );
+    let cert = CertId::new(cert);
+
+    let mut lineage = Vec::new();
+    if scheme != Scheme::V2 {
+        while !attrs.is_empty() {
+            let mut attr = attrs.length_prefixed()?;
+            if attr.u32()? == PROOF_OF_ROTATION_ATTR_ID {
+                lineage = verify_lineage(attr)?;
+            }
+        }
+        if let Some(last) = lineage.last() {
+            ensure!(
+                last.cert == cert,
+                "{scheme} lineage does not end with the signing certificate"
+            );
         }
+    }
+
+    Ok(Signer {
+        scheme,
+        cert,
+        sdk_range,
+        lineage,
+    })
+}
+
+/// Verify all v2/v3/v3.1 signers of an APK
+pub fn verify_apk(apk: &str) -> Result<ApkSignatures> {
+    let file = File::open(apk)?;
+    let apk = unsafe { Mmap::map(&file)? };
+    verify_apk_data(&apk)
+}
+
+fn verify_apk_data(apk: &[u8]) -> Result<ApkSignatures> {
+    let (zip, blocks) = read_signing_block(apk)?;
 
-        f.seek(SeekFrom::Current(
-            i64::from_le_bytes(size8) - i64::from(offset),
-        ))?;
+    let mut content_digests = HashMap::new();
+    let mut result = ApkSignatures::default();
+    for (id, scheme) in [
+        (V2_BLOCK_ID, Scheme::V2),
+        (V3_BLOCK_ID, Scheme::V3),
+        (V3_1_BLOCK_ID, Scheme::V31),
+    ] {
+        let Some(block) = blocks.get(&id) else {
+            continue;
+        };
+        let mut signers = Reader::new(block).length_prefixed()?;
+        ensure!(!signers.is_empty(), "{scheme} block has no signer");
+        while !signers.is_empty() {
+            let signer = signers.length_prefixed()?;
+            result.signers.push(verify_signer(
+                scheme,
+                signer,
+                apk,
+                &zip,
+                &mut content_digests,
+            )?);
+        }
     }
 
-    if v3_signing_exist || v3_1_signing_exist {
+    ensure!(!result.signers.is_empty(), "No signature found!");
+    Ok(result)
+}
+
+/// Get the (size, sha256) of the v2 signing certificate the kernel will match
+pub fn get_apk_signature(apk: &str) -> Result<(u32, String)> {
+    let signatures = verify_apk(apk)?;
+
+    if signatures.signers.iter().any(|s| s.scheme != Scheme::V2) {
         return Err(anyhow::anyhow!("Unexpected v3 signature found!"));
     }
 
-    v2_signing.ok_or_else(|| anyhow::anyhow!("No signature found!"))
+    signatures
+        .signers
+        .into_iter()
+        .next()
+        .map(|s| (s.cert.size, s.cert.hash))
+        .ok_or_else(|| anyhow::anyhow!("No signature found!"))
+}
+
+/// Print every verified signer and its lineage, marking the one effective on `sdk`
+pub fn dump_apk_signers(apk: &str, sdk: Option<u32>) -> Result<()> {
+    let signatures = verify_apk(apk)?;
+    let effective = sdk.and_then(|sdk| signatures.signer_for_sdk(sdk));
+
+    for signer in &signatures.signers {
+        let marker = if effective.is_some_and(|e| std::ptr::eq(e, signer)) {
+            "*"
+        } else {
+            " "
+        };
+        print!(
+            "{marker} {}: size: {:#x}, hash: {}",
+            signer.scheme, signer.cert.size, signer.cert.hash
+        );
+        if let Some((min, max)) = signer.sdk_range {
+            print!(", sdk: {min}-{max}");
+        }
+        println!();
+        for node in &signer.lineage {
+            println!(
+                "    lineage: size: {:#x}, hash: {}, flags: {:#x}",
+                node.cert.size, node.cert.hash, node.flags
+            );
+        }
+    }
+    Ok(())
 }
 
-fn calc_cert_sha256(
-    f: &mut std::fs::File,
-    size4: &mut [u8; 4],
-    offset: &mut u32,
-) -> Result<(u32, String)> {
-    f.read_exact(size4)?; // signer-sequence length
-    f.read_exact(size4)?; // signer length
-    f.read_exact(size4)?; // signed data length
-    *offset += 0x4 * 3;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // generated by testdata/apk/mkapk.py
+    const V2_RSA: &[u8] = include_bytes!("../testdata/apk/v2_rsa.apk");
+    const V2_EC: &[u8] = include_bytes!("../testdata/apk/v2_ec.apk");
+    const V2_TAMPERED: &[u8] = include_bytes!("../testdata/apk/v2_tampered.apk");
+    const V3: &[u8] = include_bytes!("../testdata/apk/v3.apk");
+    const V3_BAD_LINEAGE: &[u8] = include_bytes!("../testdata/apk/v3_bad_lineage.apk");
+
+    fn error_of(apk: &[u8]) -> String {
+        format!("{:#}", verify_apk_data(apk).unwrap_err())
+    }
+
+    // the u64 size field right before the central directory
+    fn footer_size_offset(apk: &[u8]) -> usize {
+        let eocd = find_eocd(apk).unwrap();
+        let central_dir = u32::from_le_bytes(apk[eocd + 16..eocd + 20].try_into().unwrap());
+        central_dir as usize - 0x18
+    }
+
+    #[test]
+    fn v2_signers() {
+        let rsa = verify_apk_data(V2_RSA).unwrap();
+        assert_eq!(rsa.signers.len(), 1);
+        assert_eq!(rsa.signers[0].scheme, Scheme::V2);
+        assert_eq!(rsa.signers[0].sdk_range, None);
+
+        let ec = verify_apk_data(V2_EC).unwrap();
+        assert_eq!(ec.signers.len(), 1);
+        assert_ne!(ec.signers[0].cert, rsa.signers[0].cert);
+    }
+
+    #[test]
+    fn v3_signers() {
+        let signatures = verify_apk_data(V3).unwrap();
+        let schemes: Vec<Scheme> = signatures.signers.iter().map(|s| s.scheme).collect();
+        assert_eq!(schemes, [Scheme::V2, Scheme::V3, Scheme::V31]);
+
+        let v2 = &signatures.signers[0];
+        let v31 = &signatures.signers[2];
+        assert_eq!(signatures.signer_for_sdk(23).unwrap().scheme, Scheme::V2);
+        assert_eq!(signatures.signer_for_sdk(28).unwrap().scheme, Scheme::V3);
+        assert_eq!(signatures.signer_for_sdk(34).unwrap().scheme, Scheme::V31);
+
+        let lineage: Vec<&CertId> = v31.lineage.iter().map(|node| &node.cert).collect();
+        assert_eq!(lineage, [&v2.cert, &v31.cert]);
+    }
+
+    #[test]
+    fn tampered_content() {
+        assert!(error_of(V2_TAMPERED).contains("content digest mismatch"));
 
-    f.read_exact(size4)?; // digests-sequence length
-    let pos = u32::from_le_bytes(*size4); // skip digests
-    f.seek(SeekFrom::Current(i64::from(pos)))?;
-    *offset += 0x4 + pos;
+        let mut apk = V2_RSA.to_vec();
+        apk[10] ^= 1;
+        assert!(error_of(&apk).contains("content digest mismatch"));
+    }
+
+    #[test]
+    fn tampered_signature() {
+        let mut apk = V2_EC.to_vec();
+        // the signatures follow the signed data, flip a byte near the end of the block
+        let offset = footer_size_offset(&apk) - 120;
+        apk[offset] ^= 1;
+        assert!(error_of(&apk).contains("signature 0x0202 is invalid"));
+    }
+
+    #[test]
+    fn bad_lineage() {
+        assert!(error_of(V3_BAD_LINEAGE).contains("invalid lineage signature"));
+    }
 
-    f.read_exact(size4)?; // certificates length
-    f.read_exact(size4)?; // certificate length
-    *offset += 0x4 * 2;
+    #[test]
+    fn corrupt_block_sizes() {
+        let offset = footer_size_offset(V2_RSA);
+        for size in [0, 0x17, u64::MAX, u64::MAX - 7, (offset as u64) + 0x18] {
+            let mut apk = V2_RSA.to_vec();
+            apk[offset..offset + 8].copy_from_slice(&size.to_le_bytes());
+            assert!(verify_apk_data(&apk).is_err(), "block size {size:#x}");
+        }
 
-    let cert_len = u32::from_le_bytes(*size4);
-    let mut cert: Vec<u8> = vec![0; cert_len as usize];
-    f.read_exact(&mut cert)?;
-    *offset += cert_len;
+        let mut apk = V2_RSA.to_vec();
+        let eocd = find_eocd(&apk).unwrap();
+        apk[eocd + 16..eocd + 20].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(error_of(&apk).contains("invalid central directory offset"));
+    }
 
-    Ok((cert_len, sha256::digest(&cert)))
+    #[test]
+    fn not_signed() {
+        assert!(verify_apk_data(b"PK\x05\x06").is_err());
+        let mut zip = vec![0; 0x40];
+        zip.extend_from_slice(&[0x50, 0x4b, 0x05, 0x06]);
+        zip.extend_from_slice(&[0; 18]);
+        zip[0x40 + 16..0x40 + 20].copy_from_slice(&0x40u32.to_le_bytes());
+        assert!(error_of(&zip).contains("Can not found sig block"));
+    }
 }
@@ -608,6 +608,11 @@ pub fn patch(args: BootPatchArgs) -> Result<()> {
             }
 
             cpio.add("init", CpioEntry::regular(0o755, ksu_init))?;
+            // ksuinit falls back to the previous module if the new one doesn't load
+            if is_kernelsu_patched {
+                cpio.rm("kernelsu_fallback.ko", false);
+                cpio.mv("kernelsu.ko", "kernelsu_fallback.ko")?;
+            }
             cpio.add("kernelsu.ko", CpioEntry::regular(0o755, kernelsu_ko))?;
 
             #[cfg(target_os = "android")]
@@ -883,6 +888,7 @@ fn rebuild_without_ksu(
 ) -> Result<Vec<u8>> {
     println!("- Removing KernelSU from boot image");
     cpio.rm("kernelsu.ko", false);
+    cpio.rm("kernelsu_fallback.ko", false);
     if cpio.exists("init.real") {
         cpio.mv("init.real", "init")?;
     }
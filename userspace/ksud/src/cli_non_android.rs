@@ -1,10 +1,12 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
 use clap::Parser;
 
 use crate::{
     apk_sign,
     boot_patch::{BootPatchArgs, BootRestoreArgs},
-    defs,
+    defs, prop_area,
 };
 
 /// KernelSU cli for non-android
@@ -29,8 +31,41 @@ enum Commands {
         apk: String,
     },
 
+    /// Verify apk signatures and list all signers
+    VerifyApk {
+        /// apk path
+        apk: String,
+    },
+
     /// show supported kmi versions
     SupportedKmis,
+
+    /// Get, set, delete or rebuild properties in a copy of /dev/__properties__
+    Resetprop {
+        /// directory holding the property area files
+        #[arg(long)]
+        root: PathBuf,
+
+        /// delete the named property
+        #[arg(short = 'd', long)]
+        delete: bool,
+
+        /// rebuild the property area by SELinux context name, or all areas with wasted space
+        #[arg(short = 'c', long, alias = "compact")]
+        rebuild: bool,
+
+        /// show SELinux contexts when listing, or with -c, rebuild the area holding NAME
+        #[arg(short = 'Z')]
+        show_context: bool,
+
+        /// with -c, rebuild every area
+        #[arg(long)]
+        force: bool,
+
+        name: Option<String>,
+
+        value: Option<String>,
+    },
 }
 
 pub fn run() -> Result<()> {
@@ -47,10 +82,32 @@ pub fn run() -> Result<()> {
             Ok(())
         }
 
+        Commands::VerifyApk { apk } => apk_sign::dump_apk_signers(&apk, None),
+
         Commands::BootPatch(boot_patch) => crate::boot_patch::patch(boot_patch),
 
         Commands::BootRestore(boot_restore) => crate::boot_patch::restore(boot_restore),
 
+        Commands::Resetprop {
+            root,
+            delete,
+            rebuild,
+            show_context,
+            force,
+            name,
+            value,
+        } => prop_area::run(
+            &root,
+            &prop_area::Request {
+                delete,
+                rebuild,
+                show_context,
+                force,
+                name: name.as_deref(),
+                value: value.as_deref(),
+            },
+        ),
+
         Commands::SupportedKmis => {
             let kmi = crate::assets::list_supported_kmi();
             for kmi in &kmi {
@@ -35,21 +35,43 @@ mod android {
     pub const DISABLE_FILE_NAME: &str = "disable";
     pub const UPDATE_FILE_NAME: &str = "update";
     pub const REMOVE_FILE_NAME: &str = "remove";
+    pub const SKIP_MOUNT_FILE_NAME: &str = "skip_mount";
     pub const MODULE_INIT_RC_DIR: &str = "initrc";
 
     // Module config system
     pub const MODULE_CONFIG_DIR: &str = concatcp!(WORKING_DIR, "module_configs/");
     pub const PERSIST_CONFIG_NAME: &str = "persist.config";
     pub const TEMP_CONFIG_NAME: &str = "tmp.config";
+    pub const CONFIG_STAMP_NAME: &str = "stamp";
+    pub const MODULE_CONFIG_SCHEMA: &str = "config.schema.json";
 
     // Metamodule support
     pub const METAMODULE_MOUNT_SCRIPT: &str = "metamount.sh";
     pub const METAMODULE_METAINSTALL_SCRIPT: &str = "metainstall.sh";
     pub const METAMODULE_METAUNINSTALL_SCRIPT: &str = "metauninstall.sh";
+    pub const METAMODULE_METAENABLE_SCRIPT: &str = "metaenable.sh";
+    pub const METAMODULE_METADISABLE_SCRIPT: &str = "metadisable.sh";
+    pub const METAMODULE_METAACTION_SCRIPT: &str = "metaaction.sh";
     pub const METAMODULE_MOUNT_SCRIPT_LOG: &str = concatcp!(LOG_DIR, "metamodule_mount");
     pub const METAMODULE_METAUNINSTALL_SCRIPT_LOG: &str =
         concatcp!(LOG_DIR, "metamodule_metauninstall");
+    pub const METAMODULE_METAENABLE_SCRIPT_LOG: &str = concatcp!(LOG_DIR, "metamodule_metaenable");
+    pub const METAMODULE_METADISABLE_SCRIPT_LOG: &str =
+        concatcp!(LOG_DIR, "metamodule_metadisable");
+    pub const METAMODULE_METAACTION_SCRIPT_LOG: &str = concatcp!(LOG_DIR, "metamodule_metaaction");
     pub const METAMODULE_DEBUG: &str = concatcp!(WORKING_DIR, "metamodule.debug");
+    // ksuinit's LKM status of the current boot, copied from the kernel log
+    pub const LKM_STATUS_PATH: &str = concatcp!(WORKING_DIR, "lkm_status");
+    // ksuinit also writes the LKM status to pmsg, found here after the next boot
+    pub const PSTORE_DIR: &str = "/sys/fs/pstore";
+    pub const MODULE_MOUNT_STATUS_PATH: &str = concatcp!(WORKING_DIR, "module_mount_status.json");
+    // mount points added while mounting modules at boot, in mount order
+    pub const MODULE_MOUNTS_PATH: &str = concatcp!(WORKING_DIR, "module_mounts.json");
+    // mount modules with the built-in overlayfs when the metamodule didn't
+    pub const MOUNT_FALLBACK_FLAG: &str = concatcp!(WORKING_DIR, ".mount_fallback");
+    // "metamodule" or "magic"
+    pub const MOUNT_ENGINE_PATH: &str = concatcp!(WORKING_DIR, "mount_engine");
+    pub const MAGIC_MOUNT_WORK_DIR: &str = concatcp!(WORKING_DIR, "magic_mount/");
 
     pub const KSU_BACKUP_DIR: &str = WORKING_DIR;
     pub const KSU_BACKUP_FILE_PREFIX: &str = "ksu_backup_";
@@ -58,6 +80,13 @@ mod android {
 
     pub const DYNAMIC_MANAGER: &str = concatcp!(WORKING_DIR, ".dynamic_manager");
 
+    // properties as they were before any module system.prop was applied
+    pub const PROP_SNAPSHOT_PATH: &str = concatcp!(WORKING_DIR, "props_snapshot.json");
+
+    pub const LATE_LOAD_STATUS_PATH: &str = concatcp!(WORKING_DIR, "late_load_status.json");
+
+    pub const PACKAGES_LIST_PATH: &str = "/data/system/packages.list";
+
     #[derive(Serialize)]
     pub struct MountInfo {
         pub path: String,
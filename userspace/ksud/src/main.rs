@@ -6,6 +6,7 @@ mod boot_patch;
 #[cfg(not(target_os = "android"))]
 mod cli_non_android;
 mod defs;
+mod prop_area;
 
 fn main() -> anyhow::Result<()> {
     #[cfg(target_os = "android")]
@@ -0,0 +1,941 @@
+//! Offline access to Android property areas, i.e. a copy of `/dev/__properties__`.
+//!
+//! Follows bionic's `prop_area` and `property_info` layouts. Every offset read from a file
+//! is bounds checked, trie walks are depth limited and visit every node at most once, so
+//! corrupted areas are rejected instead of trusted, and the code can be run on any host.
+
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, bail, ensure};
+use log::{info, warn};
+
+const PROP_AREA_MAGIC: u32 = 0x504f_5250;
+const PROP_AREA_VERSION: u32 = 0xfc6e_d0ab;
+const PA_SIZE: usize = 128 * 1024;
+const HEADER_SIZE: usize = 128;
+
+// prop_bt: namelen, prop, left, right, children, name[]
+const BT_SIZE: usize = 20;
+const BT_PROP: u32 = 4;
+const BT_LEFT: u32 = 8;
+const BT_RIGHT: u32 = 12;
+const BT_CHILDREN: u32 = 16;
+
+// prop_info: serial, value[PROP_VALUE_MAX] (or error message + long value offset), name[]
+const INFO_SIZE: usize = 96;
+const PROP_VALUE_MAX: usize = 92;
+const LONG_FLAG: u32 = 1 << 16;
+const LONG_LEGACY_ERROR: &[u8] = b"Must use __system_property_read_callback() to read";
+const LONG_OFFSET: usize = 4 + 56;
+
+const MAX_DEPTH: usize = 1024;
+
+const PROPERTY_INFO: &str = "property_info";
+const PROPERTIES_SERIAL: &str = "properties_serial";
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32> {
+    let bytes = offset
+        .checked_add(4)
+        .and_then(|end| data.get(offset..end))
+        .with_context(|| format!("offset {offset:#x} out of bounds"))?;
+    Ok(u32::from_le_bytes(bytes.try_into()?))
+}
+
+fn read_cstr(data: &[u8], offset: usize) -> Result<&[u8]> {
+    let tail = data
+        .get(offset..)
+        .with_context(|| format!("string at {offset:#x} out of bounds"))?;
+    let len = tail
+        .iter()
+        .position(|&b| b == 0)
+        .with_context(|| format!("unterminated string at {offset:#x}"))?;
+    Ok(&tail[..len])
+}
+
+const fn align4(size: usize) -> usize {
+    size.next_multiple_of(4)
+}
+
+fn cmp_name(a: &[u8], b: &[u8]) -> Ordering {
+    (a.len(), a).cmp(&(b.len(), b))
+}
+
+pub struct Prop {
+    pub name: String,
+    pub value: String,
+    pub serial: u32,
+}
+
+/// A single property area file. Offsets of trie nodes are relative to the data after the header.
+pub struct PropArea {
+    bytes: Vec<u8>,
+}
+
+impl PropArea {
+    pub fn new(size: usize) -> Result<Self> {
+        ensure!(
+            size >= HEADER_SIZE + BT_SIZE,
+            "property area too small: {size}"
+        );
+        let mut area = Self {
+            bytes: vec![0; size],
+        };
+        area.put_u32(0, BT_SIZE as u32);
+        area.put_u32(8, PROP_AREA_MAGIC);
+        area.put_u32(12, PROP_AREA_VERSION);
+        Ok(area)
+    }
+
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        ensure!(
+            bytes.len() >= HEADER_SIZE + BT_SIZE,
+            "file too small for a property area"
+        );
+        let area = Self { bytes };
+        ensure!(
+            read_u32(&area.bytes, 8)? == PROP_AREA_MAGIC,
+            "bad property area magic"
+        );
+        ensure!(
+            read_u32(&area.bytes, 12)? == PROP_AREA_VERSION,
+            "unsupported property area version"
+        );
+        let used = area.bytes_used()? as usize;
+        ensure!(
+            (BT_SIZE..=area.data_size()).contains(&used),
+            "bad property area size {used:#x}"
+        );
+        Ok(area)
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    fn data_size(&self) -> usize {
+        self.bytes.len() - HEADER_SIZE
+    }
+
+    fn bytes_used(&self) -> Result<u32> {
+        read_u32(&self.bytes, 0)
+    }
+
+    pub fn serial(&self) -> Result<u32> {
+        read_u32(&self.bytes, 4)
+    }
+
+    fn put_u32(&mut self, offset: usize, value: u32) {
+        self.bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    fn data_u32(&self, offset: u32) -> Result<u32> {
+        read_u32(&self.bytes, HEADER_SIZE + offset as usize)
+    }
+
+    fn set_data_u32(&mut self, offset: u32, value: u32) {
+        self.put_u32(HEADER_SIZE + offset as usize, value);
+    }
+
+    fn write_data(&mut self, offset: u32, bytes: &[u8]) {
+        let start = HEADER_SIZE + offset as usize;
+        self.bytes[start..start + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn alloc(&mut self, size: usize) -> Result<u32> {
+        let used = self.bytes_used()? as usize;
+        let aligned = align4(size);
+        if used + aligned > self.data_size() {
+            bail!("property area is full");
+        }
+        self.put_u32(0, (used + aligned) as u32);
+        Ok(used as u32)
+    }
+
+    // reads a node offset out of `field`, rejecting ones that point outside the area
+    fn link(&self, field: u32) -> Result<u32> {
+        let node = self.data_u32(field)?;
+        ensure!(
+            node as usize + BT_SIZE <= self.data_size(),
+            "node offset {node:#x} out of bounds"
+        );
+        Ok(node)
+    }
+
+    fn node_name(&self, node: u32) -> Result<&[u8]> {
+        let len = self.data_u32(node)? as usize;
+        let start = HEADER_SIZE + node as usize + BT_SIZE;
+        self.bytes
+            .get(start..start + len)
+            .with_context(|| format!("node name at {node:#x} out of bounds"))
+    }
+
+    fn new_node(&mut self, name: &[u8]) -> Result<u32> {
+        let node = self.alloc(BT_SIZE + name.len() + 1)?;
+        self.set_data_u32(node, name.len() as u32);
+        self.write_data(node + BT_SIZE as u32, name);
+        Ok(node)
+    }
+
+    /// Walk the trie like bionic's `find_property`, allocating missing nodes if asked to.
+    fn find_node(&mut self, name: &str, alloc: bool) -> Result<Option<u32>> {
+        let mut current = 0;
+        for segment in name.split('.') {
+            let segment = segment.as_bytes();
+            if segment.is_empty() {
+                bail!("invalid property name '{name}'");
+            }
+
+            let mut field = current + BT_CHILDREN;
+            let mut steps = 0;
+            current = loop {
+                let node = self.link(field)?;
+                if node == 0 {
+                    if !alloc {
+                        return Ok(None);
+                    }
+                    let node = self.new_node(segment)?;
+                    self.set_data_u32(field, node);
+                    break node;
+                }
+                field = match cmp_name(segment, self.node_name(node)?) {
+                    Ordering::Equal => break node,
+                    Ordering::Less => node + BT_LEFT,
+                    Ordering::Greater => node + BT_RIGHT,
+                };
+                steps += 1;
+                ensure!(steps < MAX_DEPTH, "property trie too deep");
+            };
+        }
+        Ok(Some(current))
+    }
+
+    fn read_prop(&self, info: u32) -> Result<Prop> {
+        let start = HEADER_SIZE + info as usize;
+        let serial = read_u32(&self.bytes, start)?;
+        let name = read_cstr(&self.bytes, start + INFO_SIZE)?;
+        let value = if serial & LONG_FLAG == 0 {
+            let value = self
+                .bytes
+                .get(start + 4..start + 4 + PROP_VALUE_MAX)
+                .context("property value out of bounds")?;
+            read_cstr(value, 0)?
+        } else {
+            let offset = read_u32(&self.bytes, start + LONG_OFFSET)? as usize;
+            read_cstr(&self.bytes, start + offset)?
+        };
+        Ok(Prop {
+            name: String::from_utf8_lossy(name).into_owned(),
+            value: String::from_utf8_lossy(value).into_owned(),
+            serial,
+        })
+    }
+
+    fn new_info(&mut self, name: &str, value: &str) -> Result<u32> {
+        let info = self.alloc(INFO_SIZE + name.len() + 1)?;
+        self.write_data(info + INFO_SIZE as u32, name.as_bytes());
+        if value.len() >= PROP_VALUE_MAX {
+            let long = self.alloc(value.len() + 1)?;
+            self.write_data(long, value.as_bytes());
+            self.set_data_u32(info, (LONG_LEGACY_ERROR.len() as u32) << 24 | LONG_FLAG);
+            self.write_data(info + 4, LONG_LEGACY_ERROR);
+            self.set_data_u32(info + LONG_OFFSET as u32, long - info);
+        } else {
+            self.set_data_u32(info, (value.len() as u32) << 24);
+            self.write_data(info + 4, value.as_bytes());
+        }
+        Ok(info)
+    }
+
+    fn info_of(&mut self, name: &str) -> Result<Option<u32>> {
+        let Some(node) = self.find_node(name, false)? else {
+            return Ok(None);
+        };
+        let info = self.data_u32(node + BT_PROP)?;
+        Ok((info != 0).then_some(info))
+    }
+
+    pub fn get(&mut self, name: &str) -> Result<Option<String>> {
+        match self.info_of(name)? {
+            Some(info) => Ok(Some(self.read_prop(info)?.value)),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+        if let Some(info) = self.info_of(name)? {
+            let serial = self.read_prop(info)?.serial;
+            if serial & LONG_FLAG == 0 && value.len() < PROP_VALUE_MAX {
+                // same as __system_property_update: bump the counter past the dirty bit
+                let mut buf = [0; PROP_VALUE_MAX];
+                buf[..value.len()].copy_from_slice(value.as_bytes());
+                self.write_data(info + 4, &buf);
+                let counter = ((serial | 1) + 1) & 0x00ff_ffff;
+                self.set_data_u32(info, (value.len() as u32) << 24 | counter);
+                return Ok(());
+            }
+            // long values can't be updated in place
+            self.delete(name)?;
+        }
+
+        let node = self
+            .find_node(name, true)?
+            .context("failed to allocate property node")?;
+        let info = self.new_info(name, value)?;
+        self.set_data_u32(node + BT_PROP, info);
+        Ok(())
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<bool> {
+        let Some(node) = self.find_node(name, false)? else {
+            return Ok(false);
+        };
+        let Some(info) = self.info_of(name)? else {
+            return Ok(false);
+        };
+
+        // wipe the prop_info and its long value, the space is reclaimed by compaction
+        let serial = self.read_prop(info)?.serial;
+        let start = HEADER_SIZE + info as usize;
+        if serial & LONG_FLAG != 0 {
+            let long = start + read_u32(&self.bytes, start + LONG_OFFSET)? as usize;
+            let len = read_cstr(&self.bytes, long)?.len();
+            self.bytes[long..long + len].fill(0);
+        }
+        let len = INFO_SIZE + read_cstr(&self.bytes, start + INFO_SIZE)?.len();
+        self.bytes[start..start + len].fill(0);
+        self.set_data_u32(node + BT_PROP, 0);
+
+        self.prune(0, 0, &mut HashSet::new())?;
+        Ok(true)
+    }
+
+    // links are a tree, a node reached twice means the area is corrupt or crafted
+    fn visit(visited: &mut HashSet<u32>, node: u32, depth: usize) -> Result<()> {
+        ensure!(depth < MAX_DEPTH, "property trie too deep");
+        ensure!(
+            visited.insert(node),
+            "property trie node {node:#x} is linked more than once"
+        );
+        Ok(())
+    }
+
+    /// Unlink leaf nodes without a property, returns whether `node` itself can go.
+    fn prune(&mut self, node: u32, depth: usize, visited: &mut HashSet<u32>) -> Result<bool> {
+        Self::visit(visited, node, depth)?;
+        let mut leaf = true;
+        for field in [BT_CHILDREN, BT_LEFT, BT_RIGHT] {
+            let child = self.link(node + field)?;
+            if child == 0 {
+                continue;
+            }
+            if self.prune(child, depth + 1, visited)? {
+                self.set_data_u32(node + field, 0);
+            } else {
+                leaf = false;
+            }
+        }
+        Ok(leaf && node != 0 && self.data_u32(node + BT_PROP)? == 0)
+    }
+
+    // in-order like bionic's foreach_property, also returns the bytes the live objects take
+    fn walk(
+        &self,
+        node: u32,
+        depth: usize,
+        visited: &mut HashSet<u32>,
+        props: &mut Vec<Prop>,
+    ) -> Result<usize> {
+        Self::visit(visited, node, depth)?;
+        let mut used = if node == 0 {
+            BT_SIZE
+        } else {
+            align4(BT_SIZE + self.node_name(node)?.len() + 1)
+        };
+
+        let left = self.link(node + BT_LEFT)?;
+        if left != 0 {
+            used += self.walk(left, depth + 1, visited, props)?;
+        }
+        let info = self.data_u32(node + BT_PROP)?;
+        if info != 0 {
+            let prop = self.read_prop(info)?;
+            used += align4(INFO_SIZE + prop.name.len() + 1);
+            if prop.serial & LONG_FLAG != 0 {
+                used += align4(prop.value.len() + 1);
+            }
+            props.push(prop);
+        }
+        let children = self.link(node + BT_CHILDREN)?;
+        if children != 0 {
+            used += self.walk(children, depth + 1, visited, props)?;
+        }
+        let right = self.link(node + BT_RIGHT)?;
+        if right != 0 {
+            used += self.walk(right, depth + 1, visited, props)?;
+        }
+        Ok(used)
+    }
+
+    pub fn props(&self) -> Result<Vec<Prop>> {
+        let mut props = Vec::new();
+        self.walk(0, 0, &mut HashSet::new(), &mut props)?;
+        Ok(props)
+    }
+
+    /// Bytes allocated but no longer reachable, left behind by deletions.
+    pub fn wasted(&self) -> Result<usize> {
+        let used = self.walk(0, 0, &mut HashSet::new(), &mut Vec::new())?;
+        Ok((self.bytes_used()? as usize).saturating_sub(used))
+    }
+
+    /// Rebuild the area from its live properties, keeping their serials.
+    pub fn compact(&self) -> Result<Self> {
+        let mut area = Self::new(self.bytes.len())?;
+        area.put_u32(4, self.serial()?);
+        for prop in self.props()? {
+            area.set(&prop.name, &prop.value)?;
+            let info = area
+                .info_of(&prop.name)?
+                .context("property lost while compacting")?;
+            area.set_data_u32(info, prop.serial);
+        }
+        Ok(area)
+    }
+}
+
+/// The serialized `property_info` trie mapping property names to SELinux contexts.
+pub struct PropertyInfo {
+    bytes: Vec<u8>,
+}
+
+impl PropertyInfo {
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self> {
+        let info = Self { bytes };
+        let size = info.u32_at(0, 8)? as usize;
+        ensure!(size <= info.bytes.len(), "truncated property_info");
+        Ok(info)
+    }
+
+    // offsets inside the file are u32, do the math in usize so corrupted ones can't overflow
+    fn u32_at(&self, base: u32, offset: usize) -> Result<u32> {
+        read_u32(&self.bytes, base as usize + offset)
+    }
+
+    fn str_at(&self, offset: u32) -> Result<&[u8]> {
+        read_cstr(&self.bytes, offset as usize)
+    }
+
+    // entry layout: name_offset, namelen, context_index, type_index
+    fn entry_name(&self, entry: u32) -> Result<&[u8]> {
+        self.str_at(self.u32_at(entry, 0)?)
+    }
+
+    fn entry_context(&self, entry: u32) -> Result<u32> {
+        self.u32_at(entry, 8)
+    }
+
+    // node layout: property_entry, num_child_nodes, child_nodes, num_prefixes,
+    // prefix_entries, num_exact_matches, exact_match_entries
+    fn node_entry(&self, node: u32) -> Result<u32> {
+        self.u32_at(node, 0)
+    }
+
+    fn items(&self, node: u32, field: usize) -> Result<impl Iterator<Item = Result<u32>>> {
+        let count = self.u32_at(node, field)?;
+        let array = self.u32_at(node, field + 4)?;
+        Ok((0..count as usize).map(move |i| self.u32_at(array, i * 4)))
+    }
+
+    fn check_prefixes(&self, node: u32, name: &[u8], context: &mut u32) -> Result<()> {
+        for entry in self.items(node, 12)? {
+            let entry = entry?;
+            let len = self.u32_at(entry, 4)? as usize;
+            let prefix = self.entry_name(entry)?;
+            if name.starts_with(prefix.get(..len).unwrap_or(prefix)) {
+                let index = self.entry_context(entry)?;
+                if index != u32::MAX {
+                    *context = index;
+                }
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Same lookup as libpropertyinfoparser's `GetPropertyInfoIndexes`.
+    pub fn context_of(&self, name: &str) -> Result<Option<String>> {
+        let mut context = u32::MAX;
+        let mut node = self.u32_at(0, 20)?;
+        let mut remaining = name.as_bytes();
+
+        loop {
+            let index = self.entry_context(self.node_entry(node)?)?;
+            if index != u32::MAX {
+                context = index;
+            }
+            self.check_prefixes(node, remaining, &mut context)?;
+
+            let Some(sep) = remaining.iter().position(|&b| b == b'.') else {
+                break;
+            };
+            let mut child = None;
+            for candidate in self.items(node, 4)? {
+                let candidate = candidate?;
+                if self.entry_name(self.node_entry(candidate)?)? == &remaining[..sep] {
+                    child = Some(candidate);
+                    break;
+                }
+            }
+            let Some(child) = child else {
+                break;
+            };
+            node = child;
+            remaining = &remaining[sep + 1..];
+        }
+
+        let mut exact = None;
+        for entry in self.items(node, 20)? {
+            let entry = entry?;
+            if self.entry_name(entry)? == remaining {
+                exact = Some(entry);
+                break;
+            }
+        }
+        match exact {
+            Some(entry) => {
+                let index = self.entry_context(entry)?;
+                if index != u32::MAX {
+                    context = index;
+                }
+            }
+            None => self.check_prefixes(node, remaining, &mut context)?,
+        }
+
+        if context == u32::MAX {
+            return Ok(None);
+        }
+        let contexts = self.u32_at(0, 12)?;
+        ensure!(
+            context < self.u32_at(contexts, 0)?,
+            "context index {context} out of range"
+        );
+        let context = self.str_at(self.u32_at(contexts, 4 + context as usize * 4)?)?;
+        Ok(Some(String::from_utf8_lossy(context).into_owned()))
+    }
+}
+
+/// A directory of property area files named after their SELinux contexts.
+pub struct PropRoot {
+    dir: PathBuf,
+    info: Option<PropertyInfo>,
+}
+
+impl PropRoot {
+    pub fn open(dir: &Path) -> Result<Self> {
+        ensure!(dir.is_dir(), "{} is not a directory", dir.display());
+        let info_path = dir.join(PROPERTY_INFO);
+        let info = if info_path.exists() {
+            let bytes = fs::read(&info_path)
+                .with_context(|| format!("Failed to read {}", info_path.display()))?;
+            Some(PropertyInfo::from_bytes(bytes).context("Invalid property_info")?)
+        } else {
+            None
+        };
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            info,
+        })
+    }
+
+    fn contexts(&self) -> Result<Vec<String>> {
+        let mut contexts = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if entry.file_type()?.is_file() && name != PROPERTY_INFO {
+                contexts.push(name);
+            }
+        }
+        contexts.sort();
+        Ok(contexts)
+    }
+
+    fn load(&self, context: &str) -> Result<PropArea> {
+        let path = self.dir.join(context);
+        let bytes =
+            fs::read(&path).with_context(|| format!("Failed to read {}", path.display()))?;
+        PropArea::from_bytes(bytes).with_context(|| format!("Invalid property area {context}"))
+    }
+
+    fn store(&self, context: &str, area: PropArea) -> Result<()> {
+        let path = self.dir.join(context);
+        fs::write(&path, area.into_bytes())
+            .with_context(|| format!("Failed to write {}", path.display()))
+    }
+
+    /// The context a property belongs to according to `property_info`.
+    pub fn context_of(&self, name: &str) -> Result<Option<String>> {
+        match &self.info {
+            Some(info) => info.context_of(name),
+            None => Ok(None),
+        }
+    }
+
+    fn locate(&self, name: &str) -> Result<Option<String>> {
+        if let Some(context) = self.context_of(name)?
+            && self.dir.join(&context).exists()
+        {
+            return Ok(Some(context));
+        }
+        for context in self.contexts()? {
+            if self.load(&context)?.get(name)?.is_some() {
+                return Ok(Some(context));
+            }
+        }
+        Ok(None)
+    }
+
+    fn bump_serial(&self) -> Result<()> {
+        if !self.dir.join(PROPERTIES_SERIAL).exists() {
+            return Ok(());
+        }
+        let mut area = self.load(PROPERTIES_SERIAL)?;
+        let serial = area.serial()?.wrapping_add(1);
+        area.put_u32(4, serial);
+        self.store(PROPERTIES_SERIAL, area)
+    }
+
+    /// All properties as (name, value, context), sorted by name.
+    pub fn list(&self) -> Result<Vec<(String, String, String)>> {
+        let mut props = Vec::new();
+        for context in self.contexts()? {
+            for prop in self.load(&context)?.props()? {
+                props.push((prop.name, prop.value, context.clone()));
+            }
+        }
+        props.sort();
+        Ok(props)
+    }
+
+    pub fn get(&self, name: &str) -> Result<Option<String>> {
+        match self.locate(name)? {
+            Some(context) => self.load(&context)?.get(name),
+            None => Ok(None),
+        }
+    }
+
+    pub fn set(&self, name: &str, value: &str) -> Result<()> {
+        let context = match self.locate(name)? {
+            Some(context) => context,
+            None => self
+                .context_of(name)?
+                .with_context(|| format!("No context for {name}, is property_info missing?"))?,
+        };
+        let mut area = if self.dir.join(&context).exists() {
+            self.load(&context)?
+        } else {
+            PropArea::new(PA_SIZE)?
+        };
+        area.set(name, value)?;
+        self.store(&context, area)?;
+        self.bump_serial()
+    }
+
+    pub fn delete(&self, name: &str) -> Result<bool> {
+        let Some(context) = self.locate(name)? else {
+            return Ok(false);
+        };
+        let mut area = self.load(&context)?;
+        if !area.delete(name)? {
+            return Ok(false);
+        }
+        self.store(&context, area)?;
+        self.bump_serial()?;
+        Ok(true)
+    }
+
+    /// Compact one area, or with `None` every area that has wasted space (all of them if
+    /// `force`). Returns false if some area could not be rebuilt.
+    pub fn compact(&self, context: Option<&str>, force: bool) -> Result<bool> {
+        if let Some(context) = context {
+            let area = self.load(context)?.compact()?;
+            self.store(context, area)?;
+            info!("Rebuilt property area {context}");
+            return Ok(true);
+        }
+
+        let mut ok = true;
+        for context in self.contexts()? {
+            let result = self.load(&context).and_then(|area| {
+                let wasted = area.wasted()?;
+                if !force && wasted == 0 {
+                    return Ok(());
+                }
+                self.store(&context, area.compact()?)?;
+                info!("Rebuilt property area {context}, reclaimed {wasted} bytes");
+                Ok(())
+            });
+            if let Err(e) = result {
+                warn!("Failed to rebuild {context}: {e:#}");
+                ok = false;
+            }
+        }
+        Ok(ok)
+    }
+}
+
+/// What resetprop was asked to do, for a [`PropRoot`].
+#[allow(clippy::struct_excessive_bools)]
+pub struct Request<'a> {
+    pub delete: bool,
+    pub rebuild: bool,
+    pub show_context: bool,
+    pub force: bool,
+    pub name: Option<&'a str>,
+    pub value: Option<&'a str>,
+}
+
+/// resetprop on property area files in `dir` instead of the live properties.
+pub fn run(dir: &Path, req: &Request) -> Result<()> {
+    let root = PropRoot::open(dir)?;
+
+    if req.delete {
+        let name = req.name.context("--delete requires a property name")?;
+        if !root.delete(name)? {
+            bail!("{name} not found");
+        }
+        if !req.rebuild {
+            return Ok(());
+        }
+    }
+
+    if req.rebuild {
+        if let Some(name) = req.name {
+            let context = if req.show_context || req.delete {
+                root.context_of(name)?
+                    .with_context(|| format!("No context for {name}"))?
+            } else {
+                name.to_owned()
+            };
+            root.compact(Some(&context), true)?;
+        } else if !root.compact(None, req.force)? {
+            bail!("Failed to rebuild some property areas");
+        }
+        return Ok(());
+    }
+
+    match (req.name, req.value) {
+        (Some(name), Some(value)) => root
+            .set(name, value)
+            .with_context(|| format!("Failed to set {name}"))?,
+        (Some(name), None) => match root.get(name)? {
+            Some(value) => println!("{value}"),
+            None => bail!("{name} not found"),
+        },
+        (None, None) => {
+            for (name, value, context) in root.list()? {
+                if req.show_context {
+                    println!("[{name}]: [{context}]");
+                } else {
+                    println!("[{name}]: [{value}]");
+                }
+            }
+        }
+        (None, Some(_)) => bail!("property name is required"),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn area_with(props: &[(&str, &str)]) -> PropArea {
+        let mut area = PropArea::new(PA_SIZE).unwrap();
+        for (name, value) in props {
+            area.set(name, value).unwrap();
+        }
+        area
+    }
+
+    fn names(area: &PropArea) -> Vec<String> {
+        let mut names: Vec<_> = area.props().unwrap().into_iter().map(|p| p.name).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn set_get_round_trip() {
+        let mut area = area_with(&[
+            ("ro.build.type", "user"),
+            ("ro.debuggable", "0"),
+            ("persist.sys.usb.config", "mtp"),
+        ]);
+        assert_eq!(area.get("ro.build.type").unwrap().as_deref(), Some("user"));
+        assert_eq!(area.get("ro.debuggable").unwrap().as_deref(), Some("0"));
+        assert_eq!(area.get("ro.build").unwrap(), None);
+        assert_eq!(area.get("ro.missing").unwrap(), None);
+
+        area.set("ro.debuggable", "1").unwrap();
+        assert_eq!(area.get("ro.debuggable").unwrap().as_deref(), Some("1"));
+
+        let mut reread = PropArea::from_bytes(area.into_bytes()).unwrap();
+        assert_eq!(reread.get("ro.debuggable").unwrap().as_deref(), Some("1"));
+        assert_eq!(
+            names(&reread),
+            ["persist.sys.usb.config", "ro.build.type", "ro.debuggable"]
+        );
+    }
+
+    #[test]
+    fn update_bumps_serial() {
+        let mut area = area_with(&[("sys.a", "1")]);
+        let serial = |area: &mut PropArea| {
+            let info = area.info_of("sys.a").unwrap().unwrap();
+            area.read_prop(info).unwrap().serial
+        };
+        let before = serial(&mut area);
+        area.set("sys.a", "22").unwrap();
+        let after = serial(&mut area);
+        assert_eq!(after >> 24, 2);
+        assert_ne!(before & 0x00ff_ffff, after & 0x00ff_ffff);
+        assert_eq!(after & 1, 0);
+    }
+
+    #[test]
+    fn delete_and_compact() {
+        let mut area = area_with(&[("a.b.c", "1"), ("a.b.d", "2"), ("a.e", "3")]);
+        assert_eq!(area.wasted().unwrap(), 0);
+
+        assert!(area.delete("a.b.c").unwrap());
+        assert!(!area.delete("a.b.c").unwrap());
+        assert!(!area.delete("a.x").unwrap());
+        assert_eq!(area.get("a.b.c").unwrap(), None);
+        assert_eq!(area.get("a.b.d").unwrap().as_deref(), Some("2"));
+        assert!(area.wasted().unwrap() > 0);
+
+        let mut compacted = area.compact().unwrap();
+        assert_eq!(compacted.wasted().unwrap(), 0);
+        assert!(compacted.bytes_used().unwrap() < area.bytes_used().unwrap());
+        assert_eq!(compacted.serial().unwrap(), area.serial().unwrap());
+        assert_eq!(names(&compacted), ["a.b.d", "a.e"]);
+        assert_eq!(compacted.get("a.e").unwrap().as_deref(), Some("3"));
+    }
+
+    #[test]
+    fn long_values() {
+        let long = "x".repeat(PROP_VALUE_MAX + 10);
+        let mut area = area_with(&[("ro.long", &long), ("ro.short", "s")]);
+        assert_eq!(area.get("ro.long").unwrap(), Some(long.clone()));
+
+        // long values are replaced, not updated in place
+        let longer = "y".repeat(300);
+        area.set("ro.long", &longer).unwrap();
+        assert_eq!(area.get("ro.long").unwrap(), Some(longer.clone()));
+        area.set("ro.long", "short now").unwrap();
+        assert_eq!(area.get("ro.long").unwrap().as_deref(), Some("short now"));
+
+        let mut compacted = area.compact().unwrap();
+        assert_eq!(compacted.get("ro.short").unwrap().as_deref(), Some("s"));
+        assert_eq!(
+            compacted.get("ro.long").unwrap().as_deref(),
+            Some("short now")
+        );
+
+        assert!(compacted.delete("ro.long").unwrap());
+        compacted.set("ro.long", &long).unwrap();
+        let mut reread = PropArea::from_bytes(compacted.into_bytes()).unwrap();
+        assert_eq!(reread.get("ro.long").unwrap(), Some(long));
+    }
+
+    #[test]
+    fn full_area() {
+        let mut area = PropArea::new(HEADER_SIZE + 512).unwrap();
+        let result = (0..100).try_for_each(|i| area.set(&format!("p.n{i}"), "v"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn invalid_names() {
+        let mut area = area_with(&[]);
+        assert!(area.set("a..b", "1").is_err());
+        assert!(area.set(".a", "1").is_err());
+        assert!(area.get("a.").is_err());
+    }
+
+    #[test]
+    fn corrupt_headers() {
+        let bytes = area_with(&[("a.b", "1")]).into_bytes();
+        assert!(PropArea::from_bytes(bytes[..HEADER_SIZE].to_vec()).is_err());
+
+        let mut bad_magic = bytes.clone();
+        bad_magic[8] ^= 0xff;
+        assert!(PropArea::from_bytes(bad_magic).is_err());
+
+        let mut bad_size = bytes;
+        bad_size[..4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(PropArea::from_bytes(bad_size).is_err());
+    }
+
+    #[test]
+    fn corrupt_offsets() {
+        let mut area = area_with(&[("a.b", "1"), ("a.c", "2")]);
+        let node = area.find_node("a.b", false).unwrap().unwrap();
+
+        let mut out_of_bounds = PropArea::from_bytes(area.bytes.clone()).unwrap();
+        out_of_bounds.set_data_u32(node + BT_RIGHT, u32::MAX - 8);
+        assert!(out_of_bounds.props().is_err());
+        assert!(out_of_bounds.get("a.z").is_err());
+
+        let mut bad_info = PropArea::from_bytes(area.bytes.clone()).unwrap();
+        bad_info.set_data_u32(node + BT_PROP, u32::MAX - 8);
+        assert!(bad_info.get("a.b").is_err());
+        assert!(bad_info.props().is_err());
+
+        let mut bad_long = area_with(&[("a.b", &"x".repeat(200))]);
+        let info = bad_long.info_of("a.b").unwrap().unwrap();
+        bad_long.set_data_u32(info + LONG_OFFSET as u32, u32::MAX - 8);
+        assert!(bad_long.get("a.b").is_err());
+        assert!(bad_long.delete("a.b").is_err());
+    }
+
+    #[test]
+    fn cyclic_links() {
+        let area = area_with(&[("a.b", "1"), ("a.c", "2")]);
+        let mut cyclic = PropArea::from_bytes(area.bytes.clone()).unwrap();
+        let a = cyclic.find_node("a", false).unwrap().unwrap();
+        cyclic.set_data_u32(a + BT_LEFT, a);
+        assert!(cyclic.props().is_err());
+        assert!(cyclic.wasted().is_err());
+        assert!(cyclic.compact().is_err());
+    }
+
+    #[test]
+    fn shared_links() {
+        // every node links both sides to the next one: 2^n paths if revisits were allowed
+        let mut area = PropArea::new(PA_SIZE).unwrap();
+        let chain: Vec<String> = (0..64).map(|i| format!("n{i:02}")).collect();
+        for name in &chain {
+            area.set(&format!("a.{name}"), "v").unwrap();
+        }
+        let nodes: Vec<u32> = chain
+            .iter()
+            .map(|name| {
+                area.find_node(&format!("a.{name}"), false)
+                    .unwrap()
+                    .unwrap()
+            })
+            .collect();
+        for pair in nodes.windows(2) {
+            area.set_data_u32(pair[0] + BT_LEFT, pair[1]);
+            area.set_data_u32(pair[0] + BT_RIGHT, pair[1]);
+        }
+        assert!(area.props().is_err());
+        assert!(area.delete("a.n00").is_err());
+    }
+}
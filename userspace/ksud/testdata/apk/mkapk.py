@@ -0,0 +1,104 @@
+"""Generate the signed APK fixtures used by the apk_sign tests.
+
+Needs the `cryptography` package, run from this directory: python3 mkapk.py
+"""
+import struct, hashlib, zipfile, io
+from cryptography.hazmat.primitives.asymmetric import rsa, ec, padding
+from cryptography.hazmat.primitives import hashes, serialization
+from cryptography import x509
+from cryptography.x509.oid import NameOID
+import datetime
+
+def lp(b): return struct.pack('<I', len(b)) + b
+def seq(items): return lp(b''.join(lp(i) for i in items))
+
+def mkcert(key, name):
+    subj = x509.Name([x509.NameAttribute(NameOID.COMMON_NAME, name)])
+    now = datetime.datetime(2020,1,1)
+    c = x509.CertificateBuilder().subject_name(subj).issuer_name(subj).public_key(key.public_key()).serial_number(1).not_valid_before(now).not_valid_after(now+datetime.timedelta(days=9000)).sign(key, hashes.SHA256())
+    return c.public_bytes(serialization.Encoding.DER)
+
+def spki(key):
+    return key.public_key().public_bytes(serialization.Encoding.DER, serialization.PublicFormat.SubjectPublicKeyInfo)
+
+def sign(key, alg, data):
+    if alg in (0x0103,0x0421): return key.sign(data, padding.PKCS1v15(), hashes.SHA256())
+    if alg == 0x0104: return key.sign(data, padding.PKCS1v15(), hashes.SHA512())
+    if alg == 0x0101: return key.sign(data, padding.PSS(padding.MGF1(hashes.SHA256()), 32), hashes.SHA256())
+    if alg == 0x0201: return key.sign(data, ec.ECDSA(hashes.SHA256()))
+    if alg == 0x0202: return key.sign(data, ec.ECDSA(hashes.SHA512()))
+
+def chunked(h, sections):
+    chunks=[]
+    for s in sections:
+        for i in range(0, len(s), 1<<20): chunks.append(s[i:i+(1<<20)])
+        if len(s)==0: pass
+    top = h(); top.update(b'\x5a'+struct.pack('<I', len(chunks)))
+    for c in chunks:
+        x=h(); x.update(b'\xa5'+struct.pack('<I',len(c))+c); top.update(x.digest())
+    return top.digest()
+
+def build(out, signers_spec, tamper=False, lineage=None):
+    buf = io.BytesIO()
+    with zipfile.ZipFile(buf,'w') as z:
+        z.writestr('AndroidManifest.xml', b'x'*500)
+        z.writestr('classes.dex', bytes(range(256))*4)
+    data = buf.getvalue()
+    eocd_off = data.rfind(b'PK\x05\x06')
+    cd_off = struct.unpack('<I', data[eocd_off+16:eocd_off+20])[0]
+    entries, cd, eocd = data[:cd_off], data[cd_off:eocd_off], data[eocd_off:]
+    def digest(alg, sig_block_off):
+        e = bytearray(eocd); e[16:20]=struct.pack('<I', sig_block_off)
+        h = hashlib.sha512 if alg in (0x0102,0x0104,0x0202) else hashlib.sha256
+        return chunked(h, [entries, cd, bytes(e)])
+    # the digests depend on sig block offset == len(entries) (fixed)
+    blocks = {}
+    for scheme, specs in signers_spec.items():
+        signers=[]
+        for key, cert, algs, sdk, lin in specs:
+            digests = b''.join(lp(struct.pack('<I',a)+lp(digest(a, len(entries)))) for a in algs)
+            attrs = b''
+            if lin: attrs = lp(struct.pack('<I',0x3ba06f8c)+lin)
+            if scheme == 'v2':
+                sd = lp(digests) + lp(lp(cert)) + lp(attrs)
+            else:
+                sd = lp(digests) + lp(lp(cert)) + struct.pack('<II',*sdk) + lp(attrs)
+            sigs = b''.join(lp(struct.pack('<I',a)+lp(sign(key,a,sd))) for a in algs)
+            if scheme == 'v2':
+                signer = lp(sd) + lp(sigs) + lp(spki(key))
+            else:
+                signer = lp(sd) + struct.pack('<II',*sdk) + lp(sigs) + lp(spki(key))
+            signers.append(signer)
+        blocks[{'v2':0x7109871a,'v3':0xf05368c0,'v31':0x1b93ad61}[scheme]] = lp(b''.join(lp(s) for s in signers))
+    pairs = b''.join(struct.pack('<Q', 4+len(v)) + struct.pack('<I',k) + v for k,v in blocks.items())
+    size = len(pairs) + 8 + 16
+    block = struct.pack('<Q', size) + pairs + struct.pack('<Q', size) + b'APK Sig Block 42'
+    new_cd_off = len(entries) + len(block)
+    e = bytearray(eocd); e[16:20] = struct.pack('<I', new_cd_off)
+    ent = bytearray(entries)
+    if tamper: ent[100] ^= 1
+    open(out,'wb').write(bytes(ent)+block+cd+bytes(e))
+
+def lineage(nodes):
+    # nodes: list of (key, cert, sig_alg_of_this_cert_for_next)
+    out = struct.pack('<I', 1)
+    prev=None
+    for key, cert, alg in nodes:
+        sd = lp(cert) + struct.pack('<I', alg)
+        if prev is None: node = lp(sd) + struct.pack('<I', 3) + struct.pack('<I', 0) + lp(b'')
+        else:
+            pk, palg = prev
+            node = lp(sd) + struct.pack('<I', 3) + struct.pack('<I', palg) + lp(sign(pk, palg, sd))
+        out += lp(node); prev=(key, alg)
+    return out
+
+rk = rsa.generate_private_key(65537, 2048); rc = mkcert(rk, 'old')
+ek = ec.generate_private_key(ec.SECP256R1()); ecert = mkcert(ek, 'new')
+build('v2_rsa.apk', {'v2':[(rk, rc, [0x0103, 0x0104], None, None)]})
+build('v2_ec.apk', {'v2':[(ek, ecert, [0x0201, 0x0202], None, None)]})
+build('v2_tampered.apk', {'v2':[(rk, rc, [0x0103], None, None)]}, tamper=True)
+lin = lineage([(rk, rc, 0x0103), (ek, ecert, 0x0201)])
+build('v3.apk', {'v2':[(rk, rc, [0x0103], None, None)], 'v3':[(rk, rc, [0x0103], (24, 32), None)], 'v31':[(ek, ecert, [0x0201], (33, 0x7fffffff), lin)]})
+# the second lineage node is signed by a key other than the first certificate's
+ek2 = ec.generate_private_key(ec.SECP256R1()); ecert2 = mkcert(ek2, 'other')
+build('v3_bad_lineage.apk', {'v31':[(ek, ecert, [0x0201], (33, 0x7fffffff), lineage([(rk, ecert2, 0x0103), (ek, ecert, 0x0201)]))]})
@@ -1,4 +1,4 @@
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::io::{ErrorKind, Write};
 
 use anyhow::{Context, Result};
@@ -12,6 +12,10 @@ use rustix::{
     },
 };
 
+// the module installed before the last boot image patch, kept in case the new one doesn't
+// load on this kernel
+const FALLBACK_MODULE: &str = "/kernelsu_fallback.ko";
+
 struct AutoUmount {
     mountpoints: Vec<String>,
 }
@@ -111,11 +115,10 @@ pub fn init() -> Result<()> {
 
     if ksuinit::has_kernelsu() {
         log::info!("KernelSU may be already loaded in kernel, skip!");
+        report_lkm_status("skipped, already present");
     } else {
         log::info!("Loading kernelsu.ko..");
-        if let Err(e) = load_module_from_path("/kernelsu.ko") {
-            log::error!("Cannot load kernelsu.ko: {:?}", e);
-        }
+        load_kernelsu();
     }
 
     // And now we should prepare the real init to transfer control to it
@@ -132,11 +135,88 @@ pub fn init() -> Result<()> {
     Ok(())
 }
 
-fn load_module_from_path(path: &str) -> Result<()> {
-    anyhow::ensure!(rustix::process::getpid().is_init(), "Invalid process");
-    let buffer = std::fs::read(path).with_context(|| format!("Cannot read file {}", path))?;
+fn load_kernelsu() {
     let params = std::fs::read("/ksu_config").unwrap_or_default();
     let params = unsafe { CString::from_vec_unchecked(params) };
-    log::info!("load kernelsu with params {params:?}");
-    ksuinit::load_module(&buffer, &params)
+    let no_params = CString::default();
+
+    // bad parameters shouldn't cost root, nor a module the kernel rejects if an older copy
+    // is around
+    let mut attempts = vec![("/kernelsu.ko", &params)];
+    if !params.is_empty() {
+        attempts.push(("/kernelsu.ko", &no_params));
+    }
+    if access(FALLBACK_MODULE, Access::EXISTS).is_ok() {
+        attempts.push((FALLBACK_MODULE, &params));
+    }
+
+    let mut first_error = None;
+    for (path, params) in attempts {
+        match load_module_from_path(path, params) {
+            Ok(()) => {
+                match &first_error {
+                    None => report_lkm_status("loaded"),
+                    Some(e) => report_lkm_status(&format!(
+                        "loaded {path} with params {params:?} after failure: {e}"
+                    )),
+                }
+                return;
+            }
+            Err(e) => {
+                log::error!("Cannot load {path} with params {params:?}: {e:?}");
+                first_error.get_or_insert_with(|| format!("{e:#}"));
+            }
+        }
+    }
+
+    let reason = first_error.unwrap_or_default();
+    report_lkm_status(&format!("failed: {reason}"));
+}
+
+// Nothing is mounted yet. ksud saves the kmsg line during this boot, but only runs if the
+// LKM is loaded, so the status also goes to pstore, which keeps it until the next boot.
+fn report_lkm_status(status: &str) {
+    let line = format!("{} {status}", ksuinit::LKM_STATUS_MARKER);
+    log::warn!("{line}");
+    if let Err(e) = write_pmsg(&line) {
+        log::warn!("Cannot write lkm status to pmsg: {e:?}");
+    }
+}
+
+// major number of a character device in /proc/devices
+fn char_device_major(devices: &str, name: &str) -> Option<u32> {
+    devices
+        .lines()
+        .skip(1)
+        .take_while(|line| !line.is_empty())
+        .find_map(|line| {
+            let (major, device) = line.trim().split_once(' ')?;
+            (device == name).then(|| major.parse().ok())?
+        })
+}
+
+fn write_pmsg(line: &str) -> Result<()> {
+    const PMSG: &str = "/pmsg0";
+    let devices = std::fs::read_to_string("/proc/devices")?;
+    let major = char_device_major(&devices, "pmsg").context("No pmsg device")?;
+    mknodat(
+        CWD,
+        PMSG,
+        FileType::CharacterDevice,
+        0o200.into(),
+        makedev(major, 0),
+    )?;
+    let result = std::fs::File::options()
+        .write(true)
+        .open(PMSG)
+        .and_then(|mut pmsg| writeln!(pmsg, "{line}"));
+    unlink(PMSG).ok();
+    Ok(result?)
+}
+
+fn load_module_from_path(path: &str, params: &CStr) -> Result<()> {
+    anyhow::ensure!(rustix::process::getpid().is_init(), "Invalid process");
+    let buffer = std::fs::read(path).with_context(|| format!("Cannot read file {}", path))?;
+    log::info!("load {path} with params {params:?}");
+    ksuinit::load_module(&buffer, params)
 }
@@ -82,14 +82,10 @@ pub fn for_each_kernel_symbols<F: FnMut(&(String, u64)) -> Result<bool>>(mut f:
     Ok(())
 }
 
-/// Relocate undefined symbols in an ELF kernel module buffer using /proc/kallsyms,
-/// then load it via init_module syscall.
-pub fn load_module(data: &[u8], params: &CStr) -> Result<()> {
-    let mut buffer = data.to_vec();
-    let elf = Elf::parse(&buffer)?;
-    let ctx = *elf.syms.ctx();
+type UndefinedSymbols = HashMap<String, (Sym, usize)>;
 
-    let mut unresolved_symbols: HashMap<String, (Sym, usize)> = HashMap::new();
+fn undefined_symbols(elf: &Elf) -> UndefinedSymbols {
+    let mut symbols = HashMap::new();
     for (index, sym) in elf.syms.iter().enumerate() {
         if index == 0 {
             continue;
@@ -104,8 +100,19 @@ pub fn load_module(data: &[u8], params: &CStr) -> Result<()> {
         };
 
         let offset = elf.syms.offset() + index * Sym::size_with(elf.syms.ctx());
-        unresolved_symbols.insert(name.to_owned(), (sym, offset));
+        symbols.insert(name.to_owned(), (sym, offset));
     }
+    symbols
+}
+
+/// Relocate undefined symbols in an ELF kernel module buffer using /proc/kallsyms,
+/// then load it via init_module syscall.
+pub fn load_module(data: &[u8], params: &CStr) -> Result<()> {
+    let mut buffer = data.to_vec();
+    let elf = Elf::parse(&buffer)?;
+    let ctx = *elf.syms.ctx();
+
+    let mut unresolved_symbols = undefined_symbols(&elf);
 
     if !unresolved_symbols.is_empty() {
         for_each_kernel_symbols(|(symbol, addr)| {
@@ -128,6 +135,140 @@ pub fn load_module(data: &[u8], params: &CStr) -> Result<()> {
     Ok(())
 }
 
+/// What [`load_module`] would do with a module, see [`check_module`].
+#[derive(Debug)]
+pub struct ModuleCheck {
+    /// Every undefined symbol with the kernel address it resolves to, sorted by name.
+    pub symbols: Vec<(String, Option<u64>)>,
+    pub vermagic: Option<String>,
+    pub kernel_release: String,
+    /// Symbol versions in the module's `__versions`. They are never compared: a `__crc_`
+    /// symbol address is only the CRC on kernels with absolute CRCs before 5.19, and
+    /// [`load_module`] resolves every symbol to an absolute address anyway.
+    pub crc_unchecked: usize,
+}
+
+// `6.1` and `android14` of `6.1.75-android14-11-g1234`, what a GKI module is bound to
+fn kernel_branch(release: &str) -> (Option<&str>, Option<&str>) {
+    let end = release
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(release.len());
+    let version = &release[..end];
+    let branch = version
+        .match_indices('.')
+        .nth(1)
+        .map_or(version, |(i, _)| &version[..i]);
+    let android = release.match_indices("android").find_map(|(i, word)| {
+        let rest = &release[i + word.len()..];
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        (digits > 0).then(|| &release[i..i + word.len() + digits])
+    });
+    (Some(branch).filter(|b| b.contains('.')), android)
+}
+
+impl ModuleCheck {
+    pub fn missing(&self) -> impl Iterator<Item = &str> {
+        self.symbols
+            .iter()
+            .filter(|(_, addr)| addr.is_none())
+            .map(|(name, _)| name.as_str())
+    }
+
+    fn module_release(&self) -> Option<&str> {
+        self.vermagic.as_deref()?.split_whitespace().next()
+    }
+
+    /// Whether the module's vermagic names the running kernel release.
+    pub fn vermagic_matches(&self) -> bool {
+        self.module_release() == Some(self.kernel_release.as_str())
+    }
+
+    /// Whether the module's vermagic is of the running kernel branch and android release,
+    /// the rest of the release string differs between builds of the same GKI.
+    pub fn vermagic_compatible(&self) -> bool {
+        let Some(release) = self.module_release() else {
+            return false;
+        };
+        let (branch, android) = kernel_branch(release);
+        let (running_branch, running_android) = kernel_branch(&self.kernel_release);
+        branch.is_some()
+            && branch == running_branch
+            && (running_android.is_none() || android == running_android)
+    }
+
+    pub fn is_loadable(&self) -> bool {
+        self.missing().next().is_none() && self.vermagic_compatible()
+    }
+}
+
+fn section_data<'a>(elf: &Elf, data: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    let sh = elf
+        .section_headers
+        .iter()
+        .find(|sh| elf.shdr_strtab.get_at(sh.sh_name) == Some(name))?;
+    let start = usize::try_from(sh.sh_offset).ok()?;
+    let end = start.checked_add(usize::try_from(sh.sh_size).ok()?)?;
+    data.get(start..end)
+}
+
+fn module_vermagic(elf: &Elf, data: &[u8]) -> Option<String> {
+    section_data(elf, data, ".modinfo")?
+        .split(|b| *b == 0)
+        .find_map(|entry| entry.strip_prefix(b"vermagic="))
+        .map(|v| String::from_utf8_lossy(v).into_owned())
+}
+
+fn module_versions(elf: &Elf, data: &[u8]) -> usize {
+    // struct modversion_info { unsigned long crc; char name[64 - sizeof(unsigned long)]; }
+    const MODVERSION_INFO_SIZE: usize = 64;
+    section_data(elf, data, "__versions")
+        .map_or(0, |versions| versions.len() / MODVERSION_INFO_SIZE)
+}
+
+/// Resolve a module's undefined symbols against /proc/kallsyms and compare its vermagic with
+/// the running kernel, without loading anything.
+pub fn check_module(data: &[u8]) -> Result<ModuleCheck> {
+    let elf = Elf::parse(data)?;
+
+    let mut resolved: HashMap<String, Option<u64>> = undefined_symbols(&elf)
+        .into_keys()
+        .map(|name| (name, None))
+        .collect();
+    for_each_kernel_symbols(|(symbol, addr)| {
+        if let Some(slot) = resolved.get_mut(symbol) {
+            slot.get_or_insert(*addr);
+        }
+        Ok(true)
+    })
+    .context("Cannot parse kallsyms")?;
+
+    let mut symbols: Vec<_> = resolved.into_iter().collect();
+    symbols.sort();
+    Ok(ModuleCheck {
+        symbols,
+        vermagic: module_vermagic(&elf, data),
+        kernel_release: rustix::system::uname()
+            .release()
+            .to_string_lossy()
+            .into_owned(),
+        crc_unchecked: module_versions(&elf, data),
+    })
+}
+
+/// Prefix of the kernel log line in which ksuinit reports how loading kernelsu.ko went,
+/// so ksud can tell after boot why the LKM is missing.
+pub const LKM_STATUS_MARKER: &str = "ksuinit lkm status:";
+
+/// Find the last LKM status reported by ksuinit in a kernel log dump.
+pub fn find_lkm_status(kmsg: &str) -> Option<&str> {
+    kmsg.lines()
+        .rev()
+        .find_map(|line| line.split_once(LKM_STATUS_MARKER))
+        .map(|(_, status)| status.trim())
+}
+
 fn has_kernelsu_legacy() -> bool {
     use syscalls::{Sysno, syscall};
     let mut version = 0;